@@ -0,0 +1,128 @@
+// Post-query pipeline (union, filters, rendering, output shaping) over synthetic tenants.
+// Run with `cargo bench`; no Keyspaces connection needed.
+
+use criterion::{ BenchmarkId, Criterion, Throughput, criterion_group, criterion_main };
+use std::collections::HashMap;
+use std::hint::black_box;
+
+#[allow(dead_code)]
+#[path = "../src/number.rs"]
+mod number;
+#[allow(dead_code)]
+#[path = "../src/pipeline.rs"]
+mod pipeline;
+
+use pipeline::{
+    MergeStrategy,
+    OutputShape,
+    RenderSettings,
+    RequestOptions,
+    TenantRow,
+    render_tenants,
+    shape_output,
+    union_tables,
+};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+fn tenant(i: usize, source: &str) -> TenantRow {
+    let labels: HashMap<String, String> = [
+        ("region", format!("region-{}", i % 4)),
+        ("tier", format!("tier-{}", i % 3)),
+        ("team", format!("team-{}", i % 50)),
+        ("env", (if i.is_multiple_of(2) { "prod" } else { "staging" }).to_string()),
+    ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+    let params: HashMap<String, String> = (0..5)
+        .map(|p| (format!("param{p}"), format!("value-{i}-{p}")))
+        .collect();
+    TenantRow {
+        tenant_id: format!("tenant-{i}"),
+        namespace: (!i.is_multiple_of(10)).then(|| format!("ns-{i}")),
+        target_cluster: "https://kubernetes.default.svc".to_string(),
+        repo_url: "https://github.com/example/tenants.git".to_string(),
+        repo_path: format!("tenants/{i}"),
+        labels: Some(labels),
+        params: Some(params),
+        dest_namespace: None,
+        sync_wave: None,
+        annotations: None,
+        project: None,
+        promoted: Vec::new(),
+        extra: Vec::new(),
+        write_time: None,
+        source: source.to_string(),
+    }
+}
+
+// Two tables where the second repeats the upper half of the first, so the union has real
+// shadowing to do
+fn tables(n: usize) -> Vec<Vec<TenantRow>> {
+    vec![
+        (0..n).map(|i| tenant(i, "tenant_configs")).collect(),
+        (n / 2..n + n / 2).map(|i| tenant(i, "tenant_configs_legacy")).collect()
+    ]
+}
+
+fn cases(n: usize) -> Vec<(&'static str, RequestOptions)> {
+    vec![
+        ("none", RequestOptions::default()),
+        ("label", RequestOptions {
+            filter_label: Some(("region".to_string(), "region-1".to_string())),
+            ..Default::default()
+        }),
+        ("include", RequestOptions {
+            include_tenant_ids: (0..n)
+                .step_by(10)
+                .map(|i| format!("tenant-{i}"))
+                .collect(),
+            ..Default::default()
+        }),
+        ("exclude+label", RequestOptions {
+            filter_label: Some(("env".to_string(), "prod".to_string())),
+            exclude_tenant_ids: (0..n)
+                .step_by(3)
+                .map(|i| format!("tenant-{i}"))
+                .collect(),
+            ..Default::default()
+        }),
+        ("nested", RequestOptions {
+            output_shape: OutputShape::NestedByTenant,
+            ..Default::default()
+        })
+    ]
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let settings = RenderSettings {
+        multi_table: true,
+        namespace_template: Some("tenant-{tenantId}".to_string()),
+        ..Default::default()
+    };
+    let mut group = c.benchmark_group("pipeline");
+    group.sample_size(10);
+    for n in SIZES {
+        let input = tables(n);
+        group.throughput(Throughput::Elements(n as u64));
+        for (name, opts) in cases(n) {
+            group.bench_with_input(BenchmarkId::new(name, n), &input, |b, input| {
+                b.iter_batched(
+                    || input.clone(),
+                    |scans| {
+                        let rows = union_tables(scans, MergeStrategy::Shadow).unwrap();
+                        black_box(
+                            shape_output(render_tenants(rows, &opts, &settings), opts.output_shape)
+                        )
+                    },
+                    criterion::BatchSize::LargeInput
+                );
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_pipeline);
+criterion_main!(benches);
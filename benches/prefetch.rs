@@ -0,0 +1,50 @@
+// Multi-page scan with simulated Keyspaces latency and decode cost, by prefetch depth. With a
+// depth, fetching the next page overlaps decoding the current one, so the total approaches
+// pages * max(fetch, decode) instead of pages * (fetch + decode).
+
+use criterion::{ BenchmarkId, Criterion, criterion_group, criterion_main };
+use std::time::Duration;
+
+#[path = "../src/prefetch.rs"]
+mod prefetch;
+
+use prefetch::Pages;
+
+const PAGES: usize = 20;
+const FETCH_LATENCY: Duration = Duration::from_millis(4);
+const DECODE_TIME: Duration = Duration::from_millis(4);
+
+async fn scan(depth: usize) -> usize {
+    let mut pages = Pages::new(depth, 0usize, |page| async move {
+        tokio::time::sleep(FETCH_LATENCY).await;
+        Ok::<_, ()>((page, (page + 1 < PAGES).then_some(page + 1)))
+    });
+    let mut decoded = 0;
+    while let Some(page) = pages.next().await {
+        page.expect("simulated fetch cannot fail");
+        // Stands in for row decoding, which runs on the scanning task
+        std::thread::sleep(DECODE_TIME);
+        decoded += 1;
+    }
+    decoded
+}
+
+fn bench_prefetch(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder
+        ::new_multi_thread()
+        .worker_threads(2)
+        .enable_time()
+        .build()
+        .expect("tokio runtime");
+    let mut group = c.benchmark_group("prefetch");
+    group.sample_size(10);
+    for depth in [0, 1, 2] {
+        group.bench_with_input(BenchmarkId::new("depth", depth), &depth, |b, &depth| {
+            b.iter(|| assert_eq!(rt.block_on(scan(depth)), PAGES));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_prefetch);
+criterion_main!(benches);
@@ -0,0 +1,92 @@
+// Rolling error ratio of getparams requests, for tuning the ApplicationSet poll interval;
+// the caller records only outcomes the plugin is responsible for, not client errors.
+// Outcomes land in fixed slots of the window, so recording is a counter bump and a slot
+// that ages out drops whole; the window is therefore accurate to one slot.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+
+const SLOTS: u64 = 12;
+
+pub(crate) struct ErrorBudget {
+    window: Duration,
+    // Error ratio above which a report warns, in (0, 1]
+    threshold: f64,
+    started: Instant,
+    // (slot index since `started`, succeeded, failed), oldest first
+    slots: Mutex<VecDeque<(u64, u64, u64)>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Outcomes {
+    pub(crate) succeeded: u64,
+    pub(crate) failed: u64,
+}
+
+impl Outcomes {
+    pub(crate) fn total(self) -> u64 {
+        self.succeeded + self.failed
+    }
+
+    // None without any requests in the window
+    pub(crate) fn error_ratio(self) -> Option<f64> {
+        (self.total() > 0).then(|| (self.failed as f64) / (self.total() as f64))
+    }
+}
+
+impl ErrorBudget {
+    pub(crate) fn new(window: Duration, threshold: f64) -> Self {
+        ErrorBudget {
+            window,
+            threshold,
+            started: Instant::now(),
+            slots: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub(crate) fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    pub(crate) fn record(&self, ok: bool) {
+        let slot = self.slot();
+        let mut slots = self.slots.lock().unwrap();
+        expire(&mut slots, slot);
+        match slots.back_mut() {
+            Some((index, succeeded, failed)) if *index == slot => {
+                if ok {
+                    *succeeded += 1;
+                } else {
+                    *failed += 1;
+                }
+            }
+            _ => slots.push_back((slot, ok as u64, !ok as u64)),
+        }
+    }
+
+    pub(crate) fn outcomes(&self) -> Outcomes {
+        let slot = self.slot();
+        let mut slots = self.slots.lock().unwrap();
+        expire(&mut slots, slot);
+        slots.iter().fold(Outcomes::default(), |acc, (_, succeeded, failed)| Outcomes {
+            succeeded: acc.succeeded + succeeded,
+            failed: acc.failed + failed,
+        })
+    }
+
+    fn slot(&self) -> u64 {
+        let width = (self.window.as_millis() as u64 / SLOTS).max(1);
+        self.started.elapsed().as_millis() as u64 / width
+    }
+}
+
+fn expire(slots: &mut VecDeque<(u64, u64, u64)>, current: u64) {
+    while slots.front().is_some_and(|(index, _, _)| index + SLOTS <= current) {
+        slots.pop_front();
+    }
+}
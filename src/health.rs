@@ -0,0 +1,162 @@
+// Keyspaces health as seen from the queries the plugin actually runs. One `HealthState` feeds
+// both `/readyz` and the generator, so Kubernetes and ArgoCD back off for the same reason at
+// the same time instead of the probe passing while every request times out.
+
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+use tracing::{ info, warn };
+
+pub(crate) struct HealthState {
+    // Consecutive failed queries that open the breaker; 0 disables it
+    failure_threshold: u32,
+    // How long an open breaker rejects requests before letting one through again
+    open_for: Duration,
+    // Age of the last successful query after which the plugin counts as unhealthy
+    max_staleness: Option<Duration>,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    // Startup until the first success, so a degraded start goes stale as well
+    last_success: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Health {
+    Healthy,
+    BreakerOpen,
+    Stale,
+}
+
+impl Health {
+    // 503 reason for the probe and the generator
+    pub(crate) fn reason(self) -> Option<&'static str> {
+        match self {
+            Health::Healthy => None,
+            Health::BreakerOpen => Some("circuit breaker open after repeated Keyspaces failures"),
+            Health::Stale => Some("no successful Keyspaces query within HEALTH_MAX_STALENESS_SECS"),
+        }
+    }
+}
+
+impl HealthState {
+    pub(crate) fn new(failure_threshold: u32, open_for: Duration, max_staleness: Option<Duration>) -> Self {
+        HealthState {
+            failure_threshold,
+            open_for,
+            max_staleness,
+            inner: Mutex::new(Inner {
+                consecutive_failures: 0,
+                open_until: None,
+                last_success: Instant::now(),
+            }),
+        }
+    }
+
+    // Once `open_for` has passed the breaker lets requests through again (half-open); the
+    // next failure reopens it and the next success closes it
+    pub(crate) fn status(&self) -> Health {
+        let inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        if inner.open_until.is_some_and(|until| now < until) {
+            return Health::BreakerOpen;
+        }
+        if self.max_staleness.is_some_and(|max| now.duration_since(inner.last_success) > max) {
+            return Health::Stale;
+        }
+        Health::Healthy
+    }
+
+    pub(crate) fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.open_until.take().is_some() {
+            info!("circuit breaker closed: Keyspaces query succeeded");
+        }
+        inner.consecutive_failures = 0;
+        inner.last_success = Instant::now();
+    }
+
+    pub(crate) fn record_failure(&self) {
+        if self.failure_threshold == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        inner.consecutive_failures = inner.consecutive_failures.saturating_add(1);
+        if inner.consecutive_failures >= self.failure_threshold {
+            if inner.open_until.is_none_or(|until| now >= until) {
+                warn!(
+                    "circuit breaker open after {} consecutive Keyspaces failures; rejecting requests for {:?}",
+                    inner.consecutive_failures,
+                    self.open_for
+                );
+            }
+            inner.open_until = Some(now + self.open_for);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPEN_FOR: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn breaker_opens_at_the_threshold() {
+        let health = HealthState::new(3, OPEN_FOR, None);
+        health.record_failure();
+        health.record_failure();
+        assert_eq!(health.status(), Health::Healthy);
+        // A success in between starts the count over
+        health.record_success();
+        health.record_failure();
+        health.record_failure();
+        assert_eq!(health.status(), Health::Healthy);
+        health.record_failure();
+        assert_eq!(health.status(), Health::BreakerOpen);
+        assert!(health.status().reason().is_some());
+    }
+
+    // After `open_for` one request gets through; its failure reopens, its success closes
+    #[test]
+    fn half_open_breaker_reopens_or_closes() {
+        let health = HealthState::new(2, OPEN_FOR, None);
+        health.record_failure();
+        health.record_failure();
+        assert_eq!(health.status(), Health::BreakerOpen);
+        std::thread::sleep(OPEN_FOR + Duration::from_millis(10));
+        assert_eq!(health.status(), Health::Healthy);
+
+        health.record_failure();
+        assert_eq!(health.status(), Health::BreakerOpen);
+        std::thread::sleep(OPEN_FOR + Duration::from_millis(10));
+        health.record_success();
+        assert_eq!(health.status(), Health::Healthy);
+        // Closed again: a single failure no longer opens it
+        health.record_failure();
+        assert_eq!(health.status(), Health::Healthy);
+    }
+
+    #[test]
+    fn zero_threshold_never_opens() {
+        let health = HealthState::new(0, OPEN_FOR, None);
+        for _ in 0..100 {
+            health.record_failure();
+        }
+        assert_eq!(health.status(), Health::Healthy);
+        assert_eq!(Health::Healthy.reason(), None);
+    }
+
+    #[test]
+    fn stale_until_the_next_success() {
+        let health = HealthState::new(3, OPEN_FOR, Some(Duration::from_millis(30)));
+        assert_eq!(health.status(), Health::Healthy);
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(health.status(), Health::Stale);
+        health.record_success();
+        assert_eq!(health.status(), Health::Healthy);
+    }
+}
@@ -1,35 +1,831 @@
+// The `json!` body of `effective_config` outgrew the default macro recursion limit
+#![recursion_limit = "256"]
+
 use axum::{
     extract::State,
     http::{ HeaderMap, StatusCode },
-    response::IntoResponse,
-    routing::post,
+    response::{ IntoResponse, Response },
+    routing::{ get, post },
+    Form,
     Json,
     Router,
 };
-use once_cell::sync::OnceCell;
-use rustls::{ ClientConfig, RootCertStore };
-use rustls::pki_types::CertificateDer;
+use once_cell::sync::{ Lazy, OnceCell };
+use rustls::{ ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme };
+use rustls::client::danger::{ HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier };
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{ CertificateDer, ServerName, UnixTime };
+use scylla::client::Compression;
+use scylla::client::execution_profile::ExecutionProfile;
 use scylla::client::session::Session;
 use scylla::client::session_builder::SessionBuilder;
+use scylla::errors::{ DbError, ExecutionError, PrepareError, RequestAttemptError };
+use scylla::policies::load_balancing::DefaultPolicy;
+use scylla::policies::retry::{
+    DefaultRetryPolicy,
+    RequestInfo,
+    RetryDecision,
+    RetryPolicy,
+    RetrySession,
+};
+use scylla::response::PagingState;
+use scylla::statement::Consistency;
+use scylla::statement::prepared::PreparedStatement;
+use scylla::value::{ CqlValue, Row };
 use serde::{ Deserialize, Serialize };
 use std::{
-    collections::HashMap,
+    collections::{ BTreeMap, BTreeSet, HashMap, HashSet },
     env,
     fs::{ self, File },
     io::BufReader,
     net::SocketAddr,
-    sync::Arc,
+    ops::ControlFlow,
+    sync::{ Arc, Mutex, atomic::{ AtomicBool, AtomicU32, Ordering } },
+    time::{ Duration, Instant },
 };
 use thiserror::Error;
-use tracing::{ error, info };
+use tracing::{ debug, error, info, warn };
+
+mod budget;
+mod health;
+mod number;
+mod pipeline;
+mod prefetch;
+mod reload;
+mod responses;
+mod saved;
+mod selfcheck;
+mod source;
+mod tls;
+
+use budget::ErrorBudget;
+use health::{ Health, HealthState };
+use prefetch::Pages;
+use reload::{ FileChanges, ReloadStrategy };
+use responses::ResponseCache;
+use saved::{ SavedResponses, Snapshot };
+use source::{ FileSource, KeyspacesSource, TenantSource };
+use tls::{ ClientAuthMode, Peer, ServerTls, TlsListener };
+use pipeline::{
+    AppNameCollisionMode,
+    DEFAULT_PARAM_PRECEDENCE,
+    KeyCase,
+    MergeStrategy,
+    MissingFieldMode,
+    OutputShape,
+    OversizeMode,
+    ParamSource,
+    PartialPageMode,
+    REQUIRED_FIELD_KEYS,
+    RenderSettings,
+    RequestOptions,
+    TemplateCharsMode,
+    TenantErrorMode,
+    TenantRow,
+    TimestampFormat,
+    Transform,
+    app_name_collisions,
+    entry_value,
+    keeps_enabled_state,
+    missing_fields,
+    render_errors,
+    render_tenants,
+    render_tenants_counted,
+    shape_output,
+    union_tables,
+};
+
+static SESSION: SessionSlot = SessionSlot::new();
+static READ_SESSION: SessionSlot = SessionSlot::new();
+// Every accepted token, one per PLUGIN_TOKEN_FILE line. Empty until loaded; replaced when
+// the file changes.
+static TOKENS: std::sync::RwLock<Vec<String>> = std::sync::RwLock::new(Vec::new());
+static CONFIG: OnceCell<Config> = OnceCell::new();
+static PREPARED: Lazy<Mutex<StatementCache>> = Lazy::new(Default::default);
+static PATTERNS: Lazy<Mutex<PatternCache>> = Lazy::new(Default::default);
+static METRICS: Lazy<Metrics> = Lazy::new(Default::default);
+static SNAPSHOTS: Lazy<Mutex<SnapshotStore>> = Lazy::new(Default::default);
+// MAX_DB_CONCURRENCY; unset means unbounded
+static DB_PERMITS: OnceCell<tokio::sync::Semaphore> = OnceCell::new();
+// Datacenter the primary session's load balancing ended up preferring
+static LOCAL_DC: OnceCell<String> = OnceCell::new();
+// Keyspaces source only; unset means always healthy
+static HEALTH: OnceCell<HealthState> = OnceCell::new();
+// Set when ERROR_BUDGET_WINDOW_SECS is
+static BUDGET: OnceCell<ErrorBudget> = OnceCell::new();
+// Keyspaces unless TENANT_SOURCE_FILE is set; fixed before the router starts
+static SOURCE: OnceCell<Box<dyn TenantSource>> = OnceCell::new();
+// Last complete responses for maintenance mode, loaded from SNAPSHOT_FILE before the
+// session is built
+static SAVED: OnceCell<SavedResponses> = OnceCell::new();
+// Set when RESPONSE_CACHE_TTL_SECS is
+static RESPONSES: OnceCell<ResponseCache> = OnceCell::new();
+// MAINTENANCE_MODE, toggled at runtime via `/api/v1/maintenance`
+static MAINTENANCE: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    // CLIENT_AUTH_MODE either/cert: the request came over a connection with a verified
+    // client certificate, so `authorize` skips the token
+    static CLIENT_CERT: bool;
+}
+
+const DEFAULT_KEYSPACES_PORT: u16 = 9142;
+const DEFAULT_PAGE_SIZE: i32 = 5000;
+const DEFAULT_MAX_PAGE_SIZE: i32 = 10000;
+// ArgoCD sends a handful of short headers; these leave room for proxies and tracing
+const DEFAULT_MAX_REQUEST_HEADERS: usize = 64;
+const DEFAULT_MAX_REQUEST_HEADER_BYTES: usize = 16 * 1024;
+const DEFAULT_SLOW_QUERY_MS: u64 = 2000;
+const DEFAULT_PREPARED_CACHE_CAPACITY: usize = 64;
+const DEFAULT_DB_WAIT_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_HANDSHAKE_TIMEOUT_MS: u64 = 10000;
+const DEFAULT_ROOT_CERT_RELOAD_SECS: u64 = 60;
+const DEFAULT_TOKEN_RELOAD_SECS: u64 = 60;
+const DEFAULT_BREAKER_OPEN_SECS: u64 = 30;
+const DEFAULT_PROJECT_COLUMN: &str = "argocd_project";
+// ArgoCD's built-in project
+const DEFAULT_PROJECT: &str = "default";
+// Tenants named in a `missingFieldMode=fail` error body
+const MAX_LISTED_OFFENDERS: usize = 20;
+// The two an Application cannot be built without
+const DEFAULT_REQUIRED_FIELDS: [&str; 2] = ["repo_url", "repo_path"];
+const DEFAULT_ERROR_BUDGET_THRESHOLD: f64 = 0.05;
+// Longest gap between error budget reports; shorter windows report once per window
+const ERROR_BUDGET_REPORT_SECS: u64 = 60;
+const DEFAULT_RESPONSE_CACHE_MAX_ENTRIES: usize = 128;
+const DEFAULT_RESPONSE_CACHE_MAX_BYTES: usize = 32 * 1024 * 1024;
+// Each prefetched page is up to KEYSPACES_PAGE_SIZE decoded rows held per scan
+const MAX_PAGE_PREFETCH_DEPTH: usize = 8;
+const DEFAULT_PREVIEW_LIMIT: usize = 5;
+// Delta snapshots kept in memory; the oldest is forgotten first
+const DELTA_SNAPSHOT_CAPACITY: usize = 32;
+// Keyspaces rejects IN restrictions with more than 100 values
+const MAX_CLUSTERS: usize = 100;
+// Every `filterMode` the plugin implements; ALLOWED_FILTER_MODES picks from these
+const FILTER_MODES: [&str; 2] = ["exact", "regex"];
+const DEFAULT_REGEX_MAX_LEN: usize = 256;
+// Compiled program size; the regex crate's own default is 10 MiB
+const DEFAULT_REGEX_SIZE_LIMIT: usize = 256 * 1024;
+const DEFAULT_REGEX_CACHE_CAPACITY: usize = 128;
+
+// Settings read once from the environment at startup
+#[derive(Debug)]
+struct Config {
+    // CQL port for the regional endpoint and for a KEYSPACES_READ_ENDPOINT without one
+    port: u16,
+    keyspace: String,
+    // Tenant tables scanned in order and unioned
+    tables: Vec<String>,
+    page_size: i32,
+    // Upper clamp for the `pageSize` request parameter
+    max_page_size: i32,
+    // Upper bound on distinct CQL strings kept prepared
+    prepared_cache_capacity: usize,
+    route_prefix: String,
+    // Where the plugin token is expected: `<auth_header_name>: <auth_scheme> <token>`, or
+    // just the token when the scheme is empty
+    auth_header_name: axum::http::HeaderName,
+    auth_scheme: String,
+    // HTTPS with SERVER_TLS_CERT/SERVER_TLS_KEY; plain HTTP when unset
+    server_tls_cert: Option<String>,
+    server_tls_key: Option<String>,
+    // Client certificates verified against this CA (mTLS)
+    server_client_ca: Option<String>,
+    client_auth_mode: ClientAuthMode,
+    // Scans slower than this log a warning; 0 disables
+    slow_query_ms: u64,
+    // Fallback namespace for rows whose column is null or empty, `{tenantId}` substituted
+    namespace_template: Option<String>,
+    // Label key -> lookup table used instead of the scan when the label filter is on that key
+    index_hints: BTreeMap<String, IndexHint>,
+    // VIEW_NAME: scanned instead of the tables, with the tenant columns renamed per
+    // VIEW_COLUMN_MAP (logical name -> view column)
+    view_name: Option<String>,
+    view_columns: BTreeMap<String, String>,
+    // READINESS_QUERY: a SELECT run by `/readyz` instead of the system_schema lookup
+    readiness_query: Option<String>,
+    // Strip trailing slashes and `.git` from `repoURL`
+    normalize_repo_url: bool,
+    // DEBUG_ECHO_QUERY: the executed CQL in `X-CQL-Query`; for non-production use only
+    debug_echo_query: bool,
+    // Optional text column emitted as `destNamespace`; unset leaves the key out entirely
+    dest_namespace_column: Option<String>,
+    // Optional integer (or integer-valued text) column emitted as `syncWave`
+    sync_wave_column: Option<String>,
+    // Optional map<text, text> column emitted as `annotations`
+    annotations_column: Option<String>,
+    // EMIT_PROJECT: text column emitted as `project`, DEFAULT_PROJECT when null or empty
+    project_column: Option<String>,
+    default_project: String,
+    // Scalar columns selected on every request and emitted as typed top-level keys
+    promoted_columns: Vec<String>,
+    // Column whose WRITETIME `includeWriteTime` reports
+    write_time_column: Option<String>,
+    // Highest first; see `ParamSource`
+    param_precedence: Vec<ParamSource>,
+    // Constant keys added to every entry that does not already have them
+    default_params: serde_json::Map<String, serde_json::Value>,
+    // Caps on map entries per tenant, enforced per `oversizeMode`; 0 disables
+    max_labels_per_tenant: usize,
+    max_params_per_tenant: usize,
+    // Tenant columns `missingFieldMode` checks, as listed in REQUIRED_FIELD_KEYS
+    required_fields: Vec<String>,
+    // Concurrent Keyspaces calls across all requests; 0 disables
+    max_db_concurrency: usize,
+    // How long a call may queue for a DB permit before the request gets a 503
+    db_wait_timeout_ms: u64,
+    // `filterMode` values requests may use; defaults to all of FILTER_MODES
+    allowed_filter_modes: Vec<String>,
+    // `filterMode=regex` guards: pattern length, compiled size, and compiled patterns kept
+    regex_max_len: usize,
+    regex_size_limit: usize,
+    regex_cache_capacity: usize,
+    // How mounted secret files are noticed when they change
+    reload_strategy: ReloadStrategy,
+    // Consecutive failed Keyspaces queries that open the breaker; 0 disables it
+    breaker_failure_threshold: u32,
+    breaker_open_secs: u64,
+    // Last-success age after which the plugin sheds load; 0 disables
+    health_max_staleness_secs: u64,
+    // Rolling window of getparams outcomes reported periodically; 0 disables
+    error_budget_window_secs: u64,
+    // Error ratio above which a report warns
+    error_budget_threshold: f64,
+    // Computed responses reused per parameter set for this long; 0 disables
+    response_cache_ttl_secs: u64,
+    response_cache_max_entries: usize,
+    response_cache_max_bytes: usize,
+    // Pages fetched ahead of the one being decoded; 0 fetches only on demand
+    page_prefetch_depth: usize,
+    // Read consistency unless a request asks for another via `consistency`
+    consistency: Consistency,
+    // Cap on pages fetched per request across all tables; 0 disables
+    max_pages: u32,
+    max_pages_mode: MaxPagesMode,
+    // How a tenant found in several KEYSPACES_TABLES is combined
+    merge_strategy: MergeStrategy,
+    // Early-warning tenant count per response; 0 disables. Never fails a request.
+    warn_tenant_threshold: usize,
+    // Cap on the serialized JSON body; 0 disables
+    max_response_bytes: usize,
+    // Request header guards, checked before routing; 0 disables
+    max_request_headers: usize,
+    max_request_header_bytes: usize,
+    max_response_bytes_mode: MaxResponseBytesMode,
+    // Last complete responses persisted for cold starts; unset disables
+    snapshot_file: Option<String>,
+    // PARAM_ALLOWLIST_FILE: SHA-256 hex of a token -> generator parameters it may send. A
+    // token without an entry may send any. Never exposed, not even by `/api/v1/config`.
+    param_allowlist: ParamAllowlist,
+}
+
+// The hashes identify tokens, so `Debug` (and with it the startup config log) shows only
+// how many entries there are
+struct ParamAllowlist(BTreeMap<String, BTreeSet<String>>);
+
+impl std::fmt::Debug for ParamAllowlist {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} entries", self.0.len())
+    }
+}
+
+// What to do when a JSON response would exceed MAX_RESPONSE_BYTES
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MaxResponseBytesMode {
+    // Fail the request
+    Fail,
+    // Keep the leading entries that fit, with `X-Truncated: true`
+    Truncate,
+}
+
+// A denormalized copy of the tenant table partitioned by one label's value. Keyspaces has
+// no materialized views or secondary indexes, so the copy is maintained by the writer.
+#[derive(Debug, Clone, Deserialize)]
+struct IndexHint {
+    table: String,
+    // Column holding the label value; defaults to the label key
+    #[serde(default)]
+    column: String,
+}
+
+// What to do when a scan hits MAX_PAGES
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MaxPagesMode {
+    // Return the rows gathered so far with `X-Truncated: true`
+    Truncate,
+    // Fail the request
+    Fail,
+}
+
+impl Config {
+    fn from_env() -> anyhow::Result<Self> {
+        let port = match env::var("KEYSPACES_PORT") {
+            Ok(v) =>
+                v
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|p| *p > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid KEYSPACES_PORT {v:?}"))?,
+            Err(_) => DEFAULT_KEYSPACES_PORT,
+        };
+        let keyspace = env::var("KEYSPACES_KEYSPACE").unwrap_or_else(|_| "tenant_ops".to_string());
+        if !is_identifier(&keyspace) {
+            anyhow::bail!("invalid KEYSPACES_KEYSPACE {keyspace:?}");
+        }
+        // KEYSPACES_TABLES (comma-separated) wins over the single KEYSPACES_TABLE
+        let tables: Vec<String> = match env::var("KEYSPACES_TABLES") {
+            Ok(v) =>
+                v
+                    .split(',')
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect(),
+            Err(_) =>
+                vec![env::var("KEYSPACES_TABLE").unwrap_or_else(|_| "tenant_configs".to_string())],
+        };
+        if tables.is_empty() {
+            anyhow::bail!("KEYSPACES_TABLES is empty");
+        }
+        for table in &tables {
+            if !is_identifier(table) {
+                anyhow::bail!("invalid table name {table:?}");
+            }
+        }
 
-static SESSION: OnceCell<Arc<Session>> = OnceCell::new();
-static TOKEN: OnceCell<String> = OnceCell::new();
+        let page_size = match env::var("KEYSPACES_PAGE_SIZE") {
+            Ok(v) =>
+                v
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid KEYSPACES_PAGE_SIZE {v:?}"))?,
+            Err(_) => DEFAULT_PAGE_SIZE,
+        };
+        let max_page_size = match env::var("MAX_PAGE_SIZE") {
+            Ok(v) =>
+                v
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid MAX_PAGE_SIZE {v:?}"))?,
+            // Never below the global size, so raising KEYSPACES_PAGE_SIZE alone keeps working
+            Err(_) => DEFAULT_MAX_PAGE_SIZE.max(page_size),
+        };
+        let route_prefix = route_prefix(&env::var("ROUTE_PREFIX").unwrap_or_default())?;
+        let auth_header_name = env::var("AUTH_HEADER_NAME").unwrap_or_else(|_| "Authorization".to_string());
+        let auth_header_name = axum::http::HeaderName
+            ::from_bytes(auth_header_name.as_bytes())
+            .map_err(|_| anyhow::anyhow!("invalid AUTH_HEADER_NAME {auth_header_name:?}"))?;
+        let auth_scheme = env::var("AUTH_SCHEME").unwrap_or_else(|_| "Bearer".to_string());
+        if !auth_scheme.chars().all(|c| c.is_ascii_graphic()) {
+            anyhow::bail!("invalid AUTH_SCHEME {auth_scheme:?} (must be a single token)");
+        }
+        let non_empty = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        let server_tls_cert = non_empty("SERVER_TLS_CERT");
+        let server_tls_key = non_empty("SERVER_TLS_KEY");
+        if server_tls_cert.is_some() != server_tls_key.is_some() {
+            anyhow::bail!("SERVER_TLS_CERT and SERVER_TLS_KEY must be set together");
+        }
+        let server_client_ca = non_empty("SERVER_CLIENT_CA");
+        if server_client_ca.is_some() && server_tls_cert.is_none() {
+            anyhow::bail!("SERVER_CLIENT_CA requires SERVER_TLS_CERT and SERVER_TLS_KEY");
+        }
+        let client_auth_mode = match non_empty("CLIENT_AUTH_MODE") {
+            None => ClientAuthMode::default(),
+            Some(_) if server_client_ca.is_none() => anyhow::bail!("CLIENT_AUTH_MODE requires SERVER_CLIENT_CA"),
+            Some(v) =>
+                ClientAuthMode::parse(&v).ok_or_else(|| {
+                    anyhow::anyhow!("invalid CLIENT_AUTH_MODE {v:?} (expected both, either or cert)")
+                })?,
+        };
+        let slow_query_ms = match env::var("SLOW_QUERY_MS") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid SLOW_QUERY_MS {v:?}"))?,
+            Err(_) => DEFAULT_SLOW_QUERY_MS,
+        };
+        let prepared_cache_capacity = match env::var("PREPARED_CACHE_CAPACITY") {
+            Ok(v) =>
+                v
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid PREPARED_CACHE_CAPACITY {v:?}"))?,
+            Err(_) => DEFAULT_PREPARED_CACHE_CAPACITY,
+        };
+        let dest_namespace_column = match env::var("DEST_NAMESPACE_COLUMN") {
+            Ok(v) if !v.is_empty() => {
+                let column = v.to_lowercase();
+                if !is_identifier(&column) || TENANT_COLUMNS.contains(&column.as_str()) {
+                    anyhow::bail!("invalid DEST_NAMESPACE_COLUMN {v:?}");
+                }
+                Some(column)
+            }
+            _ => None,
+        };
+        let mut mapped_columns: Vec<String> = dest_namespace_column.iter().cloned().collect();
+        let mut mapped_column = |name: &str, default: Option<&str>| -> anyhow::Result<Option<String>> {
+            match env::var(name).ok().filter(|v| !v.is_empty()).or(default.map(str::to_string)) {
+                Some(v) => {
+                    let column = v.to_lowercase();
+                    if
+                        !is_identifier(&column) ||
+                        TENANT_COLUMNS.contains(&column.as_str()) ||
+                        mapped_columns.contains(&column)
+                    {
+                        anyhow::bail!("invalid {name} {v:?}");
+                    }
+                    mapped_columns.push(column.clone());
+                    Ok(Some(column))
+                }
+                None => Ok(None),
+            }
+        };
+        let sync_wave_column = mapped_column("SYNC_WAVE_COLUMN", None)?;
+        let annotations_column = mapped_column("ANNOTATIONS_COLUMN", None)?;
+        // Opt-in, so tables without the column keep working
+        let project_column = if env_flag("EMIT_PROJECT") {
+            mapped_column("PROJECT_COLUMN", Some(DEFAULT_PROJECT_COLUMN))?
+        } else {
+            None
+        };
+        let default_project = env
+            ::var("DEFAULT_PROJECT")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| DEFAULT_PROJECT.to_string());
+        // Keyspaces has no WRITETIME for the primary key or collection columns
+        let write_time_column = match env::var("WRITETIME_COLUMN") {
+            Ok(v) if !v.is_empty() => {
+                let column = v.to_lowercase();
+                if !is_identifier(&column) || ["tenant_id", "labels", "params"].contains(&column.as_str()) {
+                    anyhow::bail!(
+                        "invalid WRITETIME_COLUMN {v:?} (expected a non-key, non-collection column)"
+                    );
+                }
+                Some(column)
+            }
+            _ => None,
+        };
+        let promoted_columns = promoted_columns(&env::var("PROMOTED_COLUMNS").unwrap_or_default(), &mapped_columns)?;
+        let param_precedence = match env::var("PARAM_SOURCE_PRECEDENCE") {
+            Err(_) => DEFAULT_PARAM_PRECEDENCE.to_vec(),
+            Ok(v) => {
+                let mut order = Vec::new();
+                for name in v.split(',').map(|s| s.trim()) {
+                    let source = DEFAULT_PARAM_PRECEDENCE.into_iter()
+                        .find(|s| s.name() == name)
+                        .ok_or_else(|| anyhow::anyhow!("invalid PARAM_SOURCE_PRECEDENCE entry {name:?}"))?;
+                    if order.contains(&source) {
+                        anyhow::bail!("PARAM_SOURCE_PRECEDENCE lists {name:?} twice");
+                    }
+                    order.push(source);
+                }
+                if order.len() != DEFAULT_PARAM_PRECEDENCE.len() {
+                    anyhow::bail!("PARAM_SOURCE_PRECEDENCE must list every source: promoted, params");
+                }
+                order
+            }
+        };
+        let max_labels_per_tenant = match env::var("MAX_LABELS_PER_TENANT") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid MAX_LABELS_PER_TENANT {v:?}"))?,
+            Err(_) => 0,
+        };
+        let max_params_per_tenant = match env::var("MAX_PARAMS_PER_TENANT") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid MAX_PARAMS_PER_TENANT {v:?}"))?,
+            Err(_) => 0,
+        };
+        let required_fields = match env::var("REQUIRED_FIELDS") {
+            Err(_) => DEFAULT_REQUIRED_FIELDS.iter().map(|f| f.to_string()).collect(),
+            Ok(v) => {
+                let mut fields: Vec<String> = Vec::new();
+                for field in v.split(',').map(|f| f.trim().to_lowercase()).filter(|f| !f.is_empty()) {
+                    if !REQUIRED_FIELD_KEYS.iter().any(|(column, _)| *column == field) {
+                        anyhow::bail!("invalid REQUIRED_FIELDS entry {field:?}");
+                    }
+                    if !fields.contains(&field) {
+                        fields.push(field);
+                    }
+                }
+                fields
+            }
+        };
+        // INDEX_HINTS: {"<label key>": "<table>"} or {"<label key>": {"table": ..., "column": ...}}
+        let mut index_hints: BTreeMap<String, IndexHint> = BTreeMap::new();
+        if let Ok(raw) = env::var("INDEX_HINTS") {
+            let parsed: BTreeMap<String, serde_json::Value> = serde_json
+                ::from_str(&raw)
+                .map_err(|e| anyhow::anyhow!("invalid INDEX_HINTS: {e}"))?;
+            for (key, value) in parsed {
+                let mut hint: IndexHint = match value {
+                    serde_json::Value::String(table) => IndexHint { table, column: String::new() },
+                    other =>
+                        serde_json
+                            ::from_value(other)
+                            .map_err(|e| anyhow::anyhow!("invalid INDEX_HINTS entry {key:?}: {e}"))?,
+                };
+                if hint.column.is_empty() {
+                    hint.column = key.clone();
+                }
+                if !is_identifier(&hint.table) || !is_identifier(&hint.column) {
+                    anyhow::bail!(
+                        "invalid INDEX_HINTS entry {key:?}: table {:?} and column {:?} must be identifiers",
+                        hint.table,
+                        hint.column
+                    );
+                }
+                index_hints.insert(key, hint);
+            }
+        }
+        let default_params = match env::var("DEFAULT_PARAMS") {
+            Ok(raw) =>
+                match serde_json::from_str::<serde_json::Value>(&raw) {
+                    Ok(serde_json::Value::Object(map)) => map,
+                    Ok(_) => anyhow::bail!("invalid DEFAULT_PARAMS: expected a JSON object"),
+                    Err(e) => anyhow::bail!("invalid DEFAULT_PARAMS: {e}"),
+                }
+            Err(_) => serde_json::Map::new(),
+        };
+        if let Some(key) = default_params.keys().find(|k| k.is_empty() || k.starts_with('_')) {
+            anyhow::bail!("invalid DEFAULT_PARAMS key {key:?} (empty and `_`-prefixed keys are reserved)");
+        }
+        let view_name = match env::var("VIEW_NAME") {
+            Ok(v) if !v.is_empty() => {
+                if !is_identifier(&v) {
+                    anyhow::bail!("invalid VIEW_NAME {v:?}");
+                }
+                if tables.len() > 1 || !index_hints.is_empty() {
+                    anyhow::bail!("VIEW_NAME cannot be combined with several KEYSPACES_TABLES or INDEX_HINTS");
+                }
+                Some(v)
+            }
+            _ => None,
+        };
+        let readiness_query = match env::var("READINESS_QUERY") {
+            Ok(v) if !v.trim().is_empty() =>
+                Some(read_only_query(&v).map_err(|e| anyhow::anyhow!("invalid READINESS_QUERY {v:?}: {e}"))?),
+            _ => None,
+        };
+        let mut view_columns: BTreeMap<String, String> = BTreeMap::new();
+        if let Ok(raw) = env::var("VIEW_COLUMN_MAP") {
+            if view_name.is_none() {
+                anyhow::bail!("VIEW_COLUMN_MAP needs VIEW_NAME");
+            }
+            view_columns = serde_json
+                ::from_str(&raw)
+                .map_err(|e| anyhow::anyhow!("invalid VIEW_COLUMN_MAP: {e}"))?;
+            for (logical, column) in &view_columns {
+                if !TENANT_COLUMNS.contains(&logical.as_str()) && logical != "enabled" {
+                    anyhow::bail!("invalid VIEW_COLUMN_MAP key {logical:?} (expected a tenant column or enabled)");
+                }
+                if !is_identifier(column) {
+                    anyhow::bail!("invalid VIEW_COLUMN_MAP entry {logical:?}: {column:?} is not an identifier");
+                }
+            }
+        }
+        if !index_hints.is_empty() && tables.len() > 1 {
+            anyhow::bail!("INDEX_HINTS cannot be combined with several KEYSPACES_TABLES");
+        }
+        let max_db_concurrency = match env::var("MAX_DB_CONCURRENCY") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid MAX_DB_CONCURRENCY {v:?}"))?,
+            Err(_) => 0,
+        };
+        let db_wait_timeout_ms = match env::var("DB_WAIT_TIMEOUT_MS") {
+            Ok(v) =>
+                v
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid DB_WAIT_TIMEOUT_MS {v:?}"))?,
+            Err(_) => DEFAULT_DB_WAIT_TIMEOUT_MS,
+        };
+        let allowed_filter_modes = match env::var("ALLOWED_FILTER_MODES") {
+            Err(_) => FILTER_MODES.map(String::from).to_vec(),
+            Ok(v) => {
+                let mut modes: Vec<String> = Vec::new();
+                for mode in v.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                    if !FILTER_MODES.contains(&mode) {
+                        anyhow::bail!(
+                            "invalid ALLOWED_FILTER_MODES entry {mode:?} (known modes: {})",
+                            FILTER_MODES.join(", ")
+                        );
+                    }
+                    if !modes.iter().any(|m| m == mode) {
+                        modes.push(mode.to_string());
+                    }
+                }
+                modes
+            }
+        };
+        let regex_max_len = positive_env("REGEX_MAX_LEN")
+            .map_err(anyhow::Error::msg)?
+            .unwrap_or(DEFAULT_REGEX_MAX_LEN);
+        let regex_size_limit = positive_env("REGEX_SIZE_LIMIT")
+            .map_err(anyhow::Error::msg)?
+            .unwrap_or(DEFAULT_REGEX_SIZE_LIMIT);
+        let regex_cache_capacity = positive_env("REGEX_CACHE_CAPACITY")
+            .map_err(anyhow::Error::msg)?
+            .unwrap_or(DEFAULT_REGEX_CACHE_CAPACITY);
+        let reload_strategy = match env::var("SECRET_RELOAD_STRATEGY").as_deref() {
+            Err(_) | Ok("poll") => ReloadStrategy::Poll,
+            Ok("notify") => ReloadStrategy::Notify,
+            Ok(other) => anyhow::bail!("invalid SECRET_RELOAD_STRATEGY {other:?} (expected poll or notify)"),
+        };
+        let breaker_failure_threshold = match env::var("BREAKER_FAILURE_THRESHOLD") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid BREAKER_FAILURE_THRESHOLD {v:?}"))?,
+            Err(_) => 0,
+        };
+        let breaker_open_secs = match env::var("BREAKER_OPEN_SECS") {
+            Ok(v) =>
+                v
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid BREAKER_OPEN_SECS {v:?}"))?,
+            Err(_) => DEFAULT_BREAKER_OPEN_SECS,
+        };
+        let health_max_staleness_secs = match env::var("HEALTH_MAX_STALENESS_SECS") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid HEALTH_MAX_STALENESS_SECS {v:?}"))?,
+            Err(_) => 0,
+        };
+        let error_budget_window_secs = match env::var("ERROR_BUDGET_WINDOW_SECS") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid ERROR_BUDGET_WINDOW_SECS {v:?}"))?,
+            Err(_) => 0,
+        };
+        let error_budget_threshold = match env::var("ERROR_BUDGET_THRESHOLD") {
+            Ok(v) =>
+                v
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|t| *t > 0.0 && *t <= 1.0)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("invalid ERROR_BUDGET_THRESHOLD {v:?} (expected a ratio above 0, up to 1)")
+                    })?,
+            Err(_) => DEFAULT_ERROR_BUDGET_THRESHOLD,
+        };
+        let response_cache_ttl_secs = match env::var("RESPONSE_CACHE_TTL_SECS") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid RESPONSE_CACHE_TTL_SECS {v:?}"))?,
+            Err(_) => 0,
+        };
+        let response_cache_max_entries = positive_env("RESPONSE_CACHE_MAX_ENTRIES")
+            .map_err(anyhow::Error::msg)?
+            .unwrap_or(DEFAULT_RESPONSE_CACHE_MAX_ENTRIES);
+        let response_cache_max_bytes = positive_env("RESPONSE_CACHE_MAX_BYTES")
+            .map_err(anyhow::Error::msg)?
+            .unwrap_or(DEFAULT_RESPONSE_CACHE_MAX_BYTES);
+        let page_prefetch_depth = match env::var("PAGE_PREFETCH_DEPTH") {
+            Ok(v) =>
+                v
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n <= MAX_PAGE_PREFETCH_DEPTH)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "invalid PAGE_PREFETCH_DEPTH {v:?} (expected 0 to {MAX_PAGE_PREFETCH_DEPTH})"
+                        )
+                    })?,
+            Err(_) => 0,
+        };
+        let consistency = match env::var("KEYSPACES_CONSISTENCY") {
+            Ok(v) =>
+                parse_consistency(&v).ok_or_else(|| {
+                    anyhow::anyhow!("invalid KEYSPACES_CONSISTENCY {v:?} (expected {})", READ_CONSISTENCIES.join(", "))
+                })?,
+            Err(_) => Consistency::LocalQuorum,
+        };
+        let max_pages = match env::var("MAX_PAGES") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid MAX_PAGES {v:?}"))?,
+            Err(_) => 0,
+        };
+        let max_pages_mode = match env::var("MAX_PAGES_MODE").as_deref() {
+            Err(_) | Ok("truncate") => MaxPagesMode::Truncate,
+            Ok("fail") => MaxPagesMode::Fail,
+            Ok(other) => anyhow::bail!("invalid MAX_PAGES_MODE {other:?} (expected truncate or fail)"),
+        };
+        let merge_strategy = match env::var("MERGE_STRATEGY").as_deref() {
+            Err(_) | Ok("shadow") => MergeStrategy::Shadow,
+            Ok("first") => MergeStrategy::First,
+            Ok("last") => MergeStrategy::Last,
+            Ok("error") => MergeStrategy::Error,
+            Ok(other) =>
+                anyhow::bail!("invalid MERGE_STRATEGY {other:?} (expected shadow, first, last or error)"),
+        };
+        let warn_tenant_threshold = match env::var("WARN_TENANT_THRESHOLD") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid WARN_TENANT_THRESHOLD {v:?}"))?,
+            Err(_) => 0,
+        };
+        let max_response_bytes = match env::var("MAX_RESPONSE_BYTES") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid MAX_RESPONSE_BYTES {v:?}"))?,
+            Err(_) => 0,
+        };
+        let max_request_headers = match env::var("MAX_REQUEST_HEADERS") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid MAX_REQUEST_HEADERS {v:?}"))?,
+            Err(_) => DEFAULT_MAX_REQUEST_HEADERS,
+        };
+        let max_request_header_bytes = match env::var("MAX_REQUEST_HEADER_BYTES") {
+            Ok(v) => v.parse().map_err(|_| anyhow::anyhow!("invalid MAX_REQUEST_HEADER_BYTES {v:?}"))?,
+            Err(_) => DEFAULT_MAX_REQUEST_HEADER_BYTES,
+        };
+        let max_response_bytes_mode = match env::var("MAX_RESPONSE_BYTES_MODE").as_deref() {
+            Err(_) | Ok("fail") => MaxResponseBytesMode::Fail,
+            Ok("truncate") => MaxResponseBytesMode::Truncate,
+            Ok(other) =>
+                anyhow::bail!("invalid MAX_RESPONSE_BYTES_MODE {other:?} (expected fail or truncate)"),
+        };
+        let snapshot_file = env
+            ::var("SNAPSHOT_FILE")
+            .ok()
+            .filter(|s| !s.is_empty());
+        let param_allowlist = match env::var("PARAM_ALLOWLIST_FILE") {
+            Ok(path) if !path.is_empty() => {
+                let raw = fs
+                    ::read_to_string(&path)
+                    .map_err(|e| anyhow::anyhow!("PARAM_ALLOWLIST_FILE {path}: {e}"))?;
+                let allowlist: BTreeMap<String, BTreeSet<String>> = serde_json
+                    ::from_str(&raw)
+                    .map_err(|e| anyhow::anyhow!("PARAM_ALLOWLIST_FILE {path}: {e}"))?;
+                for (hash, names) in &allowlist {
+                    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+                        anyhow::bail!("PARAM_ALLOWLIST_FILE {path}: {hash:?} is not a lowercase hex SHA-256");
+                    }
+                    if let Some(name) = names.iter().find(|n| !REQUEST_PARAMETERS.iter().any(|p| p.name == n.as_str())) {
+                        anyhow::bail!("PARAM_ALLOWLIST_FILE {path}: unknown parameter {name:?}");
+                    }
+                }
+                ParamAllowlist(allowlist)
+            }
+            _ => ParamAllowlist(BTreeMap::new()),
+        };
+        Ok(Config {
+            port,
+            keyspace,
+            tables,
+            page_size,
+            max_page_size,
+            prepared_cache_capacity,
+            route_prefix,
+            auth_header_name,
+            auth_scheme,
+            server_tls_cert,
+            server_tls_key,
+            server_client_ca,
+            client_auth_mode,
+            slow_query_ms,
+            namespace_template: env
+                ::var("NAMESPACE_TEMPLATE")
+                .ok()
+                .filter(|t| !t.is_empty()),
+            index_hints,
+            view_name,
+            readiness_query,
+            view_columns,
+            normalize_repo_url: env_flag("NORMALIZE_REPO_URL"),
+            debug_echo_query: env_flag("DEBUG_ECHO_QUERY"),
+            dest_namespace_column,
+            sync_wave_column,
+            annotations_column,
+            project_column,
+            default_project,
+            promoted_columns,
+            write_time_column,
+            param_precedence,
+            default_params,
+            max_labels_per_tenant,
+            max_params_per_tenant,
+            required_fields,
+            max_db_concurrency,
+            db_wait_timeout_ms,
+            allowed_filter_modes,
+            regex_max_len,
+            regex_size_limit,
+            regex_cache_capacity,
+            reload_strategy,
+            breaker_failure_threshold,
+            breaker_open_secs,
+            health_max_staleness_secs,
+            error_budget_window_secs,
+            error_budget_threshold,
+            response_cache_ttl_secs,
+            response_cache_max_entries,
+            response_cache_max_bytes,
+            page_prefetch_depth,
+            consistency,
+            max_pages,
+            max_pages_mode,
+            merge_strategy,
+            warn_tenant_threshold,
+            max_response_bytes,
+            max_response_bytes_mode,
+            max_request_headers,
+            max_request_header_bytes,
+            snapshot_file,
+            param_allowlist,
+        })
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct PluginInput {
-    #[serde(default)]
-    applicationSetName: Option<String>,
+    #[serde(default, rename = "applicationSetName")]
+    application_set_name: Option<String>,
     #[serde(default)]
     input: InputWrapper,
 }
@@ -40,60 +836,332 @@ struct InputWrapper {
     parameters: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 struct PluginResponse {
     output: Output,
+    // `includeMeta`: what the generator did; ArgoCD ignores unknown top-level fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<serde_json::Value>,
+    // `delta`: snapshot id and removed tenant ids for the changed-only output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone)]
 struct Output {
     // One map per tenant
     parameters: Vec<HashMap<String, serde_json::Value>>,
+    // `countOnly`: replaces `parameters`, outside the ArgoCD contract
+    count: Option<usize>,
+}
+
+impl Serialize for Output {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut output = serializer.serialize_struct("Output", 1)?;
+        match self.count {
+            Some(count) => output.serialize_field("count", &count)?,
+            None => output.serialize_field("parameters", &self.parameters)?,
+        }
+        output.end()
+    }
 }
 
 #[derive(Debug, Error)]
 enum AppError {
     #[error("forbidden")]
     Unauthorized,
+    #[error("bad request: {0}")] BadRequest(String),
     #[error("internal: {0}")] Internal(String),
+    // A failed Keyspaces call; `class` is from `query_error_class`
+    #[error("query failed ({class}): {message}")] Query {
+        class: &'static str,
+        message: String,
+    },
+    #[error("unavailable: {0}")] Unavailable(&'static str),
+    #[error("not found: {0}")] NotFound(String),
+    #[error("request headers too large: {0}")] HeadersTooLarge(String),
+    #[error("conflict: {0}")] Conflict(String),
+    // The request is fine but the data it selects is not, e.g. `missingFieldMode=fail`
+    #[error("unprocessable: {0}")] Unprocessable(String),
+    // A valid token sending a parameter outside its PARAM_ALLOWLIST_FILE entry
+    #[error("parameter not allowed: {0}")] ParamNotAllowed(String),
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::Unauthorized | AppError::ParamNotAllowed(_) => StatusCode::FORBIDDEN,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Internal(_) | AppError::Query { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::HeadersTooLarge(_) => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::Unauthorized => "forbidden",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Internal(_) | AppError::Query { .. } => "internal",
+            AppError::Unavailable(_) => "unavailable",
+            AppError::NotFound(_) => "not_found",
+            AppError::HeadersTooLarge(_) => "headers_too_large",
+            AppError::Conflict(_) => "conflict",
+            AppError::Unprocessable(_) => "unprocessable",
+            AppError::ParamNotAllowed(_) => "param_not_allowed",
+        }
+    }
+
+    fn query_class(&self) -> Option<&'static str> {
+        match self {
+            AppError::Query { class, .. } => Some(class),
+            _ => None,
+        }
+    }
 }
 
+// Every error is `{"error": {"code": ..., "message": ...}}` so clients can parse one shape
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        match self {
-            AppError::Unauthorized => (StatusCode::FORBIDDEN, "forbidden").into_response(),
+        let message = match &self {
+            AppError::Unauthorized => "forbidden".to_string(),
+            AppError::BadRequest(msg) |
+            AppError::NotFound(msg) |
+            AppError::HeadersTooLarge(msg) |
+            AppError::Conflict(msg) |
+            AppError::Unprocessable(msg) |
+            AppError::ParamNotAllowed(msg) => msg.clone(),
             AppError::Internal(msg) => {
                 error!("internal-error: {}", msg);
-                (StatusCode::INTERNAL_SERVER_ERROR, "internal error").into_response()
+                "internal error".to_string()
             }
-        }
+            // Same generic 500 for the client; the class is for operators
+            AppError::Query { class, message } => {
+                error!(error_class = *class, "internal-error: keyspaces query failed ({}): {}", class, message);
+                "internal error".to_string()
+            }
+            AppError::Unavailable(msg) => msg.to_string(),
+        };
+        let body = serde_json::json!({ "error": { "code": self.code(), "message": message } });
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+// MAX_REQUEST_HEADERS / MAX_REQUEST_HEADER_BYTES, ahead of routing and auth. Hyper's own
+// parser limits still apply first.
+async fn guard_headers(request: axum::extract::Request, next: axum::middleware::Next) -> Result<Response, AppError> {
+    let config = CONFIG.get().expect("config not initialized");
+    let headers = request.headers();
+    let bytes: usize = headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum();
+    let rejected = if config.max_request_headers > 0 && headers.len() > config.max_request_headers {
+        Some(("count", format!("{} headers, limit {}", headers.len(), config.max_request_headers)))
+    } else if config.max_request_header_bytes > 0 && bytes > config.max_request_header_bytes {
+        Some(("size", format!("{} header bytes, limit {}", bytes, config.max_request_header_bytes)))
+    } else {
+        None
+    };
+    if let Some((reason, message)) = rejected {
+        warn!("rejecting {} {}: {}", request.method(), request.uri().path(), message);
+        METRICS.inc_counter(
+            "keyspaces_plugin_rejected_header_requests_total",
+            "Requests rejected by MAX_REQUEST_HEADERS or MAX_REQUEST_HEADER_BYTES",
+            &[("reason", reason)]
+        );
+        return Err(AppError::HeadersTooLarge(message));
     }
+    Ok(next.run(request).await)
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+// Unmatched paths; known routes still authorize first, so a bad token there stays a 403
+async fn not_found(uri: axum::http::Uri) -> AppError {
+    AppError::NotFound(format!("no route for {}", uri.path()))
+}
+
+const DEFAULT_MAX_BLOCKING_THREADS: usize = 64;
+const DEFAULT_STARTUP_RETRY_DELAY_MS: usize = 1000;
+const MAX_STARTUP_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Tracing
     tracing_subscriber
         ::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
+    if env_flag("SELFCHECK") || env::args().any(|a| a == "--selfcheck") {
+        return selfcheck::run();
+    }
+
+    // Runtime sized explicitly so resource-constrained pods can be right-sized
+    let worker_threads = positive_env("TOKIO_WORKER_THREADS")?.unwrap_or_else(|| {
+        std::thread
+            ::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    let max_blocking_threads = positive_env("TOKIO_MAX_BLOCKING_THREADS")?.unwrap_or(
+        DEFAULT_MAX_BLOCKING_THREADS
+    );
+    let runtime = tokio::runtime::Builder
+        ::new_multi_thread()
+        .worker_threads(worker_threads)
+        .max_blocking_threads(max_blocking_threads)
+        .enable_all()
+        .build()?;
+    info!(
+        "tokio runtime: worker_threads={}, max_blocking_threads={}",
+        worker_threads,
+        max_blocking_threads
+    );
+    runtime.block_on(serve())
+}
+
+// Prepended to every route, e.g. `/keyspaces` -> `/keyspaces/api/v1/getparams.execute`
+fn route_prefix(value: &str) -> anyhow::Result<String> {
+    let prefix = value.trim_end_matches('/');
+    if !prefix.is_empty() && (!prefix.starts_with('/') || prefix.contains(['*', '{', '}', ':', '?', '#'])) {
+        anyhow::bail!("invalid ROUTE_PREFIX {prefix:?} (must start with '/' and contain no wildcards)");
+    }
+    Ok(prefix.to_string())
+}
+
+// Lowercased and deduplicated; tenant and otherwise-mapped columns can't be promoted
+fn promoted_columns(value: &str, mapped_columns: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut promoted_columns: Vec<String> = Vec::new();
+    for column in value.split(',') {
+        let column = column.trim().to_lowercase();
+        if column.is_empty() {
+            continue;
+        }
+        if
+            !is_identifier(&column) ||
+            TENANT_COLUMNS.contains(&column.as_str()) ||
+            mapped_columns.contains(&column)
+        {
+            anyhow::bail!("invalid PROMOTED_COLUMNS entry {column:?}");
+        }
+        if !promoted_columns.contains(&column) {
+            promoted_columns.push(column);
+        }
+    }
+    Ok(promoted_columns)
+}
+
+fn positive_env(name: &str) -> Result<Option<usize>, String> {
+    match env::var(name) {
+        Ok(v) =>
+            v
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .map(Some)
+                .ok_or_else(|| format!("invalid {name} {v:?}")),
+        Err(_) => Ok(None),
+    }
+}
+
+async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::from_env().map_err(|e| format!("config: {e}"))?;
+    info!("config: {:?}", config);
+    if !config.index_hints.is_empty() {
+        info!(
+            "label keys eligible for pushdown: {}",
+            config.index_hints
+                .iter()
+                .map(|(k, h)| format!("{k} -> {}.{}", h.table, h.column))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    let prefix = config.route_prefix.clone();
+    if config.debug_echo_query {
+        warn!("DEBUG_ECHO_QUERY is set: responses carry the executed CQL in X-CQL-Query; do not use in production");
+    }
+    if config.max_db_concurrency > 0 {
+        DB_PERMITS.set(tokio::sync::Semaphore::new(config.max_db_concurrency)).ok();
+    }
+    if config.error_budget_window_secs > 0 {
+        let window = Duration::from_secs(config.error_budget_window_secs);
+        BUDGET.set(ErrorBudget::new(window, config.error_budget_threshold)).ok();
+        tokio::spawn(report_error_budget(window.min(Duration::from_secs(ERROR_BUDGET_REPORT_SECS))));
+    }
+    if config.response_cache_ttl_secs > 0 {
+        RESPONSES.set(
+            ResponseCache::new(
+                Duration::from_secs(config.response_cache_ttl_secs),
+                config.response_cache_max_entries,
+                config.response_cache_max_bytes
+            )
+        ).ok();
+    }
+    let saved = SavedResponses::load(config.snapshot_file.as_deref());
+    if env_flag("MAINTENANCE_MODE") {
+        if saved.is_empty() {
+            return Err("MAINTENANCE_MODE=true needs a warm cache, but SNAPSHOT_FILE has no saved responses".into());
+        }
+        set_maintenance(true, saved.len());
+    }
+    SAVED.set(saved).ok();
+    CONFIG.set(config).ok();
+
     // Load bearer token from a file that we mount via Secret
     let token_path = env
         ::var("PLUGIN_TOKEN_FILE")
         .unwrap_or_else(|_| "/var/run/argo/token".to_string());
-    let token = fs
+    let tokens = fs
         ::read_to_string(&token_path)
-        .map(|s| s.trim().to_string())
+        .map(|s| parse_tokens(&s))
         .map_err(|e| format!("failed to read plugin token: {e}"))?;
-    TOKEN.set(token).ok();
+    *TOKENS.write().unwrap() = tokens;
+    let token_reload_secs: u64 = match env::var("PLUGIN_TOKEN_RELOAD_SECS") {
+        Ok(v) => v.parse().map_err(|_| format!("invalid PLUGIN_TOKEN_RELOAD_SECS {v:?}"))?,
+        Err(_) => DEFAULT_TOKEN_RELOAD_SECS,
+    };
+    let strategy = CONFIG.get().expect("config not initialized").reload_strategy;
+    if token_reload_secs > 0 || strategy == ReloadStrategy::Notify {
+        let changes = FileChanges::new(
+            "plugin token",
+            std::slice::from_ref(&token_path),
+            strategy,
+            Duration::from_secs(token_reload_secs)
+        );
+        tokio::spawn(watch_token(token_path, changes));
+    }
 
-    // Build and cache DB session
-    let session = build_session().await.map_err(|e| format!("session build: {e}"))?;
-    SESSION.set(Arc::new(session)).ok();
+    match env::var("TENANT_SOURCE_FILE") {
+        Ok(path) if !path.is_empty() => {
+            let source = FileSource::load(&path).map_err(|e| format!("tenant source: {e}"))?;
+            warn!("TENANT_SOURCE_FILE is set: serving tenants from {}, Keyspaces is not used", path);
+            SOURCE.set(Box::new(source)).ok();
+        }
+        _ => {
+            start_keyspaces().await?;
+            SOURCE.set(Box::new(KeyspacesSource)).ok();
+        }
+    }
 
-    // HTTP router
-    let app = Router::new().route("/api/v1/getparams.execute", post(handler)).with_state(());
+    let app = router(&prefix);
+    let config = CONFIG.get().expect("config not initialized");
+    let server_tls = match (&config.server_tls_cert, &config.server_tls_key) {
+        (Some(cert), Some(key)) => {
+            let tls = ServerTls {
+                cert,
+                key,
+                client_ca: config.server_client_ca.as_deref(),
+                client_auth_mode: config.client_auth_mode,
+            };
+            Some(tls::server_config(&tls).map_err(|e| format!("server TLS: {e}"))?)
+        }
+        _ => None,
+    };
+    let app = client_cert_layer(app, config.server_client_ca.is_some(), config.client_auth_mode);
 
     let port: u16 = env
         ::var("PORT")
@@ -101,151 +1169,5019 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .and_then(|s| s.parse().ok())
         .unwrap_or(4355);
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    info!("listening on {}", addr);
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    match server_tls {
+        None => {
+            info!("listening on {}", addr);
+            axum::serve(listener, app).await?;
+        }
+        Some(server_tls) => {
+            match &config.server_client_ca {
+                Some(ca) =>
+                    info!(
+                        "listening on {} (HTTPS, client certificates from {}, CLIENT_AUTH_MODE={})",
+                        addr,
+                        ca,
+                        config.client_auth_mode.name()
+                    ),
+                None => info!("listening on {} (HTTPS)", addr),
+            }
+            axum::serve(
+                TlsListener::new(listener, server_tls),
+                app.into_make_service_with_connect_info::<Peer>()
+            ).await?;
+        }
+    }
+    Ok(())
+}
+
+// Every route under ROUTE_PREFIX; the client-certificate layer is added by `serve`
+fn router(prefix: &str) -> Router {
+    Router::new()
+        .route(&format!("{prefix}/api/v1/getparams.execute"), post(handler))
+        .route(&format!("{prefix}/api/v1/debug/plan"), post(debug_plan))
+        .route(&format!("{prefix}/api/v1/debug/getparams"), post(debug_getparams_form))
+        .route(&format!("{prefix}/api/v1/config"), get(effective_config))
+        .route(&format!("{prefix}/api/v1/validate"), post(validate_row))
+        .route(&format!("{prefix}/api/v1/diff"), post(diff_output))
+        .route(&format!("{prefix}/api/v1/maintenance"), post(toggle_maintenance))
+        .route(&format!("{prefix}/api/v1/schema/check"), get(schema_check))
+        .route(&format!("{prefix}/metrics"), get(metrics))
+        .route(&format!("{prefix}/readyz"), get(readyz))
+        .route(&format!("{prefix}/openapi.json"), get(openapi))
+        .fallback(not_found)
+        .with_state(())
+        .layer(axum::middleware::from_fn(guard_headers))
+}
+
+// Only CLIENT_AUTH_MODE either/cert let a certificate stand in for the token
+fn client_cert_layer(app: Router, client_ca: bool, mode: ClientAuthMode) -> Router {
+    if client_ca && mode != ClientAuthMode::Both {
+        app.layer(axum::middleware::from_fn(scope_client_cert))
+    } else {
+        app
+    }
+}
+
+// Only layered for CLIENT_AUTH_MODE either/cert, and only reachable over TLS
+async fn scope_client_cert(
+    axum::extract::ConnectInfo(peer): axum::extract::ConnectInfo<Peer>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next
+) -> Response {
+    CLIENT_CERT.scope(peer.client_cert, next.run(request)).await
+}
+
+// Sessions plus their background upkeep (keepalive, root CA reload)
+async fn start_keyspaces() -> Result<(), Box<dyn std::error::Error>> {
+    // Before any retries: no amount of waiting makes an unsupported proxy work
+    check_proxy_url()?;
+
+    let config = CONFIG.get().expect("config not initialized");
+    HEALTH.set(
+        HealthState::new(
+            config.breaker_failure_threshold,
+            Duration::from_secs(config.breaker_open_secs),
+            (config.health_max_staleness_secs > 0).then(|| Duration::from_secs(config.health_max_staleness_secs))
+        )
+    ).ok();
+
+    // Build and cache DB session
+    let retries: u32 = match env::var("STARTUP_RETRIES") {
+        Ok(v) => v.parse().map_err(|_| format!("invalid STARTUP_RETRIES {v:?}"))?,
+        Err(_) => 0,
+    };
+    let retry_delay = Duration::from_millis(
+        positive_env("STARTUP_RETRY_DELAY_MS")?.unwrap_or(DEFAULT_STARTUP_RETRY_DELAY_MS) as u64
+    );
+    match build_session_with_retry(retries, retry_delay).await {
+        Ok(session) => {
+            SESSION.set(Arc::new(session));
+            // As in `recover_session`: the primary session can serve generator reads too
+            match build_read_session().await {
+                Ok(Some(read)) => {
+                    READ_SESSION.set(Arc::new(read));
+                }
+                Ok(None) => {}
+                Err(e) => warn!("read session build failed, using the primary session: {}", e),
+            }
+        }
+        Err(e) if env_flag("START_WITHOUT_DB") => {
+            // Serve anyway: /readyz and the generator answer 503 until the session is up
+            error!("session build failed, starting without Keyspaces: {}", e);
+            tokio::spawn(recover_session(retry_delay));
+        }
+        Err(e) => {
+            return Err(format!("session build: {e}").into());
+        }
+    }
+    // Prepared ids belong to the session that prepared them
+    PREPARED.lock().unwrap().clear();
 
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, app).await?;
+    let keepalive_secs: u64 = match env::var("KEEPALIVE_QUERY_SECS") {
+        Ok(v) => v.parse().map_err(|_| format!("invalid KEEPALIVE_QUERY_SECS {v:?}"))?,
+        Err(_) => 0,
+    };
+    if keepalive_secs > 0 {
+        tokio::spawn(keepalive(Duration::from_secs(keepalive_secs)));
+    }
+    let cert_reload_secs: u64 = match env::var("KEYSPACES_ROOT_CERT_RELOAD_SECS") {
+        Ok(v) => v.parse().map_err(|_| format!("invalid KEYSPACES_ROOT_CERT_RELOAD_SECS {v:?}"))?,
+        Err(_) => DEFAULT_ROOT_CERT_RELOAD_SECS,
+    };
+    let root_cert = (!env_flag("KEYSPACES_INSECURE_SKIP_VERIFY")).then(root_cert_path);
+    let mut files: Vec<String> = root_cert.iter().cloned().collect();
+    files.extend(credential_files());
+    if !files.is_empty() && (cert_reload_secs > 0 || config.reload_strategy == ReloadStrategy::Notify) {
+        let changes = FileChanges::new(
+            "session files",
+            &files,
+            config.reload_strategy,
+            Duration::from_secs(cert_reload_secs)
+        );
+        tokio::spawn(watch_session_files(root_cert, files, changes));
+    }
     Ok(())
 }
 
+// KEYSPACES_PROXY_URL is validated and then rejected: the driver opens its own TCP
+// connections to every node and has no hook for a custom connector, so a SOCKS or HTTP
+// CONNECT tunnel cannot be inserted. Failing here beats silently connecting directly.
+fn check_proxy_url() -> anyhow::Result<()> {
+    let Ok(url) = env::var("KEYSPACES_PROXY_URL") else {
+        return Ok(());
+    };
+    if url.is_empty() {
+        return Ok(());
+    }
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| anyhow::anyhow!("invalid KEYSPACES_PROXY_URL {url:?} (expected scheme://host:port)"))?;
+    if !matches!(scheme, "socks5" | "socks5h" | "http" | "https") {
+        anyhow::bail!("invalid KEYSPACES_PROXY_URL {url:?} (scheme must be socks5, socks5h, http or https)");
+    }
+    let authority = rest.trim_end_matches('/');
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, hp)| hp);
+    let valid = host_port
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+    if !valid {
+        anyhow::bail!("invalid KEYSPACES_PROXY_URL {url:?} (expected scheme://host:port)");
+    }
+    anyhow::bail!(
+        "KEYSPACES_PROXY_URL is set, but the Cassandra driver cannot tunnel connections through a proxy; unset it to connect directly (see README, Networking)"
+    )
+}
+
+// Attempts `build_session` up to `retries + 1` times, doubling the delay in between
+async fn build_session_with_retry(retries: u32, delay: Duration) -> anyhow::Result<Session> {
+    let mut delay = delay;
+    let mut attempt = 1;
+    loop {
+        match build_session().await {
+            Ok(session) => {
+                return Ok(session);
+            }
+            Err(e) if attempt <= retries => {
+                warn!(
+                    "session build attempt {}/{} failed: {}; retrying in {:?}",
+                    attempt,
+                    retries + 1,
+                    e,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_STARTUP_RETRY_DELAY);
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+}
+
+// START_WITHOUT_DB: keeps trying in the background until Keyspaces is reachable
+async fn recover_session(delay: Duration) {
+    let mut delay = delay;
+    loop {
+        tokio::time::sleep(delay).await;
+        match build_session().await {
+            Ok(session) => {
+                info!("session established after degraded start");
+                match build_read_session().await {
+                    Ok(Some(read)) => {
+                        READ_SESSION.set(Arc::new(read));
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("read session build failed, using the primary session: {}", e),
+                }
+                SESSION.set(Arc::new(session));
+                return;
+            }
+            Err(e) => {
+                warn!("session build failed: {}; retrying in {:?}", e, delay);
+                delay = (delay * 2).min(MAX_STARTUP_RETRY_DELAY);
+            }
+        }
+    }
+}
+
+// Keeps pooled connections from idling out behind AWS's idle timeout, so the first query
+// after a quiet period does not hit a dead connection. Ticks before a degraded start has a
+// session are skipped.
+async fn keepalive(period: Duration) {
+    let mut ticker = tokio::time::interval(period);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let sessions = [("primary", SESSION.get()), ("read", READ_SESSION.get())];
+        for (role, session) in sessions {
+            let Some(session) = session else {
+                continue;
+            };
+            let result = session.query_unpaged("SELECT release_version FROM system.local", &[]).await;
+            record_query(result.is_ok());
+            if let Err(e) = result {
+                warn!("{} session keepalive failed: {}", role, e);
+            }
+        }
+    }
+}
+
+// Every failed getparams request counts, client errors included, so the ratio agrees with
+// keyspaces_plugin_errors_total over keyspaces_plugin_requests_total
+async fn report_error_budget(period: Duration) {
+    let Some(budget) = BUDGET.get() else {
+        return;
+    };
+    let mut ticker = tokio::time::interval(period);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let outcomes = budget.outcomes();
+        METRICS.set_gauge(
+            "keyspaces_plugin_error_budget_requests",
+            "getparams requests in the error budget window",
+            &[],
+            outcomes.total() as f64
+        );
+        let Some(ratio) = outcomes.error_ratio() else {
+            debug!("error budget: no getparams requests in the last {:?}", budget.window());
+            continue;
+        };
+        METRICS.set_gauge(
+            "keyspaces_plugin_error_budget_ratio",
+            "Failed share of getparams requests in the error budget window",
+            &[],
+            ratio
+        );
+        if ratio > budget.threshold() {
+            warn!(
+                "error budget exceeded: {} of {} getparams requests failed in the last {:?} ({:.1}%, threshold {:.1}%); check keyspaces_plugin_errors_total by status, and consider a longer requeueAfterSeconds on the generator",
+                outcomes.failed,
+                outcomes.total(),
+                budget.window(),
+                ratio * 100.0,
+                budget.threshold() * 100.0
+            );
+        } else {
+            info!(
+                "error budget: {} of {} getparams requests succeeded in the last {:?} ({:.1}% failed)",
+                outcomes.succeeded,
+                outcomes.total(),
+                budget.window(),
+                ratio * 100.0
+            );
+        }
+    }
+}
+
+// Primary session: health checks always, generator queries unless a read session exists
 async fn build_session() -> anyhow::Result<Session> {
     let region = env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
-    let node = format!("cassandra.{}.amazonaws.com:9142", region);
+    let port = CONFIG.get().map(|c| c.port).unwrap_or(DEFAULT_KEYSPACES_PORT);
+    let node = node_address(&format!("cassandra.{region}.amazonaws.com"), port);
 
-    // Load Starfield CA (unchanged)
-    let cert_path = env
-        ::var("KEYSPACES_ROOT_CERT")
-        .unwrap_or_else(|_| "/certs/sf-class2-root.crt".to_string());
-    let mut store = RootCertStore::empty();
-    let mut rd = BufReader::new(File::open(cert_path)?);
-    let certs: Vec<CertificateDer<'static>> = rustls_pemfile
-        ::certs(&mut rd)
-        .collect::<Result<_, _>>()?;
-    store.add_parsable_certificates(certs);
+    // Service-specific creds
+    let user = credential("KEYSPACES_USERNAME")?.ok_or_else(||
+        anyhow::anyhow!("missing env KEYSPACES_USERNAME")
+    )?;
+    let pass = credential("KEYSPACES_PASSWORD")?.ok_or_else(||
+        anyhow::anyhow!("missing env KEYSPACES_PASSWORD")
+    )?;
 
-    // ⬇️ wrap in Arc
-    let tls = Arc::new(ClientConfig::builder().with_root_certificates(store).with_no_client_auth());
+    connect("primary", node, &user, &pass).await
+}
 
-    // Service-specific creds
-    let user = env
-        ::var("KEYSPACES_USERNAME")
-        .map_err(|_| anyhow::anyhow!("missing env KEYSPACES_USERNAME"))?;
-    let pass = env
-        ::var("KEYSPACES_PASSWORD")
-        .map_err(|_| anyhow::anyhow!("missing env KEYSPACES_PASSWORD"))?;
-
-    let session = SessionBuilder::new()
-        .known_node(node)
-        .tls_context(Some(tls)) // now satisfies Into<TlsContext>
-        .user(&user, &pass)
-        .build().await?;
+// Optional dedicated session for generator reads, isolating their cost from the primary
+// endpoint. Credentials default to the primary ones.
+async fn build_read_session() -> anyhow::Result<Option<Session>> {
+    let Ok(endpoint) = env::var("KEYSPACES_READ_ENDPOINT") else {
+        return Ok(None);
+    };
+    let port = CONFIG.get().map(|c| c.port).unwrap_or(DEFAULT_KEYSPACES_PORT);
+    let node = node_address(&endpoint, port);
 
-    Ok(session)
+    let user = match credential("KEYSPACES_READ_USERNAME")? {
+        Some(user) => user,
+        None =>
+            credential("KEYSPACES_USERNAME")?.ok_or_else(||
+                anyhow::anyhow!("missing env KEYSPACES_READ_USERNAME/KEYSPACES_USERNAME")
+            )?,
+    };
+    let pass = match credential("KEYSPACES_READ_PASSWORD")? {
+        Some(pass) => pass,
+        None =>
+            credential("KEYSPACES_PASSWORD")?.ok_or_else(||
+                anyhow::anyhow!("missing env KEYSPACES_READ_PASSWORD/KEYSPACES_PASSWORD")
+            )?,
+    };
+
+    connect("read", node, &user, &pass).await.map(Some)
 }
 
-async fn handler(
-    State(()): State<()>,
-    headers: HeaderMap,
-    Json(body): Json<PluginInput>
-) -> Result<Json<PluginResponse>, AppError> {
-    // Bearer check
-    let Some(authz) = headers.get(axum::http::header::AUTHORIZATION) else {
-        return Err(AppError::Unauthorized);
-    };
-    let authz = authz.to_str().unwrap_or_default();
-    let expected = format!("Bearer {}", TOKEN.get().cloned().unwrap_or_default());
-    if authz != expected {
-        return Err(AppError::Unauthorized);
-    }
-
-    // Optional filters to trim the result set (e.g., by label)
-    let filter_label_key = body.input.parameters
-        .get("filterLabelKey")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
-    let filter_label_val = body.input.parameters
-        .get("filterLabelValue")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
+// `host:port` for the driver; an endpoint that already names a port keeps it
+fn node_address(endpoint: &str, port: u16) -> String {
+    let has_port = endpoint
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+    if has_port { endpoint.to_string() } else { format!("{endpoint}:{port}") }
+}
 
-    // Query Keyspaces
-    let session = SESSION.get().cloned().expect("session not initialized");
+const CREDENTIAL_VARS: [&str; 4] = [
+    "KEYSPACES_USERNAME",
+    "KEYSPACES_PASSWORD",
+    "KEYSPACES_READ_USERNAME",
+    "KEYSPACES_READ_PASSWORD",
+];
 
-    let query =
-        r#"
-        SELECT tenant_id, namespace, target_cluster, repo_url, repo_path, labels, params
-        FROM tenant_ops.tenant_configs
-        WHERE enabled = true ALLOW FILTERING
-    "#;
+// `NAME_FILE` (a mounted Secret key, trimmed) wins over `NAME`, so rotated credentials can
+// be picked up without a restart
+fn credential(name: &str) -> anyhow::Result<Option<String>> {
+    match env::var(format!("{name}_FILE")) {
+        Ok(path) if !path.is_empty() =>
+            fs
+                ::read_to_string(&path)
+                .map(|s| Some(s.trim().to_string()))
+                .map_err(|e| anyhow::anyhow!("failed to read {name}_FILE {path}: {e}")),
+        _ => Ok(env::var(name).ok()),
+    }
+}
 
-    // consider using Iterator API `query_iter`
-    let qr = session
-        .query_unpaged(
-            r#"
-                SELECT tenant_id, namespace, target_cluster, repo_url, repo_path, labels, params
-                FROM tenant_ops.tenant_configs
-                WHERE enabled = true ALLOW FILTERING
-            "#,
-            ()
-        ).await
-        .map_err(|e| AppError::Internal(e.to_string()))?;
+fn credential_files() -> Vec<String> {
+    CREDENTIAL_VARS.iter()
+        .filter_map(|name| env::var(format!("{name}_FILE")).ok())
+        .filter(|path| !path.is_empty())
+        .collect()
+}
 
-    // Turn the QueryResult into a rows parser and iterate typed rows
-    let rows_result = qr.into_rows_result().map_err(|e| AppError::Internal(e.to_string()))?;
+async fn connect(role: &str, node: String, user: &str, pass: &str) -> anyhow::Result<Session> {
+    let region = env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+    info!("{} session: connecting to {}", role, node);
 
-    let mut out: Vec<HashMap<String, serde_json::Value>> = Vec::new();
+    // Keyspaces names its single datacenter after the region, so pin to it until the
+    // topology says otherwise
+    let configured_dc = env::var("KEYSPACES_LOCAL_DC").unwrap_or_else(|_| region.clone());
+    let mut profile = dc_profile(&configured_dc).into_handle();
 
-    for row in rows_result
-        .rows::<
-            (
-                String, // tenant_id
-                String, // namespace
-                String, // target_cluster
-                String, // repo_url
-                String, // repo_path
-                Option<HashMap<String, String>>, // labels
-                Option<HashMap<String, String>>, // params
-            )
-        >()
-        .map_err(|e| AppError::Internal(e.to_string()))? {
-        let (tenant_id, namespace, target_cluster, repo_url, repo_path, labels_opt, params_opt) =
-            row.map_err(|e| AppError::Internal(e.to_string()))?;
+    // Local development against self-signed Cassandra only; needs a second switch so a
+    // single stray env var can't disable verification in production
+    let skip_verify = env_flag("KEYSPACES_INSECURE_SKIP_VERIFY");
+    if skip_verify && !env_flag("ALLOW_INSECURE") {
+        anyhow::bail!("KEYSPACES_INSECURE_SKIP_VERIFY=true requires ALLOW_INSECURE=true");
+    }
 
-        // optional label filter (unchanged)
-        if
-            let (Some(k), Some(v)) = (
-                body.input.parameters.get("filterLabelKey").and_then(|x| x.as_str()),
-                body.input.parameters.get("filterLabelValue").and_then(|x| x.as_str()),
-            )
-        {
-            let pass = labels_opt
-                .as_ref()
-                .and_then(|m| m.get(k))
-                .map(|val| val == v)
-                .unwrap_or(false);
-            if !pass {
-                continue;
+    let tls = if skip_verify {
+        warn!(
+            "!!! TLS CERTIFICATE VERIFICATION IS DISABLED (KEYSPACES_INSECURE_SKIP_VERIFY) - never use this outside local development !!!"
+        );
+        let provider = CryptoProvider::get_default()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()));
+        Arc::new(
+            ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+                .with_no_client_auth()
+        )
+    } else {
+        // Load Starfield CA (unchanged)
+        let store = load_root_certs(&root_cert_path())?;
+
+        // ⬇️ wrap in Arc
+        Arc::new(ClientConfig::builder().with_root_certificates(store).with_no_client_auth())
+    };
+
+    // Protocol-level compression; CQL v4 only defines lz4 and snappy
+    let compression = match
+        env::var("KEYSPACES_COMPRESSION").unwrap_or_else(|_| "none".to_string()).to_lowercase().as_str()
+    {
+        "none" | "" => None,
+        "lz4" => Some(Compression::Lz4),
+        "snappy" => Some(Compression::Snappy),
+        other =>
+            anyhow::bail!("invalid KEYSPACES_COMPRESSION {other:?} (expected none, lz4 or snappy)"),
+    };
+    info!("cql compression: {}", compression.map(|c| c.as_str()).unwrap_or("none"));
+
+    // The driver's connect timeout only covers TCP, so a hung TLS handshake during a regional
+    // event would block the build forever; bound the whole build as well
+    let handshake_timeout = match env::var("KEYSPACES_HANDSHAKE_TIMEOUT_MS") {
+        Ok(v) =>
+            match v.parse::<u64>() {
+                Ok(ms) if ms > 0 => Duration::from_millis(ms),
+                _ =>
+                    anyhow::bail!(
+                        "invalid KEYSPACES_HANDSHAKE_TIMEOUT_MS {v:?} (expected a positive number of milliseconds)"
+                    ),
+            }
+        Err(_) => Duration::from_millis(DEFAULT_HANDSHAKE_TIMEOUT_MS),
+    };
+
+    let builder = SessionBuilder::new()
+        .known_node(&node)
+        .compression(compression)
+        .tls_context(Some(tls)) // now satisfies Into<TlsContext>
+        .user(user, pass)
+        .connection_timeout(handshake_timeout)
+        .default_execution_profile_handle(profile.clone());
+    let session = match tokio::time::timeout(handshake_timeout, builder.build()).await {
+        Ok(session) => session?,
+        Err(_) => {
+            METRICS.inc_counter(
+                "keyspaces_plugin_session_connect_timeouts_total",
+                "Session builds that hit KEYSPACES_HANDSHAKE_TIMEOUT_MS",
+                &[("role", role)]
+            );
+            anyhow::bail!(
+                "{role} session: connecting to {node} timed out after {handshake_timeout:?} (TLS handshake or CQL startup did not complete)"
+            );
+        }
+    };
+
+    let local_dc = match discover_local_dc(&session).await {
+        Ok(dc) if dc == configured_dc => dc,
+        Ok(dc) => {
+            if env::var("KEYSPACES_LOCAL_DC").is_ok() {
+                warn!(
+                    "{} session: KEYSPACES_LOCAL_DC={} but the node reports {}, using {}",
+                    role,
+                    configured_dc,
+                    dc,
+                    dc
+                );
             }
+            profile.map_to_another_profile(dc_profile(&dc));
+            dc
+        }
+        Err(e) => {
+            warn!("{} session: local datacenter discovery failed ({}), using {}", role, e, configured_dc);
+            configured_dc
         }
+    };
+    info!(
+        "{} session: load balancing token-aware, dc-aware (local_dc={}, dc_failover=false)",
+        role,
+        local_dc
+    );
+    if role == "primary" {
+        LOCAL_DC.set(local_dc).ok();
+    }
+
+    Ok(session)
+}
+
+fn root_cert_path() -> String {
+    env::var("KEYSPACES_ROOT_CERT").unwrap_or_else(|_| "/certs/sf-class2-root.crt".to_string())
+}
+
+// A file with no usable certificate is an error rather than an empty store, so a truncated
+// PEM fails here instead of at the first handshake
+fn load_root_certs(path: &str) -> anyhow::Result<RootCertStore> {
+    let mut store = RootCertStore::empty();
+    let mut rd = BufReader::new(File::open(path)?);
+    let certs: Vec<CertificateDer<'static>> = rustls_pemfile
+        ::certs(&mut rd)
+        .collect::<Result<_, _>>()?;
+    let (added, _) = store.add_parsable_certificates(certs);
+    if added == 0 {
+        anyhow::bail!("no usable certificate in {path}");
+    }
+    Ok(store)
+}
 
-        let mut map = HashMap::new();
-        map.insert("tenantId".into(), tenant_id.into());
-        map.insert("namespace".into(), namespace.into());
-        map.insert("cluster".into(), target_cluster.into());
-        map.insert("repoURL".into(), repo_url.into());
-        map.insert("path".into(), repo_path.into());
+// One token per line; surrounding whitespace and blank lines are ignored
+fn parse_tokens(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// A token file that is unreadable or empty mid-rotation keeps the current token
+async fn watch_token(path: String, mut changes: FileChanges) {
+    loop {
+        let trigger = changes.next().await;
+        match fs::read_to_string(&path).map(|s| parse_tokens(&s)) {
+            Ok(tokens) if tokens.is_empty() => {
+                warn!("plugin token {} is empty; keeping the current token", path);
+            }
+            Ok(tokens) => {
+                if *TOKENS.read().unwrap() != tokens {
+                    info!("plugin token reloaded from {} ({}, {} tokens)", path, trigger, tokens.len());
+                    *TOKENS.write().unwrap() = tokens;
+                }
+            }
+            Err(e) => warn!("plugin token {} not readable: {}; keeping the current token", path, e),
+        }
+    }
+}
 
-        if let Some(labels) = labels_opt {
-            map.insert("labels".into(), serde_json::to_value(labels).unwrap());
+// Watches the root CA and any `*_FILE` credentials and, once a changed set is usable,
+// rebuilds both sessions through the normal build path. A half-written or broken CA keeps
+// the current sessions and is retried on the next wake-up, as is a rebuild that fails.
+async fn watch_session_files(root_cert: Option<String>, files: Vec<String>, mut changes: FileChanges) {
+    use sha2::Digest;
+    let digest = |files: &[String]| {
+        let mut hasher = sha2::Sha256::new();
+        for file in files {
+            hasher.update(std::fs::read(file).ok()?);
+        }
+        Some(hasher.finalize())
+    };
+    let mut applied = digest(&files);
+    loop {
+        let trigger = changes.next().await;
+        let current = digest(&files);
+        if current.is_none() || current == applied {
+            continue;
+        }
+        if let Some(Err(e)) = root_cert.as_deref().map(load_root_certs) {
+            warn!(
+                "session files changed but the root CA is not usable yet: {}; keeping the current sessions",
+                e
+            );
+            continue;
         }
-        if let Some(params) = params_opt {
-            for (k, v) in &params {
-                map.insert(k.clone(), serde_json::Value::String(v.clone()));
+        // Degraded start: recover_session reads the new file when it connects
+        if SESSION.get().is_none() {
+            applied = current;
+            continue;
+        }
+        let rebuilt = match build_session().await {
+            Ok(primary) => build_read_session().await.map(|read| (primary, read)),
+            Err(e) => Err(e),
+        };
+        match rebuilt {
+            Ok((primary, read)) => {
+                SESSION.set(Arc::new(primary));
+                if let Some(read) = read {
+                    READ_SESSION.set(Arc::new(read));
+                }
+                PREPARED.lock().unwrap().clear();
+                invalidate_responses("sessions rebuilt");
+                info!("session files reloaded ({}); sessions rebuilt", trigger);
+                applied = current;
             }
-            map.insert("params".into(), serde_json::to_value(params).unwrap());
+            Err(e) => warn!("session files changed but the session rebuild failed: {}; retrying", e),
+        }
+    }
+}
+
+fn dc_profile(local_dc: &str) -> ExecutionProfile {
+    let policy = DefaultPolicy::builder()
+        .prefer_datacenter(local_dc.to_string())
+        .token_aware(true)
+        .permit_dc_failover(false)
+        .build();
+    ExecutionProfile::builder().load_balancing_policy(policy).build()
+}
+
+// The contact node's own `system.local` row, falling back to any peer's
+async fn discover_local_dc(session: &Session) -> anyhow::Result<String> {
+    for cql in ["SELECT data_center FROM system.local", "SELECT data_center FROM system.peers"] {
+        let rows = session.query_unpaged(cql, &[]).await?.into_rows_result()?;
+        let dc = rows
+            .rows::<(Option<String>,)>()?
+            .filter_map(|row| row.ok().and_then(|(dc,)| dc))
+            .find(|dc| !dc.is_empty());
+        if let Some(dc) = dc {
+            return Ok(dc);
         }
+    }
+    anyhow::bail!("no data_center in system.local or system.peers")
+}
+
+const TENANT_COLUMNS: [&str; 7] = [
+    "tenant_id",
+    "namespace",
+    "target_cluster",
+    "repo_url",
+    "repo_path",
+    "labels",
+    "params",
+];
 
-        out.push(map);
+// Name of a tenant column in the relation being scanned; identity outside VIEW_NAME
+fn source_column(logical: &'static str) -> &'static str {
+    let config = CONFIG.get().expect("config not initialized");
+    config.view_columns
+        .get(logical)
+        .map(|c| c.as_str())
+        .unwrap_or(logical)
+}
+
+// Single-line so the debug plan can echo it verbatim. With `clusters` the scan becomes a
+// partition lookup on target_cluster, bound as one list value; a hinted label filter
+// becomes a lookup on the hint's column.
+fn tenant_query(table: &str, opts: &RequestOptions) -> String {
+    let config = CONFIG.get().expect("config not initialized");
+    let mut columns: Vec<&str> = TENANT_COLUMNS.iter()
+        .map(|c| source_column(c))
+        .collect();
+    columns.extend(config.dest_namespace_column.as_deref());
+    columns.extend(config.sync_wave_column.as_deref());
+    columns.extend(config.annotations_column.as_deref());
+    columns.extend(config.project_column.as_deref());
+    columns.extend(config.promoted_columns.iter().map(|c| c.as_str()));
+    columns.extend(opts.extra_columns.iter().map(|c| c.as_str()));
+    let write_time = config.write_time_column
+        .as_ref()
+        .filter(|_| opts.include_write_time)
+        .map(|c| format!("WRITETIME({c})"));
+    columns.extend(write_time.as_deref());
+    let mut conditions: Vec<String> = Vec::new();
+    match pushdown(opts) {
+        _ if !opts.clusters.is_empty() => conditions.push(format!("{} IN ?", source_column("target_cluster"))),
+        Some(hint) => conditions.push(format!("{} = ?", hint.column)),
+        None => {}
     }
+    // `includeDisabledForClusters` reads every row and filters by `enabled` after the scan
+    let enabled = source_column("enabled");
+    if opts.include_disabled_for_clusters.is_empty() {
+        conditions.push(format!("{enabled} = true"));
+    } else {
+        columns.push(enabled);
+    }
+    let restriction = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
+    };
+    format!("SELECT {} FROM {}.{}{} ALLOW FILTERING", columns.join(", "), config.keyspace, table, restriction)
+}
+
+// Paging statistics for one scan; only surfaced by the debug plan endpoint
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScanStats {
+    page_size: i32,
+    pages: u32,
+    rows_scanned: u64,
+    // Read, so counted in `rows_scanned`, but dropped by `includeDisabledForClusters`
+    rows_disabled: u64,
+    rows_returned: u64,
+    // Tenants whose output construction failed; a response only has any with
+    // `tenantErrorMode=skip`, which dropped them
+    failed_tenants: u64,
+    throttle_events: u32,
+    prepared_cache_hits: u32,
+    prepared_cache_misses: u32,
+    // MAX_PAGES stopped the scan before the tables were exhausted
+    truncated: bool,
+    // A page read failed and `partialPageMode=returnPartial` kept the rows before it
+    partial: bool,
+    // Answered from a saved response; when it was saved
+    #[serde(skip)]
+    snapshot_saved_at: Option<String>,
+    #[serde(skip)]
+    maintenance: bool,
+    // Answered from RESPONSE_CACHE_TTL_SECS without a scan
+    #[serde(skip)]
+    response_cached: bool,
+    // CQL of each table scan that ran, with `?` where values are bound
+    #[serde(skip)]
+    queries: Vec<String>,
+    #[serde(skip)]
+    decode_time: Duration,
+}
 
-    Ok(Json(PluginResponse { output: Output { parameters: out } }))
+#[derive(Debug, Clone, Copy)]
+enum ParamKind {
+    String,
+    // `true`/`false` or their string forms
+    Flag,
+    // JSON array of strings or a comma-separated string
+    List,
+    // One of `ParamSpec::allowed`
+    Enum,
+    // JSON object whose values match `ParamSpec::allowed` (or a list of them); also
+    // accepted as a JSON-encoded string
+    Map,
+}
+
+struct ParamSpec {
+    name: &'static str,
+    kind: ParamKind,
+    allowed: &'static [&'static str],
+    description: &'static str,
+}
+
+// Every generator input parameter the plugin understands. `/openapi.json` is generated from
+// this list, so a new parameter belongs here as well as in `RequestOptions::from_parameters`.
+const REQUEST_PARAMETERS: &[ParamSpec] = &[
+    ParamSpec {
+        name: "filterLabelKey",
+        kind: ParamKind::String,
+        allowed: &[],
+        description: "Label key to filter on; requires filterLabelValue",
+    },
+    ParamSpec {
+        name: "filterLabelValue",
+        kind: ParamKind::String,
+        allowed: &[],
+        description: "Keep only tenants whose filterLabelKey label matches this value",
+    },
+    ParamSpec {
+        name: "excludeLabelValue",
+        kind: ParamKind::String,
+        allowed: &[],
+        description: "Drop tenants whose filterLabelKey label matches this value; wins over filterLabelValue",
+    },
+    ParamSpec {
+        name: "filterMode",
+        kind: ParamKind::Enum,
+        allowed: &FILTER_MODES,
+        description: "How filterLabelValue and excludeLabelValue are compared: exact equality, or regex matching the whole value. ALLOWED_FILTER_MODES can narrow the accepted values",
+    },
+    ParamSpec {
+        name: "includeTenantIds",
+        kind: ParamKind::List,
+        allowed: &[],
+        description: "Keep only these tenant ids; empty keeps everyone",
+    },
+    ParamSpec {
+        name: "excludeTenantIds",
+        kind: ParamKind::List,
+        allowed: &[],
+        description: "Drop these tenant ids; wins over includeTenantIds",
+    },
+    ParamSpec {
+        name: "clusters",
+        kind: ParamKind::List,
+        allowed: &[],
+        description: "Look up these target clusters with a partition-key IN query instead of a full scan",
+    },
+    ParamSpec {
+        name: "includeDisabledForClusters",
+        kind: ParamKind::List,
+        allowed: &[],
+        description: "Keep disabled tenants whose target cluster is listed; other disabled tenants are still excluded",
+    },
+    ParamSpec {
+        name: "extraColumns",
+        kind: ParamKind::List,
+        allowed: &[],
+        description: "Additional columns to select and emit under their own names, typed",
+    },
+    ParamSpec {
+        name: "outputShape",
+        kind: ParamKind::Enum,
+        allowed: &["flat", "nestedByTenant", "clusters"],
+        description: "flat: one map per tenant; nestedByTenant: one map keyed by tenant id; clusters: one map per target cluster",
+    },
+    ParamSpec {
+        name: "emitEmptyMaps",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Emit labels: {} and params: {} for null map columns instead of omitting them",
+    },
+    ParamSpec {
+        name: "includeParamsObject",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Default true; false drops the nested params object and keeps only the expanded keys",
+    },
+    ParamSpec {
+        name: "keyCase",
+        kind: ParamKind::Enum,
+        allowed: &["asIs", "camel", "snake"],
+        description: "Case applied to label and param keys in the output",
+    },
+    ParamSpec {
+        name: "timestampFormat",
+        kind: ParamKind::Enum,
+        allowed: &["rfc3339", "epochMillis", "date"],
+        description: "Format of timestamp column values, in UTC",
+    },
+    ParamSpec {
+        name: "oversizeMode",
+        kind: ParamKind::Enum,
+        allowed: &["truncate", "skip"],
+        description: "Tenants over MAX_LABELS_PER_TENANT/MAX_PARAMS_PER_TENANT: truncate keeps the first keys in sorted order, skip drops the tenant",
+    },
+    ParamSpec {
+        name: "partialPageMode",
+        kind: ParamKind::Enum,
+        allowed: &["fail", "returnPartial"],
+        description: "A page read failing mid-scan: fail the request, or return the rows read so far with X-Partial: true",
+    },
+    ParamSpec {
+        name: "consistency",
+        kind: ParamKind::Enum,
+        allowed: &READ_CONSISTENCIES,
+        description: "Read consistency for this request, overriding KEYSPACES_CONSISTENCY",
+    },
+    ParamSpec {
+        name: "transforms",
+        kind: ParamKind::Map,
+        allowed: &["lower", "upper", "trim", "default:<value>"],
+        description: "Per-output-key transforms applied in order, e.g. {\"namespace\": [\"trim\", \"lower\"]}",
+    },
+    ParamSpec {
+        name: "includeGeneratedAt",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Add a generatedAt RFC3339 timestamp to every entry; makes every response differ",
+    },
+    ParamSpec {
+        name: "includeMeta",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Add a top-level meta object with the effective filters, row counts and cache status",
+    },
+    ParamSpec {
+        name: "emptyWarning",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Add meta.warning when no tenant matched; the status stays 200",
+    },
+    ParamSpec {
+        name: "preview",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Onboarding: return at most previewLimit tenants, each tagged _preview: true",
+    },
+    ParamSpec {
+        name: "previewLimit",
+        kind: ParamKind::String,
+        allowed: &[],
+        description: "Tenants returned in preview mode (default 5)",
+    },
+    ParamSpec {
+        name: "delta",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Advisory: return only tenants changed since sinceEtag plus delta.removed; not for ApplicationSets",
+    },
+    ParamSpec {
+        name: "sinceEtag",
+        kind: ParamKind::String,
+        allowed: &[],
+        description: "delta.snapshot from a previous delta response",
+    },
+    ParamSpec {
+        name: "rawRow",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Debug: add the untransformed columns under _raw",
+    },
+    ParamSpec {
+        name: "pageSize",
+        kind: ParamKind::String,
+        allowed: &[],
+        description: "Rows per Keyspaces page for this request, overriding KEYSPACES_PAGE_SIZE; clamped to MAX_PAGE_SIZE",
+    },
+    ParamSpec {
+        name: "trimStrings",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Trim leading and trailing whitespace from every output string value",
+    },
+    ParamSpec {
+        name: "escapeTemplateChars",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Escape {{ and }} in output strings as Go template literals",
+    },
+    ParamSpec {
+        name: "rejectTemplateChars",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Drop tenants with {{ or }} in any output string, with a warning",
+    },
+    ParamSpec {
+        name: "includeWriteTime",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Add _writeTimeMicros, the WRITETIME of WRITETIME_COLUMN; requires WRITETIME_COLUMN",
+    },
+    ParamSpec {
+        name: "includeAppName",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Add appName, <tenantId>-<cluster> as an RFC 1123 label",
+    },
+    ParamSpec {
+        name: "missingFieldMode",
+        kind: ParamKind::Enum,
+        allowed: &["allow", "skip", "fail"],
+        description: "Tenants with an empty REQUIRED_FIELDS value: allow keeps them, skip drops them, fail rejects the request with 422",
+    },
+    ParamSpec {
+        name: "tenantErrorMode",
+        kind: ParamKind::Enum,
+        allowed: &["fail", "skip"],
+        description: "A tenant whose output construction fails: fail rejects the request with 500, skip drops it and reports the count in X-Skipped-Tenants",
+    },
+    ParamSpec {
+        name: "appNameCollisionMode",
+        kind: ParamKind::Enum,
+        allowed: &["suffix", "fail"],
+        description: "Tenant/cluster pairs sharing an appName: suffix adds a hash to each, fail rejects the request with 409",
+    },
+    ParamSpec {
+        name: "includeRegion",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Add _region, the Keyspaces region the rows were read from",
+    },
+    ParamSpec {
+        name: "countOnly",
+        kind: ParamKind::Flag,
+        allowed: &[],
+        description: "Return {output: {count}} instead of the rows; for monitoring, not ApplicationSets",
+    },
+];
+
+impl RequestOptions {
+    fn from_parameters(params: &HashMap<String, serde_json::Value>) -> Result<Self, AppError> {
+        for name in params.keys() {
+            if !REQUEST_PARAMETERS.iter().any(|p| p.name == name) {
+                debug!("ignoring unknown generator parameter {}", name);
+            }
+        }
+
+        let filter_mode = params.get("filterMode").and_then(|v| v.as_str());
+        match filter_mode {
+            None | Some("exact" | "regex") => {}
+            Some(other) => {
+                return Err(
+                    AppError::BadRequest(
+                        format!("filterMode: unsupported value {other:?} (expected exact or regex)")
+                    )
+                );
+            }
+        }
+        // Only a mode that would actually be used is checked: an explicit one, or the default
+        // once a label filter is set
+        let label_filter_set = ["filterLabelKey", "filterLabelValue", "excludeLabelValue"]
+            .iter()
+            .any(|name| params.contains_key(*name));
+        let mode_in_use = filter_mode.or(label_filter_set.then_some("exact"));
+        let disallowed = mode_in_use
+            .zip(CONFIG.get())
+            .filter(|(mode, config)| !config.allowed_filter_modes.iter().any(|m| m == mode));
+        if let Some((mode, config)) = disallowed {
+            return Err(
+                AppError::BadRequest(
+                    format!(
+                        "filterMode: {mode:?} is disabled on this plugin (ALLOWED_FILTER_MODES: {})",
+                        if config.allowed_filter_modes.is_empty() {
+                            "none".to_string()
+                        } else {
+                            config.allowed_filter_modes.join(", ")
+                        }
+                    )
+                )
+            );
+        }
+        // Labels are text, so a non-string filter could never match; say so instead of
+        // silently returning nothing (the classic `filterLabelValue: 3` vs `"3"`)
+        for name in ["filterLabelKey", "filterLabelValue", "excludeLabelValue"] {
+            match params.get(name) {
+                None | Some(serde_json::Value::String(_)) => {}
+                Some(other) => {
+                    return Err(
+                        AppError::BadRequest(
+                            format!(
+                                "{name} must be a string for filterMode {}, got {}; quote it, e.g. \"{}\"",
+                                filter_mode.unwrap_or("exact"),
+                                json_type_name(other),
+                                other
+                            )
+                        )
+                    );
+                }
+            }
+        }
+
+        let key = params.get("filterLabelKey").and_then(|v| v.as_str());
+        let val = params.get("filterLabelValue").and_then(|v| v.as_str());
+        let exclude = params.get("excludeLabelValue").and_then(|v| v.as_str());
+        let filter_label = key.zip(val).map(|(k, v)| (k.to_string(), v.to_string()));
+        let exclude_label = key.zip(exclude).map(|(k, v)| (k.to_string(), v.to_string()));
+        let (filter_regex, exclude_regex) = if filter_mode == Some("regex") {
+            (
+                filter_label
+                    .as_ref()
+                    .map(|(_, v)| label_pattern("filterLabelValue", v))
+                    .transpose()?,
+                exclude_label
+                    .as_ref()
+                    .map(|(_, v)| label_pattern("excludeLabelValue", v))
+                    .transpose()?,
+            )
+        } else {
+            (None, None)
+        };
+
+        let mut clusters: Vec<String> = Vec::new();
+        for cluster in param_list(params, "clusters") {
+            if !clusters.contains(&cluster) {
+                clusters.push(cluster);
+            }
+        }
+        if clusters.len() > MAX_CLUSTERS {
+            return Err(
+                AppError::BadRequest(
+                    format!("clusters: {} values exceed the limit of {MAX_CLUSTERS}", clusters.len())
+                )
+            );
+        }
+
+        let mut extra_columns: Vec<String> = Vec::new();
+        for column in param_list(params, "extraColumns") {
+            if !is_identifier(&column) {
+                return Err(AppError::BadRequest(format!("extraColumns: invalid column {column:?}")));
+            }
+            let column = column.to_lowercase();
+            let promoted = CONFIG.get().is_some_and(|c| {
+                c.promoted_columns.contains(&column) ||
+                    c.dest_namespace_column.as_ref() == Some(&column) ||
+                    c.sync_wave_column.as_ref() == Some(&column) ||
+                    c.annotations_column.as_ref() == Some(&column) ||
+                    c.project_column.as_ref() == Some(&column)
+            });
+            if
+                !TENANT_COLUMNS.contains(&column.as_str()) &&
+                !promoted &&
+                !extra_columns.contains(&column)
+            {
+                extra_columns.push(column);
+            }
+        }
+
+        let output_shape = match params.get("outputShape").and_then(|v| v.as_str()) {
+            None | Some("flat") => OutputShape::Flat,
+            Some("nestedByTenant") => OutputShape::NestedByTenant,
+            Some("clusters") => OutputShape::Clusters,
+            Some(other) => {
+                return Err(
+                    AppError::BadRequest(
+                        format!("outputShape: unsupported value {other:?} (expected flat, nestedByTenant or clusters)")
+                    )
+                );
+            }
+        };
+        // Delta sees only changed tenants, so per-cluster counts would be wrong
+        if output_shape == OutputShape::Clusters && param_flag(params, "delta") {
+            return Err(AppError::BadRequest("outputShape: clusters cannot be combined with delta".to_string()));
+        }
+        let count_only = param_flag(params, "countOnly");
+        if count_only && param_flag(params, "delta") {
+            return Err(AppError::BadRequest("countOnly cannot be combined with delta".to_string()));
+        }
+        // The timestamp is stamped before the delta is taken, so every tenant would change
+        if param_flag(params, "includeGeneratedAt") && param_flag(params, "delta") {
+            return Err(AppError::BadRequest("includeGeneratedAt cannot be combined with delta".to_string()));
+        }
+
+        let oversize_mode = match params.get("oversizeMode").and_then(|v| v.as_str()) {
+            None | Some("truncate") => OversizeMode::Truncate,
+            Some("skip") => OversizeMode::Skip,
+            Some(other) => {
+                return Err(
+                    AppError::BadRequest(
+                        format!("oversizeMode: unsupported value {other:?} (expected truncate or skip)")
+                    )
+                );
+            }
+        };
+
+        let missing_field_mode = match params.get("missingFieldMode").and_then(|v| v.as_str()) {
+            None | Some("allow") => MissingFieldMode::Allow,
+            Some("skip") => MissingFieldMode::Skip,
+            Some("fail") => MissingFieldMode::Fail,
+            Some(other) => {
+                return Err(
+                    AppError::BadRequest(
+                        format!("missingFieldMode: unsupported value {other:?} (expected allow, skip or fail)")
+                    )
+                );
+            }
+        };
+
+        let tenant_error_mode = match params.get("tenantErrorMode").and_then(|v| v.as_str()) {
+            None | Some("fail") => TenantErrorMode::Fail,
+            Some("skip") => TenantErrorMode::Skip,
+            Some(other) => {
+                return Err(
+                    AppError::BadRequest(format!("tenantErrorMode: unsupported value {other:?} (expected fail or skip)"))
+                );
+            }
+        };
+
+        let app_name_collision_mode = match params.get("appNameCollisionMode").and_then(|v| v.as_str()) {
+            None | Some("suffix") => AppNameCollisionMode::Suffix,
+            Some("fail") => AppNameCollisionMode::Fail,
+            Some(other) => {
+                return Err(
+                    AppError::BadRequest(
+                        format!("appNameCollisionMode: unsupported value {other:?} (expected suffix or fail)")
+                    )
+                );
+            }
+        };
+
+        let partial_page_mode = match params.get("partialPageMode").and_then(|v| v.as_str()) {
+            None | Some("fail") => PartialPageMode::Fail,
+            Some("returnPartial") => PartialPageMode::ReturnPartial,
+            Some(other) => {
+                return Err(
+                    AppError::BadRequest(
+                        format!("partialPageMode: unsupported value {other:?} (expected fail or returnPartial)")
+                    )
+                );
+            }
+        };
+
+        let consistency = match params.get("consistency") {
+            None => None,
+            Some(v) =>
+                Some(
+                    v
+                        .as_str()
+                        .and_then(parse_consistency)
+                        .ok_or_else(|| {
+                            AppError::BadRequest(
+                                format!(
+                                    "consistency: unsupported value {v} (expected {})",
+                                    READ_CONSISTENCIES.join(", ")
+                                )
+                            )
+                        })?
+                ),
+        };
+
+        let transforms = parse_transforms(params.get("transforms"))?;
+        let key_case = match params.get("keyCase").and_then(|v| v.as_str()) {
+            None | Some("asIs") => KeyCase::AsIs,
+            Some("camel") => KeyCase::Camel,
+            Some("snake") => KeyCase::Snake,
+            Some(other) => {
+                return Err(
+                    AppError::BadRequest(
+                        format!("keyCase: unsupported value {other:?} (expected asIs, camel or snake)")
+                    )
+                );
+            }
+        };
+
+        let timestamp_format = match params.get("timestampFormat").and_then(|v| v.as_str()) {
+            None | Some("rfc3339") => TimestampFormat::Rfc3339,
+            Some("epochMillis") => TimestampFormat::EpochMillis,
+            Some("date") => TimestampFormat::Date,
+            Some(other) => {
+                return Err(
+                    AppError::BadRequest(
+                        format!("timestampFormat: unsupported value {other:?} (expected rfc3339, epochMillis or date)")
+                    )
+                );
+            }
+        };
+
+        let template_chars = match (param_flag(params, "escapeTemplateChars"), param_flag(params, "rejectTemplateChars")) {
+            (false, false) => TemplateCharsMode::Keep,
+            (true, false) => TemplateCharsMode::Escape,
+            (false, true) => TemplateCharsMode::Reject,
+            (true, true) => {
+                return Err(
+                    AppError::BadRequest(
+                        "escapeTemplateChars and rejectTemplateChars cannot be combined".to_string()
+                    )
+                );
+            }
+        };
+
+        let include_write_time = param_flag(params, "includeWriteTime");
+        if include_write_time && CONFIG.get().is_none_or(|c| c.write_time_column.is_none()) {
+            return Err(
+                AppError::BadRequest("includeWriteTime: needs WRITETIME_COLUMN to be configured".to_string())
+            );
+        }
+
+        let page_size = match params.get("pageSize") {
+            None => None,
+            Some(v) => {
+                let requested = v
+                    .as_u64()
+                    .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| {
+                        AppError::BadRequest(format!("pageSize: expected a positive integer, got {v}"))
+                    })?;
+                let max = CONFIG.get().map_or(i32::MAX, |c| c.max_page_size);
+                let size = requested.min(max as u64) as i32;
+                if u64::try_from(size) != Ok(requested) {
+                    debug!("pageSize {} clamped to MAX_PAGE_SIZE={}", requested, max);
+                }
+                Some(size)
+            }
+        };
+
+        let preview = if param_flag(params, "preview") {
+            let limit = match params.get("previewLimit") {
+                None => DEFAULT_PREVIEW_LIMIT,
+                Some(v) =>
+                    v
+                        .as_u64()
+                        .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| {
+                            AppError::BadRequest(format!("previewLimit: expected a positive integer, got {v}"))
+                        })? as usize,
+            };
+            Some(limit)
+        } else {
+            None
+        };
+
+        Ok(RequestOptions {
+            filter_label,
+            exclude_label,
+            filter_regex,
+            exclude_regex,
+            include_tenant_ids: param_list(params, "includeTenantIds").into_iter().collect(),
+            exclude_tenant_ids: param_list(params, "excludeTenantIds").into_iter().collect(),
+            clusters,
+            include_disabled_for_clusters: param_list(params, "includeDisabledForClusters").into_iter().collect(),
+            extra_columns,
+            output_shape,
+            emit_empty_maps: param_flag(params, "emitEmptyMaps"),
+            // Defaults to true, so only an explicit non-true value turns it off
+            omit_params_object: params.contains_key("includeParamsObject") &&
+            !param_flag(params, "includeParamsObject"),
+            oversize_mode,
+            page_size,
+            trim_strings: param_flag(params, "trimStrings"),
+            template_chars,
+            partial_page_mode,
+            consistency,
+            key_case,
+            timestamp_format,
+            include_generated_at: param_flag(params, "includeGeneratedAt"),
+            include_meta: param_flag(params, "includeMeta"),
+            empty_warning: param_flag(params, "emptyWarning"),
+            transforms,
+            preview,
+            delta: param_flag(params, "delta"),
+            since_snapshot: params
+                .get("sinceEtag")
+                .and_then(|v| v.as_str())
+                .map(|s| s.trim_matches('"').to_string()),
+            raw_row: param_flag(params, "rawRow"),
+            include_write_time,
+            include_app_name: param_flag(params, "includeAppName"),
+            app_name_collision_mode,
+            missing_field_mode,
+            tenant_error_mode,
+            include_region: param_flag(params, "includeRegion"),
+            count_only,
+        })
+    }
+}
+
+// Accepts any server certificate but still checks handshake signatures, so the session is
+// encrypted yet unauthenticated. Only reachable via KEYSPACES_INSECURE_SKIP_VERIFY.
+#[derive(Debug)]
+struct SkipServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+async fn handler(
+    State(()): State<()>,
+    headers: HeaderMap,
+    Json(body): Json<PluginInput>
+) -> Result<Response, AppError> {
+    debug!(
+        "getparams request from applicationSet={}",
+        body.application_set_name.as_deref().unwrap_or("unknown")
+    );
+    // The name is the caller's to choose, so it only becomes a label once the caller is
+    // authenticated; every rejected one shares `unauthenticated`
+    let started = Instant::now();
+    let (application_set, result) = match authorize(&headers) {
+        Ok(caller) => {
+            let timings = PhaseTimings { auth: started.elapsed(), ..Default::default() };
+            let application_set = application_set_label(body.application_set_name.as_deref());
+            (application_set, getparams(&headers, &body, &caller, timings).await)
+        }
+        Err(e) => ("unauthenticated", Err(e)),
+    };
+    METRICS.inc_counter(
+        "keyspaces_plugin_requests_total",
+        "getparams requests by calling ApplicationSet",
+        &[("application_set", application_set)]
+    );
+    if let Some((budget, ok)) = BUDGET.get().zip(budget_outcome(&result)) {
+        budget.record(ok);
+    }
+    if let Err(e) = &result {
+        METRICS.inc_counter(
+            "keyspaces_plugin_errors_total",
+            "Failed getparams requests by calling ApplicationSet and status",
+            &[
+                ("application_set", application_set),
+                ("status", e.status().as_str()),
+            ]
+        );
+    }
+    result
+}
+
+// Whether a getparams outcome counts toward the error budget, and as a success. Only the
+// plugin's own failures do: a rejected token or a bad parameter says nothing about how
+// often the ApplicationSet can poll.
+fn budget_outcome<T>(result: &Result<T, AppError>) -> Option<bool> {
+    match result {
+        Ok(_) => Some(true),
+        Err(e) if e.status().is_server_error() => Some(false),
+        Err(_) => None,
+    }
+}
+
+// Metric label value for an authenticated caller's `applicationSetName`. Anything that is
+// not a plausible Kubernetes object name collapses to `invalid`.
+fn application_set_label(name: Option<&str>) -> &str {
+    match name {
+        None | Some("") => "unknown",
+        Some(name) if
+            name.len() <= 253 &&
+            name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'.')
+        => name,
+        Some(_) => "invalid",
+    }
+}
+
+async fn getparams(
+    headers: &HeaderMap,
+    body: &PluginInput,
+    caller: &Caller,
+    mut timings: PhaseTimings
+) -> Result<Response, AppError> {
+    let started = Instant::now();
+    check_param_allowlist(caller, &body.input.parameters)?;
+    timings.auth += started.elapsed();
+
+    if wants_ndjson(headers) {
+        return stream_ndjson(&body.input.parameters).await;
+    }
+    // ArgoCD would read the missing `parameters` as no tenants and prune every Application
+    if body.application_set_name.is_some() && param_flag(&body.input.parameters, "countOnly") {
+        return Err(
+            AppError::BadRequest(
+                "countOnly: not for ApplicationSets; call without applicationSetName from monitoring tools".to_string()
+            )
+        );
+    }
+    let (resp, stats) = generate(&body.input.parameters, &mut timings, true).await?;
+    let response = json_response(&resp, &stats, headers, &mut timings)?;
+    timings.record();
+    Ok(response)
+}
+
+// Debug twin of `handler` for shell testing: `curl -d filterLabelKey=region -d ...`.
+// Form values become string parameters; the ArgoCD endpoint stays JSON-only.
+async fn debug_getparams_form(
+    State(()): State<()>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>
+) -> Result<Response, AppError> {
+    let mut timings = PhaseTimings::default();
+    let started = Instant::now();
+    let caller = authorize(&headers)?;
+    timings.auth = started.elapsed();
+
+    let parameters = form
+        .into_iter()
+        .map(|(k, v)| (k, serde_json::Value::String(v)))
+        .collect();
+    check_param_allowlist(&caller, &parameters)?;
+    if wants_ndjson(&headers) {
+        return stream_ndjson(&parameters).await;
+    }
+    let (resp, stats) = generate(&parameters, &mut timings, true).await?;
+    let response = json_response(&resp, &stats, &headers, &mut timings)?;
+    timings.record();
+    Ok(response)
+}
+
+// `persist` is off for lookups that are not what ArgoCD was served (`/api/v1/diff`): they
+// neither read nor fill the response cache and never replace a saved response
+async fn generate(
+    parameters: &HashMap<String, serde_json::Value>,
+    timings: &mut PhaseTimings,
+    persist: bool
+) -> Result<(PluginResponse, ScanStats), AppError> {
+    let opts = RequestOptions::from_parameters(parameters)?;
+    warn_preview(&opts);
+    let mut stats = ScanStats::default();
+    if MAINTENANCE.load(Ordering::Relaxed) {
+        let resp = maintenance_response(parameters, &opts, &mut stats)?;
+        return Ok((resp, stats));
+    }
+    let cache_key = response_cache_key(parameters, &opts).filter(|_| persist);
+    if let Some(resp) = cache_key.as_deref().and_then(|key| cached_response(key, &mut stats)) {
+        return Ok((resp, stats));
+    }
+
+    // Query Keyspaces
+    let started = Instant::now();
+    let rows = match tenant_source().fetch(&opts, &mut stats, None).await {
+        Ok(rows) => rows,
+        Err(AppError::Unavailable(reason)) => {
+            let resp = cold_start_response(parameters, &opts, &mut stats, reason)?;
+            return Ok((resp, stats));
+        }
+        Err(e) => {
+            return Err(e);
+        }
+    };
+    timings.parse = stats.decode_time;
+    timings.query = started.elapsed().saturating_sub(stats.decode_time);
+
+    let started = Instant::now();
+    let (mut entries, skipped) = render_tenants_counted(rows, &opts, &render_settings());
+    if opts.tenant_error_mode == TenantErrorMode::Fail {
+        check_render_errors(&entries)?;
+    }
+    count_skipped_tenants(skipped);
+    stats.failed_tenants = skipped as u64;
+    if opts.include_app_name && opts.app_name_collision_mode == AppNameCollisionMode::Fail {
+        check_app_names(&entries)?;
+    }
+    if opts.missing_field_mode == MissingFieldMode::Fail {
+        check_required_fields(&entries)?;
+    }
+    stats.rows_returned = entries.len() as u64;
+    warn_tenant_count(entries.len());
+    if opts.include_generated_at {
+        stamp_generated_at(&mut entries, &generated_at_now());
+    }
+    let delta = if opts.delta {
+        let (changed, delta) = apply_delta(entries, opts.since_snapshot.as_deref());
+        entries = changed;
+        Some(delta)
+    } else {
+        None
+    };
+    let out = shape_output(entries, opts.output_shape);
+    timings.filter = started.elapsed();
+    let mut meta = opts.include_meta.then(|| response_meta(&opts, &stats));
+    // Counted before delta, so an unchanged delta is not mistaken for an empty result
+    if opts.empty_warning && stats.rows_returned == 0 {
+        meta.get_or_insert_with(|| serde_json::json!({}))["warning"] = "no tenants matched".into();
+    }
+    if opts.count_only {
+        let output = Output { parameters: Vec::new(), count: Some(stats.rows_returned as usize) };
+        let resp = PluginResponse { output, meta, delta: None };
+        cache_response(cache_key, &stats, &resp);
+        return Ok((resp, stats));
+    }
+    let resp = PluginResponse { output: Output { parameters: out, count: None }, meta, delta };
+    if persist {
+        save_snapshot(parameters, &opts, &stats, &resp);
+    }
+    cache_response(cache_key, &stats, &resp);
+    Ok((resp, stats))
+}
+
+// None when the response cache is off or the response depends on more than the parameters:
+// a delta on the caller's previous call, `includeGeneratedAt` on the clock
+fn response_cache_key(parameters: &HashMap<String, serde_json::Value>, opts: &RequestOptions) -> Option<String> {
+    RESPONSES.get()?;
+    (!opts.delta && !opts.include_generated_at).then(|| snapshot_key(parameters))
+}
+
+// Short and stable, so per-key series stay readable; logged with the full key on insert
+fn response_cache_label(key: &str) -> String {
+    use sha2::Digest;
+    hex::encode(&sha2::Sha256::digest(key.as_bytes())[..6])
+}
+
+fn cached_response(key: &str, stats: &mut ScanStats) -> Option<PluginResponse> {
+    let cache = RESPONSES.get()?;
+    let label = response_cache_label(key);
+    let Some((resp, rows_returned)) = cache.get(key) else {
+        // The lookup may have dropped an expired entry
+        report_response_cache(cache);
+        METRICS.inc_counter(
+            "keyspaces_plugin_response_cache_misses_total",
+            "getparams requests computed because RESPONSE_CACHE_TTL_SECS had no fresh response",
+            &[("key", &label)]
+        );
+        return None;
+    };
+    METRICS.inc_counter(
+        "keyspaces_plugin_response_cache_hits_total",
+        "getparams requests answered from RESPONSE_CACHE_TTL_SECS without a scan",
+        &[("key", &label)]
+    );
+    stats.rows_returned = rows_returned;
+    stats.response_cached = true;
+    Some(resp)
+}
+
+// Complete responses only, as for saved responses
+fn cache_response(key: Option<String>, stats: &ScanStats, resp: &PluginResponse) {
+    let (Some(cache), Some(key)) = (RESPONSES.get(), key) else {
+        return;
+    };
+    if stats.partial || stats.truncated || stats.failed_tenants > 0 {
+        return;
+    }
+    let Ok(bytes) = serde_json::to_vec(resp).map(|b| b.len()) else {
+        return;
+    };
+    debug!("response cache: storing {} as key {} ({} bytes)", key, response_cache_label(&key), bytes);
+    cache.insert(key, resp.clone(), stats.rows_returned, bytes);
+    report_response_cache(cache);
+}
+
+fn invalidate_responses(reason: &str) {
+    if let Some(cache) = RESPONSES.get() {
+        let dropped = cache.invalidate();
+        if dropped > 0 {
+            info!("response cache: dropped {} responses ({})", dropped, reason);
+        }
+        report_response_cache(cache);
+    }
+}
+
+// Also drops the per-key hit/miss series of keys the cache no longer holds, so they stay
+// bounded by RESPONSE_CACHE_MAX_ENTRIES (plus misses still being computed) instead of
+// growing with every parameter set ever seen
+fn report_response_cache(cache: &ResponseCache) {
+    let held: HashSet<String> = cache
+        .keys()
+        .iter()
+        .map(|key| render_labels(&[("key", &response_cache_label(key))]))
+        .collect();
+    for name in ["keyspaces_plugin_response_cache_hits_total", "keyspaces_plugin_response_cache_misses_total"] {
+        METRICS.retain_series(name, |labels| held.contains(labels));
+    }
+    let usage = cache.usage();
+    METRICS.set_gauge(
+        "keyspaces_plugin_response_cache_entries",
+        "Responses currently held by RESPONSE_CACHE_TTL_SECS",
+        &[],
+        usage.entries as f64
+    );
+    METRICS.set_gauge(
+        "keyspaces_plugin_response_cache_bytes",
+        "Serialized size of the responses currently held by RESPONSE_CACHE_TTL_SECS",
+        &[],
+        usage.bytes as f64
+    );
+}
+
+// Two Applications with one name would overwrite each other in ArgoCD
+fn check_app_names(entries: &[HashMap<String, serde_json::Value>]) -> Result<(), AppError> {
+    let collisions = app_name_collisions(entries);
+    if collisions.is_empty() {
+        return Ok(());
+    }
+    let listed: Vec<String> = collisions
+        .iter()
+        .map(|(name, pairs)| format!("{name} ({})", pairs.join(", ")))
+        .collect();
+    warn!("appName collisions, failing the request: {}", listed.join("; "));
+    Err(AppError::Conflict(format!("appName collisions: {}", listed.join("; "))))
+}
+
+// A skipped tenant is one Application ArgoCD prunes, so each is counted, not only logged
+fn count_skipped_tenants(skipped: usize) {
+    for _ in 0..skipped {
+        METRICS.inc_counter(
+            "keyspaces_plugin_skipped_tenants_total",
+            "Tenants dropped by tenantErrorMode=skip because their output construction failed",
+            &[]
+        );
+    }
+}
+
+// Each failure is already logged with its reason; the client gets a generic 500
+fn check_render_errors(entries: &[HashMap<String, serde_json::Value>]) -> Result<(), AppError> {
+    let failed: Vec<&str> = render_errors(entries)
+        .into_iter()
+        .map(|(tenant, _)| tenant)
+        .collect();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    Err(AppError::Internal(format!("output construction failed for tenants: {}", failed.join(", "))))
+}
+
+// Lists the first few offenders; the log line has them all
+fn check_required_fields(entries: &[HashMap<String, serde_json::Value>]) -> Result<(), AppError> {
+    let required = &CONFIG.get().expect("config not initialized").required_fields;
+    let offenders: Vec<String> = entries
+        .iter()
+        .filter_map(|entry| {
+            let missing = missing_fields(entry, required);
+            let tenant = entry.get("tenantId").and_then(|v| v.as_str()).unwrap_or_default();
+            (!missing.is_empty()).then(|| format!("{tenant} ({})", missing.join(", ")))
+        })
+        .collect();
+    if offenders.is_empty() {
+        return Ok(());
+    }
+    warn!("tenants missing required fields, failing the request: {}", offenders.join("; "));
+    let mut listed = offenders[..offenders.len().min(MAX_LISTED_OFFENDERS)].join("; ");
+    if offenders.len() > MAX_LISTED_OFFENDERS {
+        listed.push_str(&format!("; and {} more", offenders.len() - MAX_LISTED_OFFENDERS));
+    }
+    Err(AppError::Unprocessable(format!("tenants missing required fields: {listed}")))
+}
+
+// Saved-response key: sorted parameters, so key order in the generator config does not matter
+fn snapshot_key(parameters: &HashMap<String, serde_json::Value>) -> String {
+    serde_json::to_string(&parameters.iter().collect::<BTreeMap<_, _>>()).unwrap_or_default()
+}
+
+// Cold start: with Keyspaces unavailable and no live response yet, the last saved response
+// for the same parameters, tagged `X-Served-From: snapshot`. Otherwise the 503 as before.
+fn cold_start_response(
+    parameters: &HashMap<String, serde_json::Value>,
+    opts: &RequestOptions,
+    stats: &mut ScanStats,
+    reason: &'static str
+) -> Result<PluginResponse, AppError> {
+    let snapshot = SAVED.get()
+        .filter(|_| !opts.delta)
+        .and_then(|s| s.cold_start(&snapshot_key(parameters)));
+    let Some(snapshot) = snapshot else {
+        return Err(AppError::Unavailable(reason));
+    };
+    warn!("{}; serving the response saved at {} from SNAPSHOT_FILE", reason, snapshot.saved_at);
+    METRICS.inc_counter(
+        "keyspaces_plugin_snapshot_responses_total",
+        "Responses served from SNAPSHOT_FILE while Keyspaces was unavailable",
+        &[]
+    );
+    Ok(saved_response(snapshot, stats))
+}
+
+// Maintenance mode: no query at all, only the last complete response for these parameters,
+// tagged `X-Maintenance: true`. A delta has no saved form.
+fn maintenance_response(
+    parameters: &HashMap<String, serde_json::Value>,
+    opts: &RequestOptions,
+    stats: &mut ScanStats
+) -> Result<PluginResponse, AppError> {
+    if opts.delta {
+        return Err(AppError::Unavailable("maintenance mode: delta responses are unavailable"));
+    }
+    let snapshot = SAVED.get()
+        .and_then(|s| s.get(&snapshot_key(parameters)))
+        .ok_or(AppError::Unavailable("maintenance mode: no saved response for these parameters"))?;
+    debug!("maintenance mode: serving the response saved at {}", snapshot.saved_at);
+    stats.maintenance = true;
+    Ok(saved_response(snapshot, stats))
+}
+
+fn saved_response(snapshot: Snapshot, stats: &mut ScanStats) -> PluginResponse {
+    stats.rows_returned = snapshot.parameters.len() as u64;
+    stats.snapshot_saved_at = Some(snapshot.saved_at);
+    PluginResponse {
+        output: Output { parameters: snapshot.parameters, count: None },
+        meta: snapshot.meta,
+        delta: None,
+    }
+}
+
+fn set_maintenance(enabled: bool, saved: usize) {
+    if MAINTENANCE.swap(enabled, Ordering::Relaxed) != enabled {
+        if enabled {
+            warn!("maintenance mode on: serving {} saved responses, no Keyspaces queries", saved);
+        } else {
+            info!("maintenance mode off: querying Keyspaces again");
+        }
+    }
+    METRICS.set_gauge(
+        "keyspaces_plugin_maintenance_mode",
+        "1 while maintenance mode serves saved responses instead of querying",
+        &[],
+        if enabled { 1.0 } else { 0.0 }
+    );
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceInput {
+    enabled: bool,
+}
+
+// Token-protected switch for MAINTENANCE_MODE. Turning it on needs at least one saved
+// response, or every request would fail.
+async fn toggle_maintenance(
+    State(()): State<()>,
+    headers: HeaderMap,
+    Json(body): Json<MaintenanceInput>
+) -> Result<Json<serde_json::Value>, AppError> {
+    authorize(&headers)?;
+    let saved = SAVED.get().map_or(0, SavedResponses::len);
+    if body.enabled && saved == 0 {
+        return Err(
+            AppError::Conflict(
+                "maintenance mode needs a warm cache: no complete response has been saved yet".to_string()
+            )
+        );
+    }
+    set_maintenance(body.enabled, saved);
+    // Maintenance covers migrations, so anything cached before it may be outdated
+    if !body.enabled {
+        invalidate_responses("maintenance mode off");
+    }
+    Ok(Json(serde_json::json!({ "maintenance": body.enabled, "savedResponses": saved })))
+}
+
+// Complete responses only: a delta depends on the caller's previous call, and a partial or
+// truncated result would be served later as if it were the whole tenant set
+fn save_snapshot(
+    parameters: &HashMap<String, serde_json::Value>,
+    opts: &RequestOptions,
+    stats: &ScanStats,
+    resp: &PluginResponse
+) {
+    let Some(saved) = SAVED.get() else {
+        return;
+    };
+    saved.mark_live();
+    if opts.delta || stats.partial || stats.truncated || stats.failed_tenants > 0 {
+        return;
+    }
+    let key = snapshot_key(parameters);
+    let snapshot = Snapshot {
+        saved_at: generated_at_now(),
+        parameters: resp.output.parameters.clone(),
+        meta: resp.meta.clone(),
+    };
+    tokio::task::spawn_blocking(move || saved.save(key, snapshot));
+}
+
+// Per-tenant hashes of one full result, keyed by tenant id
+#[derive(Default)]
+struct SnapshotStore {
+    order: std::collections::VecDeque<String>,
+    snapshots: HashMap<String, HashMap<String, String>>,
+}
+
+impl SnapshotStore {
+    fn insert(&mut self, id: String, hashes: HashMap<String, String>) {
+        if self.snapshots.insert(id.clone(), hashes).is_none() {
+            self.order.push_back(id);
+        }
+        while self.order.len() > DELTA_SNAPSHOT_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.snapshots.remove(&oldest);
+            }
+        }
+    }
+}
+
+// Shared by delta mode and `/api/v1/diff`; `Value` keys are sorted, so equal entries match
+fn entry_hash(entry: &serde_json::Value) -> String {
+    use sha2::Digest;
+    let body = serde_json::to_vec(entry).unwrap_or_default();
+    hex::encode(&sha2::Sha256::digest(&body)[..16])
+}
+
+// Keeps the entries whose content changed since `since` and reports the tenant ids that
+// disappeared. An unknown or missing `since` (first call, evicted, other replica) yields the
+// full set with `full: true`, so a client can always recover.
+fn apply_delta(
+    entries: Vec<HashMap<String, serde_json::Value>>,
+    since: Option<&str>
+) -> (Vec<HashMap<String, serde_json::Value>>, serde_json::Value) {
+    use sha2::Digest;
+    let hash = |bytes: &[u8]| hex::encode(&sha2::Sha256::digest(bytes)[..16]);
+
+    let hashes: Vec<(String, String)> = entries
+        .iter()
+        .map(|entry| {
+            let id = entry
+                .get("tenantId")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string();
+            (id, entry_hash(&entry_value(entry)))
+        })
+        .collect();
+    let mut sorted = hashes.clone();
+    sorted.sort();
+    let snapshot = hash(
+        sorted
+            .iter()
+            .map(|(id, h)| format!("{id}={h}\n"))
+            .collect::<String>()
+            .as_bytes()
+    );
+
+    let mut store = SNAPSHOTS.lock().unwrap();
+    let previous = since.and_then(|id| store.snapshots.get(id)).cloned();
+    store.insert(snapshot.clone(), hashes.iter().cloned().collect());
+    drop(store);
+
+    let Some(previous) = previous else {
+        let delta = serde_json::json!({
+            "snapshot": snapshot,
+            "since": since,
+            "full": true,
+            "removed": [],
+        });
+        return (entries, delta);
+    };
+    let current: HashSet<&str> = hashes
+        .iter()
+        .map(|(id, _)| id.as_str())
+        .collect();
+    let mut removed: Vec<&String> = previous
+        .keys()
+        .filter(|id| !current.contains(id.as_str()))
+        .collect();
+    removed.sort();
+    let changed = entries
+        .into_iter()
+        .zip(&hashes)
+        .filter(|(_, (id, h))| previous.get(id) != Some(h))
+        .map(|(entry, _)| entry)
+        .collect();
+    let delta = serde_json::json!({
+        "snapshot": snapshot,
+        "since": since,
+        "full": false,
+        "removed": removed,
+    });
+    (changed, delta)
+}
+
+fn response_meta(opts: &RequestOptions, stats: &ScanStats) -> serde_json::Value {
+    let mut include: Vec<&String> = opts.include_tenant_ids.iter().collect();
+    include.sort();
+    let mut exclude: Vec<&String> = opts.exclude_tenant_ids.iter().collect();
+    exclude.sort();
+    let mut include_disabled: Vec<&String> = opts.include_disabled_for_clusters.iter().collect();
+    include_disabled.sort();
+    serde_json::json!({
+        "filters": {
+            "labelKey": opts.filter_label.as_ref().or(opts.exclude_label.as_ref()).map(|(k, _)| k),
+            "labelValue": opts.filter_label.as_ref().map(|(_, v)| v),
+            "excludeLabelValue": opts.exclude_label.as_ref().map(|(_, v)| v),
+            "filterMode": label_filter_mode(opts),
+            "includeTenantIds": include,
+            "excludeTenantIds": exclude,
+            "clusters": &opts.clusters,
+            "includeDisabledForClusters": include_disabled,
+            "pushdownTable": pushdown(opts).map(|h| &h.table),
+        },
+        "tables": scan_tables(opts),
+        "pages": stats.pages,
+        "scanned": stats.rows_scanned,
+        "disabled": stats.rows_disabled,
+        "returned": stats.rows_returned,
+        "truncated": stats.truncated,
+        "partial": stats.partial,
+        "consistency": consistency_name(
+            opts.consistency.unwrap_or(CONFIG.get().expect("config not initialized").consistency)
+        ),
+        "cache": {
+            "preparedStatementHits": stats.prepared_cache_hits,
+            "preparedStatementMisses": stats.prepared_cache_misses,
+        },
+    })
+}
+
+// WARN_TENANT_THRESHOLD: early signal that a fleet is growing toward what one generator
+// should emit, counted before delta so it tracks the fleet rather than the churn
+fn warn_tenant_count(tenants: usize) {
+    let threshold = CONFIG.get().map_or(0, |c| c.warn_tenant_threshold);
+    if threshold == 0 || tenants <= threshold {
+        return;
+    }
+    warn!(
+        tenants,
+        threshold,
+        "!!! response has {} tenants, over WARN_TENANT_THRESHOLD={}; consider splitting the ApplicationSet !!!",
+        tenants,
+        threshold
+    );
+    METRICS.inc_counter(
+        "keyspaces_plugin_tenant_threshold_exceeded_total",
+        "Responses with more tenants than WARN_TENANT_THRESHOLD",
+        &[]
+    );
+}
+
+// Loud on purpose: a preview left in a live ApplicationSet silently drops tenants
+fn warn_preview(opts: &RequestOptions) {
+    if let Some(limit) = opts.preview {
+        warn!(
+            "PREVIEW MODE ACTIVE: returning at most {} tenants tagged _preview; remove preview=true to generate for everyone",
+            limit
+        );
+    }
+}
+
+fn generated_at_now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+fn stamp_generated_at(entries: &mut [HashMap<String, serde_json::Value>], now: &str) {
+    for entry in entries {
+        entry.insert("generatedAt".into(), now.into());
+    }
+}
+
+fn wants_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get(axum::http::header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("application/x-ndjson"))
+}
+
+// `Accept: application/x-ndjson`: one tenant object per line, written as pages arrive, so
+// memory stays bounded by the page size rather than the tenant count. Headers are sent
+// before the scan finishes, so a failure mid-scan can only end the stream early (and is
+// logged); there is no ETag or X-Truncated.
+async fn stream_ndjson(parameters: &HashMap<String, serde_json::Value>) -> Result<Response, AppError> {
+    let mut opts = RequestOptions::from_parameters(parameters)?;
+    warn_preview(&opts);
+    if opts.output_shape != OutputShape::Flat {
+        return Err(AppError::BadRequest("outputShape: only flat can be streamed as NDJSON".to_string()));
+    }
+    if opts.count_only {
+        return Err(AppError::BadRequest("countOnly: not available as NDJSON".to_string()));
+    }
+    // Pages are rendered one at a time, so collisions across them would go unnoticed
+    if opts.app_name_collision_mode == AppNameCollisionMode::Fail {
+        return Err(AppError::BadRequest("appNameCollisionMode: fail is not available as NDJSON".to_string()));
+    }
+    // Entries already streamed could not be taken back
+    if opts.missing_field_mode == MissingFieldMode::Fail {
+        return Err(AppError::BadRequest("missingFieldMode: fail is not available as NDJSON".to_string()));
+    }
+    // Only an explicit `fail`; otherwise a stream skips failed tenants, as the default
+    // `fail` has no way to take back the lines already sent
+    if parameters.get("tenantErrorMode").and_then(|v| v.as_str()) == Some("fail") {
+        return Err(AppError::BadRequest("tenantErrorMode: fail is not available as NDJSON".to_string()));
+    }
+    opts.tenant_error_mode = TenantErrorMode::Skip;
+    if MAINTENANCE.load(Ordering::Relaxed) {
+        return Err(AppError::Unavailable("maintenance mode: NDJSON streaming is unavailable"));
+    }
+    let opts = Arc::new(opts);
+    let source = tenant_source();
+    source.check_available()?;
+
+    let (tx, rx) = tokio::sync::mpsc::channel(2);
+    let scan_opts = opts.clone();
+    tokio::spawn(async move {
+        let mut stats = ScanStats::default();
+        if let Err(e) = source.fetch(&scan_opts, &mut stats, Some(&tx)).await {
+            error!("ndjson stream aborted: {}", e);
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+
+    Ok(
+        (
+            [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+            axum::body::Body::from_stream(ndjson_lines(rx, opts)),
+        ).into_response()
+    )
+}
+
+// One chunk of lines per page. A failed scan ends the stream with an error rather than
+// cleanly, so hyper aborts the chunked body and the client cannot mistake the lines it got
+// for the whole set.
+fn ndjson_lines(
+    rx: tokio::sync::mpsc::Receiver<Result<Vec<TenantRow>, AppError>>,
+    opts: Arc<RequestOptions>
+) -> impl futures_util::Stream<Item = Result<Vec<u8>, std::io::Error>> {
+    struct StreamState {
+        rx: tokio::sync::mpsc::Receiver<Result<Vec<TenantRow>, AppError>>,
+        seen: HashSet<String>,
+        opts: Arc<RequestOptions>,
+        settings: RenderSettings,
+        generated_at: Option<String>,
+        // Entries written so far, for `preview`
+        emitted: usize,
+    }
+    let state = StreamState {
+        rx,
+        seen: HashSet::new(),
+        generated_at: opts.include_generated_at.then(generated_at_now),
+        opts,
+        settings: render_settings(),
+        emitted: 0,
+    };
+    futures_util::stream::unfold(state, |mut state| async move {
+        // Ending the stream drops the receiver, which stops the scan
+        if state.opts.preview.is_some_and(|n| state.emitted >= n) {
+            return None;
+        }
+        let page = match state.rx.recv().await? {
+            Ok(page) => page,
+            Err(e) => return Some((Err(std::io::Error::other(e)), state)),
+        };
+        // Same union rule as `union_tables`: the first table a tenant id appears in wins
+        let rows: Vec<TenantRow> = page
+            .into_iter()
+            .filter(|row| state.seen.insert(row.tenant_id.clone()))
+            .collect();
+        let (mut entries, skipped) = render_tenants_counted(rows, &state.opts, &state.settings);
+        count_skipped_tenants(skipped);
+        if let Some(limit) = state.opts.preview {
+            entries.truncate(limit - state.emitted);
+        }
+        state.emitted += entries.len();
+        if let Some(now) = &state.generated_at {
+            stamp_generated_at(&mut entries, now);
+        }
+        let mut chunk = Vec::new();
+        for entry in entries {
+            // Sorted keys, matching the buffered response
+            if let Err(e) = serde_json::to_writer(&mut chunk, &entry_value(&entry)) {
+                return Some((Err(std::io::Error::other(e)), state));
+            }
+            chunk.push(b'\n');
+        }
+        Some((Ok(chunk), state))
+    })
+}
+
+// Serializes explicitly (rather than via `Json`) so the cost shows up in the phase timings.
+// Going through `serde_json::Value` sorts every object's keys, so identical tenant sets give
+// byte-identical bodies and the ETag only changes when the output does.
+fn json_response(
+    resp: &PluginResponse,
+    stats: &ScanStats,
+    headers: &HeaderMap,
+    timings: &mut PhaseTimings
+) -> Result<Response, AppError> {
+    use sha2::Digest;
+    let started = Instant::now();
+    let (body, capped) = match CONFIG.get() {
+        Some(config) if config.max_response_bytes > 0 =>
+            capped_body(resp, config.max_response_bytes, config.max_response_bytes_mode)?,
+        _ => (to_json_bytes(resp)?, false),
+    };
+    let etag = format!("\"{}\"", hex::encode(&sha2::Sha256::digest(&body)[..16]));
+    timings.serialize = started.elapsed();
+
+    let mut response = if if_none_match(headers, &etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        ([(axum::http::header::CONTENT_TYPE, "application/json")], body).into_response()
+    };
+    response
+        .headers_mut()
+        .insert(
+            axum::http::header::ETAG,
+            axum::http::HeaderValue::from_str(&etag).map_err(|e| AppError::Internal(e.to_string()))?
+        );
+    if stats.truncated || capped {
+        response
+            .headers_mut()
+            .insert("x-truncated", axum::http::HeaderValue::from_static("true"));
+    }
+    if stats.partial {
+        response
+            .headers_mut()
+            .insert("x-partial", axum::http::HeaderValue::from_static("true"));
+    }
+    if stats.failed_tenants > 0 {
+        response.headers_mut().insert("x-skipped-tenants", axum::http::HeaderValue::from(stats.failed_tenants));
+    }
+    if let Some(saved_at) = &stats.snapshot_saved_at {
+        let saved_at = axum::http::HeaderValue
+            ::from_str(saved_at)
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        response.headers_mut().insert("x-served-from", axum::http::HeaderValue::from_static("snapshot"));
+        response.headers_mut().insert("x-snapshot-saved-at", saved_at);
+    }
+    if stats.maintenance {
+        response
+            .headers_mut()
+            .insert("x-maintenance", axum::http::HeaderValue::from_static("true"));
+    }
+    if stats.response_cached {
+        response
+            .headers_mut()
+            .insert("x-cache", axum::http::HeaderValue::from_static("hit"));
+    }
+    echo_queries(&mut response, &stats.queries, CONFIG.get().is_some_and(|c| c.debug_echo_query));
+    Ok(response)
+}
+
+// Bind values never appear: the statements only hold `?` markers. Several scans are joined
+// with `; `; none at all (file source, cached or saved responses) means no header.
+fn echo_queries(response: &mut Response, queries: &[String], enabled: bool) {
+    if !enabled || queries.is_empty() {
+        return;
+    }
+    match axum::http::HeaderValue::from_str(&queries.join("; ")) {
+        Ok(value) => {
+            response.headers_mut().insert("x-cql-query", value);
+        }
+        Err(e) => debug!("X-CQL-Query not set: {}", e),
+    }
+}
+
+// Through `Value` so keys are sorted and the bytes, and with them the ETag, are stable
+fn to_json_bytes(value: &impl Serialize) -> Result<Vec<u8>, AppError> {
+    serde_json
+        ::to_value(value)
+        .and_then(|v| serde_json::to_vec(&v))
+        .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+// MAX_RESPONSE_BYTES: sizes the envelope and then one entry at a time, stopping at the first
+// entry that does not fit, so an oversized result is never serialized as a whole. A delta
+// response always fails: its snapshot id covers every entry, so dropping some would hide
+// them from the next call too. Returns the body and whether entries were dropped.
+fn capped_body(
+    resp: &PluginResponse,
+    cap: usize,
+    mode: MaxResponseBytesMode
+) -> Result<(Vec<u8>, bool), AppError> {
+    let mut envelope = PluginResponse {
+        output: Output { parameters: Vec::new(), count: None },
+        meta: resp.meta.clone(),
+        delta: resp.delta.clone(),
+    };
+    let mut size = to_json_bytes(&envelope)?.len();
+    let mut kept = 0;
+    for entry in &resp.output.parameters {
+        let entry_size = to_json_bytes(entry)?.len() + usize::from(kept > 0);
+        if size + entry_size > cap {
+            break;
+        }
+        size += entry_size;
+        kept += 1;
+    }
+    if kept == resp.output.parameters.len() {
+        return Ok((to_json_bytes(resp)?, false));
+    }
+    if mode == MaxResponseBytesMode::Fail || resp.delta.is_some() {
+        return Err(
+            AppError::Internal(
+                format!(
+                    "response exceeds MAX_RESPONSE_BYTES={cap} ({kept} of {} entries fit)",
+                    resp.output.parameters.len()
+                )
+            )
+        );
+    }
+    warn!(
+        "response cut to {} of {} entries at MAX_RESPONSE_BYTES={}; returning a truncated result",
+        kept,
+        resp.output.parameters.len(),
+        cap
+    );
+    envelope.output.parameters = resp.output.parameters[..kept].to_vec();
+    if let Some(meta) = envelope.meta.as_mut().and_then(|m| m.as_object_mut()) {
+        meta.insert("truncated".to_string(), serde_json::Value::Bool(true));
+    }
+    Ok((to_json_bytes(&envelope)?, true))
+}
+
+// Weak comparison per RFC 9110: `W/` prefixes are ignored and `*` matches anything
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(axum::http::header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+// Wall-clock cost of each pipeline phase for one request, so a slow call can be pinned on
+// Keyspaces or on post-processing
+#[derive(Debug, Default)]
+struct PhaseTimings {
+    auth: Duration,
+    // Waiting on Keyspaces, excluding row decoding
+    query: Duration,
+    parse: Duration,
+    // Filtering plus output shaping
+    filter: Duration,
+    serialize: Duration,
+}
+
+impl PhaseTimings {
+    fn record(&self) {
+        let phases = [
+            ("auth", self.auth),
+            ("query", self.query),
+            ("parse", self.parse),
+            ("filter", self.filter),
+            ("serialize", self.serialize),
+        ];
+        debug!(
+            auth_ms = self.auth.as_secs_f64() * 1000.0,
+            query_ms = self.query.as_secs_f64() * 1000.0,
+            parse_ms = self.parse.as_secs_f64() * 1000.0,
+            filter_ms = self.filter.as_secs_f64() * 1000.0,
+            serialize_ms = self.serialize.as_secs_f64() * 1000.0,
+            "getparams phase timings"
+        );
+        for (phase, duration) in phases {
+            METRICS.observe(
+                "keyspaces_plugin_phase_duration_seconds",
+                "Time spent per getparams pipeline phase",
+                &[("phase", phase)],
+                duration.as_secs_f64()
+            );
+        }
+    }
+}
+
+// Runs the same scan as `handler` and reports how it was executed instead of the tenants
+async fn debug_plan(
+    State(()): State<()>,
+    headers: HeaderMap,
+    Json(body): Json<PluginInput>
+) -> Result<Json<serde_json::Value>, AppError> {
+    let caller = authorize(&headers)?;
+    check_param_allowlist(&caller, &body.input.parameters)?;
+    let opts = RequestOptions::from_parameters(&body.input.parameters)?;
+
+    let mut stats = ScanStats::default();
+    let rows = tenant_source().fetch(&opts, &mut stats, None).await?;
+    let (entries, skipped) = render_tenants_counted(rows, &opts, &render_settings());
+    let failed = render_errors(&entries).len();
+    stats.rows_returned = (entries.len() - failed) as u64;
+    stats.failed_tenants = (skipped + failed) as u64;
+
+    Ok(
+        Json(
+            serde_json::json!({
+                "queries": scan_tables(&opts)
+                    .into_iter()
+                    .map(|t| tenant_query(t, &opts))
+                    .collect::<Vec<_>>(),
+                "filters": {
+                    "labelKey": opts.filter_label.as_ref().or(opts.exclude_label.as_ref()).map(|(k, _)| k),
+                    "labelValue": opts.filter_label.as_ref().map(|(_, v)| v),
+                    "excludeLabelValue": opts.exclude_label.as_ref().map(|(_, v)| v),
+                    "filterMode": label_filter_mode(&opts),
+                },
+                "paging": stats,
+            })
+        )
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct DiffInput {
+    // Generator parameters for the current generation, as in a getparams call
+    #[serde(default)]
+    parameters: HashMap<String, serde_json::Value>,
+    // A previously captured `output.parameters` array
+    previous: Vec<serde_json::Value>,
+}
+
+// CI gate: what a tenant-table change would do to the generated Applications. Entries are
+// matched by `tenantId` and compared with the delta-mode hash.
+async fn diff_output(
+    State(()): State<()>,
+    headers: HeaderMap,
+    Json(body): Json<DiffInput>
+) -> Result<Json<serde_json::Value>, AppError> {
+    let caller = authorize(&headers)?;
+    check_param_allowlist(&caller, &body.parameters)?;
+    let opts = RequestOptions::from_parameters(&body.parameters)?;
+    if opts.output_shape != OutputShape::Flat || opts.delta {
+        return Err(AppError::BadRequest("diff needs the flat, non-delta output".to_string()));
+    }
+    // Either would report every tenant past the cut as removed
+    if opts.count_only || opts.preview.is_some() {
+        return Err(AppError::BadRequest("diff needs the full output; countOnly and preview are not supported".to_string()));
+    }
+    let id_of = |entry: &serde_json::Value| {
+        entry
+            .get("tenantId")
+            .and_then(|v| v.as_str())
+            .map(|id| id.to_string())
+    };
+    let mut previous: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    for (i, entry) in body.previous.into_iter().enumerate() {
+        let id = id_of(&entry).ok_or_else(|| {
+            AppError::BadRequest(format!("previous[{i}]: expected an object with a string tenantId"))
+        })?;
+        previous.insert(id, entry);
+    }
+
+    let (resp, _) = generate(&body.parameters, &mut PhaseTimings::default(), false).await?;
+    let mut current: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    for entry in resp.output.parameters {
+        let entry = serde_json::to_value(entry).map_err(|e| AppError::Internal(e.to_string()))?;
+        current.insert(id_of(&entry).unwrap_or_default(), entry);
+    }
+
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    let mut unchanged = 0;
+    for (id, after) in current {
+        match previous.remove(&id) {
+            None => added.push(after),
+            Some(before) if entry_hash(&before) == entry_hash(&after) => {
+                unchanged += 1;
+            }
+            Some(before) => changed.push(serde_json::json!({ "tenantId": id, "before": before, "after": after })),
+        }
+    }
+    let removed: Vec<serde_json::Value> = previous.into_values().collect();
+    Ok(
+        Json(
+            serde_json::json!({
+                "added": added,
+                "removed": removed,
+                "changed": changed,
+                "unchanged": unchanged,
+            })
+        )
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct ValidateInput {
+    // Column name -> value, as it would be written to the tenant table
+    row: serde_json::Map<String, serde_json::Value>,
+    // Generator parameters to render with, as in a getparams call
+    #[serde(default)]
+    parameters: HashMap<String, serde_json::Value>,
+}
+
+// Dry run for tenant-writing tooling: decodes one row the way a scan would and renders it
+// through the generator pipeline, without touching Keyspaces. 200 with the output map (or
+// `filteredOut` when the parameters drop it), 422 with every problem found otherwise.
+async fn validate_row(
+    State(()): State<()>,
+    headers: HeaderMap,
+    Json(body): Json<ValidateInput>
+) -> Result<Response, AppError> {
+    let caller = authorize(&headers)?;
+    check_param_allowlist(&caller, &body.parameters)?;
+    let opts = RequestOptions::from_parameters(&body.parameters)?;
+    let config = CONFIG.get().expect("config not initialized");
+
+    let row = match decode_json_row(body.row, &opts, config.tables[0].clone(), false) {
+        Ok(row) => row,
+        Err(errors) => {
+            return Ok(
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(serde_json::json!({ "valid": false, "errors": errors })),
+                ).into_response()
+            );
+        }
+    };
+    let entries = render_tenants(vec![row], &opts, &render_settings());
+    check_render_errors(&entries)?;
+    let output = entries.into_iter().next();
+    Ok(
+        Json(
+            serde_json::json!({
+                "valid": true,
+                "filteredOut": output.is_none(),
+                "output": output,
+            })
+        ).into_response()
+    )
+}
+
+// A tenant row given as JSON column name -> value, decoded with the same rules as a scanned
+// row. Shared by `/api/v1/validate` and TENANT_SOURCE_FILE; every problem is reported.
+fn decode_json_row(
+    mut row: serde_json::Map<String, serde_json::Value>,
+    opts: &RequestOptions,
+    source: String,
+    ignore_unknown: bool
+) -> Result<TenantRow, Vec<String>> {
+    let config = CONFIG.get().expect("config not initialized");
+    let mut errors: Vec<String> = Vec::new();
+    let mut text = |column: &str, required: bool, errors: &mut Vec<String>| -> Option<String> {
+        match row.remove(column) {
+            Some(serde_json::Value::String(s)) => Some(s),
+            None | Some(serde_json::Value::Null) if !required => None,
+            None | Some(serde_json::Value::Null) => {
+                errors.push(format!("{column}: required"));
+                None
+            }
+            Some(other) => {
+                errors.push(format!("{column}: expected text, got {}", json_type_name(&other)));
+                None
+            }
+        }
+    };
+    let tenant_id = text("tenant_id", true, &mut errors).unwrap_or_default();
+    let namespace = text("namespace", false, &mut errors);
+    let target_cluster = text("target_cluster", true, &mut errors).unwrap_or_default();
+    // Null reads as empty, as in `scan_table`, and is left to `missingFieldMode`
+    let repo_url = text("repo_url", false, &mut errors).unwrap_or_default();
+    let repo_path = text("repo_path", false, &mut errors).unwrap_or_default();
+    let dest_namespace = match &config.dest_namespace_column {
+        Some(column) => text(column, false, &mut errors),
+        None => None,
+    };
+    let project = match &config.project_column {
+        Some(column) => text(column, false, &mut errors),
+        None => None,
+    };
+    if tenant_id.is_empty() && !errors.iter().any(|e| e.starts_with("tenant_id")) {
+        errors.push("tenant_id: must not be empty".to_string());
+    }
+
+    let mut text_map = |column: &str, errors: &mut Vec<String>| -> Option<HashMap<String, String>> {
+        match row.remove(column) {
+            None | Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::Object(m)) => {
+                let mut out = HashMap::new();
+                for (k, v) in m {
+                    match v {
+                        serde_json::Value::String(v) => {
+                            out.insert(k, v);
+                        }
+                        other =>
+                            errors.push(
+                                format!("{column}.{k}: expected text, got {}", json_type_name(&other))
+                            ),
+                    }
+                }
+                // Cassandra stores an empty map as null
+                (!out.is_empty()).then_some(out)
+            }
+            Some(other) => {
+                errors.push(format!("{column}: expected map<text, text>, got {}", json_type_name(&other)));
+                None
+            }
+        }
+    };
+    let labels = text_map("labels", &mut errors);
+    let params = text_map("params", &mut errors);
+    let annotations = match &config.annotations_column {
+        Some(column) => text_map(column, &mut errors),
+        None => None,
+    };
+    // Stricter than a scan, which logs a non-integer value and omits it
+    let sync_wave = match config.sync_wave_column.as_ref().map(|c| (c, row.remove(c.as_str()))) {
+        None | Some((_, None | Some(serde_json::Value::Null))) => None,
+        Some((column, Some(value))) => {
+            let wave = match &value {
+                serde_json::Value::Number(n) => n.as_i64(),
+                serde_json::Value::String(s) => parse_sync_wave(s),
+                _ => None,
+            };
+            if wave.is_none() {
+                errors.push(format!("{column}: expected an integer, got {value}"));
+            }
+            wave
+        }
+    };
+
+    let mut scalar = |column: &String, errors: &mut Vec<String>| -> (String, Option<CqlValue>) {
+        let value = match row.remove(column.as_str()) {
+            None | Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::Bool(b)) => Some(CqlValue::Boolean(b)),
+            Some(serde_json::Value::String(s)) => Some(CqlValue::Text(s)),
+            Some(serde_json::Value::Number(n)) => Some(number::json_to_cql(&n)),
+            Some(other) => {
+                errors.push(format!("{column}: expected a scalar, got {}", json_type_name(&other)));
+                None
+            }
+        };
+        (column.clone(), value)
+    };
+    let promoted = config.promoted_columns
+        .iter()
+        .map(|c| scalar(c, &mut errors))
+        .collect();
+    let extra = opts.extra_columns
+        .iter()
+        .map(|c| scalar(c, &mut errors))
+        .collect();
+
+    if !ignore_unknown {
+        let mut unknown: Vec<&String> = row.keys().collect();
+        unknown.sort();
+        for column in unknown {
+            errors.push(format!("{column}: not a column the plugin reads"));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(TenantRow {
+        tenant_id,
+        namespace,
+        target_cluster,
+        repo_url,
+        repo_path,
+        labels,
+        params,
+        dest_namespace,
+        sync_wave,
+        annotations,
+        project,
+        promoted,
+        extra,
+        write_time: None,
+        source,
+    })
+}
+
+// Resolved, non-secret settings, so operators can see which env vars took effect. Built
+// from an explicit allowlist: the token, passwords and usernames are never read here.
+async fn effective_config(
+    State(()): State<()>,
+    headers: HeaderMap
+) -> Result<Json<serde_json::Value>, AppError> {
+    authorize(&headers)?;
+    let config = CONFIG.get().expect("config not initialized");
+    let region = env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+
+    Ok(
+        Json(
+            serde_json::json!({
+                "session": {
+                    "region": &region,
+                    "port": config.port,
+                    "localDc": LOCAL_DC.get()
+                        .cloned()
+                        .unwrap_or_else(|| env::var("KEYSPACES_LOCAL_DC").unwrap_or_else(|_| region.clone())),
+                    "compression": env::var("KEYSPACES_COMPRESSION").unwrap_or_else(|_| "none".to_string()),
+                    "rootCert": env::var("KEYSPACES_ROOT_CERT").ok(),
+                    "insecureSkipVerify": env_flag("KEYSPACES_INSECURE_SKIP_VERIFY"),
+                    "readEndpoint": env::var("KEYSPACES_READ_ENDPOINT").ok(),
+                    "readSessionActive": READ_SESSION.get().is_some(),
+                },
+                "keyspace": &config.keyspace,
+                "tables": &config.tables,
+                "viewName": &config.view_name,
+                "readinessQuery": &config.readiness_query,
+                "viewColumnMap": &config.view_columns,
+                "pageSize": config.page_size,
+                "maxPageSize": config.max_page_size,
+                "pagePrefetchDepth": config.page_prefetch_depth,
+                "consistency": consistency_name(config.consistency),
+                "allowedFilterModes": &config.allowed_filter_modes,
+                "regexMaxLen": config.regex_max_len,
+                "regexSizeLimit": config.regex_size_limit,
+                "regexCacheCapacity": config.regex_cache_capacity,
+                "breakerFailureThreshold": config.breaker_failure_threshold,
+                "breakerOpenSecs": config.breaker_open_secs,
+                "healthMaxStalenessSecs": config.health_max_staleness_secs,
+                "errorBudgetWindowSecs": config.error_budget_window_secs,
+                "errorBudgetThreshold": config.error_budget_threshold,
+                "responseCacheTtlSecs": config.response_cache_ttl_secs,
+                "responseCacheMaxEntries": config.response_cache_max_entries,
+                "responseCacheMaxBytes": config.response_cache_max_bytes,
+                "maxPages": config.max_pages,
+                "maxPagesMode": format!("{:?}", config.max_pages_mode).to_lowercase(),
+                "mergeStrategy": config.merge_strategy.name(),
+                "warnTenantThreshold": config.warn_tenant_threshold,
+                "maxResponseBytes": config.max_response_bytes,
+                "maxRequestHeaders": config.max_request_headers,
+                "maxRequestHeaderBytes": config.max_request_header_bytes,
+                "maxResponseBytesMode": format!("{:?}", config.max_response_bytes_mode).to_lowercase(),
+                "snapshotFile": &config.snapshot_file,
+                "paramAllowlistEntries": config.param_allowlist.0.len(),
+                "maintenanceMode": MAINTENANCE.load(Ordering::Relaxed),
+                "preparedCacheCapacity": config.prepared_cache_capacity,
+                "slowQueryMs": config.slow_query_ms,
+                "routePrefix": &config.route_prefix,
+                "authHeaderName": config.auth_header_name.as_str(),
+                "authScheme": &config.auth_scheme,
+                "serverTls": config.server_tls_cert.is_some(),
+                "serverClientCa": &config.server_client_ca,
+                "clientAuthMode": config.server_client_ca.as_ref().map(|_| config.client_auth_mode.name()),
+                "namespaceTemplate": &config.namespace_template,
+                "destNamespaceColumn": &config.dest_namespace_column,
+                "syncWaveColumn": &config.sync_wave_column,
+                "annotationsColumn": &config.annotations_column,
+                "projectColumn": &config.project_column,
+                "defaultProject": &config.default_project,
+                "normalizeRepoUrl": config.normalize_repo_url,
+                "debugEchoQuery": config.debug_echo_query,
+                "promotedColumns": &config.promoted_columns,
+                "writeTimeColumn": &config.write_time_column,
+                "paramSourcePrecedence": config.param_precedence
+                    .iter()
+                    .map(|s| s.name())
+                    .collect::<Vec<_>>(),
+                // Keys only: values are operator data, not settings
+                "defaultParamKeys": config.default_params.keys().collect::<Vec<_>>(),
+                "maxLabelsPerTenant": config.max_labels_per_tenant,
+                "maxParamsPerTenant": config.max_params_per_tenant,
+                "requiredFields": &config.required_fields,
+                "indexHints": config.index_hints
+                    .iter()
+                    .map(|(k, h)| (k.clone(), serde_json::json!({ "table": &h.table, "column": &h.column })))
+                    .collect::<serde_json::Map<_, _>>(),
+            })
+        )
+    )
+}
+
+// Machine-readable description of the request parameters and response shape; public
+async fn openapi() -> Json<serde_json::Value> {
+    use serde_json::json;
+    let prefix = CONFIG.get().map(|c| c.route_prefix.as_str()).unwrap_or_default();
+
+    let properties: serde_json::Map<String, serde_json::Value> = REQUEST_PARAMETERS.iter()
+        .map(|p| {
+            let mut schema = match p.kind {
+                ParamKind::String => json!({ "type": "string" }),
+                ParamKind::Flag =>
+                    json!({
+                    "oneOf": [{ "type": "boolean" }, { "type": "string", "enum": ["true", "false"] }],
+                }),
+                ParamKind::List =>
+                    json!({
+                    "oneOf": [
+                        { "type": "array", "items": { "type": "string" } },
+                        { "type": "string", "description": "comma-separated" },
+                    ],
+                }),
+                ParamKind::Enum => json!({ "type": "string", "enum": p.allowed }),
+                ParamKind::Map => {
+                    // `allowed` may hold patterns such as `default:<value>`, so no `enum`
+                    let op = json!({ "type": "string", "description": p.allowed.join(", ") });
+                    json!({
+                        "type": "object",
+                        "additionalProperties": {
+                            "oneOf": [op.clone(), { "type": "array", "items": op }],
+                        },
+                    })
+                }
+            };
+            schema["description"] = p.description.into();
+            (p.name.to_string(), schema)
+        })
+        .collect();
+
+    let request =
+        json!({
+        "type": "object",
+        "properties": {
+            "applicationSetName": { "type": "string" },
+            "input": {
+                "type": "object",
+                "properties": {
+                    "parameters": { "type": "object", "properties": properties },
+                },
+            },
+        },
+    });
+    let response =
+        json!({
+        "type": "object",
+        "properties": {
+            "output": {
+                "type": "object",
+                "properties": {
+                    "parameters": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "additionalProperties": true,
+                            "properties": {
+                                "tenantId": { "type": "string" },
+                                "namespace": { "type": "string" },
+                                "cluster": { "type": "string" },
+                                "repoURL": { "type": "string" },
+                                "path": { "type": "string" },
+                                "labels": { "type": "object", "additionalProperties": { "type": "string" } },
+                                "params": { "type": "object", "additionalProperties": { "type": "string" } },
+                            },
+                        },
+                    },
+                    // Instead of `parameters` with `countOnly`
+                    "count": { "type": "integer", "minimum": 0 },
+                },
+            },
+        },
+    });
+
+    let error =
+        json!({
+        "type": "object",
+        "required": ["error"],
+        "properties": {
+            "error": {
+                "type": "object",
+                "required": ["code", "message"],
+                "properties": {
+                    "code": { "type": "string", "enum": ["bad_request", "forbidden", "param_not_allowed", "not_found", "conflict", "unprocessable", "headers_too_large", "internal", "unavailable"] },
+                    "message": { "type": "string" },
+                },
+            },
+        },
+    });
+    let error_response = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } },
+        })
+    };
+
+    Json(
+        json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "argocd-aws-keyspaces-tenant-generator-plugin",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "components": {
+            "securitySchemes": { "bearer": { "type": "http", "scheme": "bearer" } },
+            "schemas": { "PluginInput": request, "PluginResponse": response, "Error": error },
+        },
+        "paths": {
+            format!("{prefix}/api/v1/getparams.execute"): {
+                "post": {
+                    "summary": "ApplicationSet plugin generator call",
+                    "security": [{ "bearer": [] }],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/PluginInput" } } },
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "One parameter map per tenant",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/PluginResponse" } } },
+                        },
+                        "304": { "description": "Output unchanged since the If-None-Match ETag" },
+                        "400": error_response("Invalid parameter"),
+                        "403": error_response("Missing or wrong bearer token, or a parameter outside the token's allowlist"),
+                        "409": error_response("Tenants share an appName under appNameCollisionMode=fail"),
+                        "422": error_response("Tenants miss REQUIRED_FIELDS under missingFieldMode=fail"),
+                        "431": error_response("Too many or too large request headers"),
+                        "503": error_response("Keyspaces session not established yet"),
+                    },
+                },
+            },
+        },
+    })
+    )
+}
+
+// Readiness: the configured table must be visible to the current credentials. Catches
+// typos and missing IAM permissions at deploy time rather than on the first generator call.
+async fn readyz() -> impl IntoResponse {
+    let Some(source) = SOURCE.get() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "tenant source not initialized".to_string());
+    };
+    // No probe query: maintenance mode never touches Keyspaces
+    if MAINTENANCE.load(Ordering::Relaxed) {
+        return (StatusCode::OK, "ready (maintenance mode)".to_string());
+    }
+    match source.readiness().await {
+        Ok(()) => (StatusCode::OK, "ready".to_string()),
+        // Ready while a cold start can answer from SNAPSHOT_FILE, or the Service would send
+        // ArgoCD nowhere
+        Err(reason) if SAVED.get().is_some_and(SavedResponses::cold_start_serving) => {
+            warn!("readiness failed: {}; ready anyway, serving SNAPSHOT_FILE", reason);
+            (StatusCode::OK, format!("ready (serving SNAPSHOT_FILE: {reason})"))
+        }
+        Err(reason) => {
+            warn!("readiness failed: {}", reason);
+            (StatusCode::SERVICE_UNAVAILABLE, reason)
+        }
+    }
+}
+
+async fn check_table(session: &Session) -> Result<(), String> {
+    let config = CONFIG.get().expect("config not initialized");
+    // Only whether it runs; its rows are not looked at
+    if let Some(query) = &config.readiness_query {
+        return session
+            .query_unpaged(query.as_str(), &[])
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("readiness query failed: {e}"));
+    }
+    let Some(view) = &config.view_name else {
+        for table in &config.tables {
+            if !schema_has(session, "tables", "table_name", table).await? {
+                return Err(format!("table {}.{} not found or not authorized", config.keyspace, table));
+            }
+        }
+        return Ok(());
+    };
+    // A Cassandra materialized view, or a plain table maintained as one (Keyspaces has no
+    // system_schema.views, hence the fallback)
+    let found =
+        schema_has(session, "views", "view_name", view).await.unwrap_or(false) ||
+        schema_has(session, "tables", "table_name", view).await?;
+    if !found {
+        return Err(format!("view {}.{} not found or not authorized", config.keyspace, view));
+    }
+    Ok(())
+}
+
+// One SELECT without bind markers, returned without the trailing semicolon. A `;` or `?`
+// anywhere else is rejected even inside a string literal; the probe has no use for either.
+fn read_only_query(cql: &str) -> Result<String, String> {
+    let query = cql.trim();
+    let query = query.strip_suffix(';').unwrap_or(query).trim_end();
+    let keyword = query.split_whitespace().next().unwrap_or_default();
+    if !keyword.eq_ignore_ascii_case("select") || keyword.len() == query.len() {
+        return Err("must be a SELECT statement".to_string());
+    }
+    if query.contains(';') {
+        return Err("must be a single statement".to_string());
+    }
+    if query.contains('?') {
+        return Err("must not have bind markers".to_string());
+    }
+    Ok(query.to_string())
+}
+
+async fn schema_has(session: &Session, relation: &str, column: &str, name: &str) -> Result<bool, String> {
+    let config = CONFIG.get().expect("config not initialized");
+    let qr = session
+        .query_unpaged(
+            format!("SELECT {column} FROM system_schema.{relation} WHERE keyspace_name = ? AND {column} = ?"),
+            (&config.keyspace, name)
+        ).await
+        .map_err(|e| format!("schema query failed: {e}"))?;
+    let rows = qr
+        .into_rows_result()
+        .map_err(|e| format!("schema query failed: {e}"))?
+        .rows_num();
+    Ok(rows > 0)
+}
+
+const TEXT_TYPES: &[&str] = &["text", "ascii"];
+const TEXT_MAP_TYPES: &[&str] = &["map<text, text>", "frozen<map<text, text>>"];
+const SYNC_WAVE_TYPES: &[&str] = &["tinyint", "smallint", "int", "bigint", "text", "ascii"];
+
+// Every column a request may read, as (what reads it, source column, accepted types in
+// system_schema.columns spelling); no types means any. `extraColumns` are per request and
+// not listed.
+fn expected_columns() -> Vec<(String, String, &'static [&'static str])> {
+    let config = CONFIG.get().expect("config not initialized");
+    let mut columns: Vec<(String, String, &'static [&'static str])> = TENANT_COLUMNS.iter()
+        .map(|c| {
+            let types = if ["labels", "params"].contains(c) { TEXT_MAP_TYPES } else { TEXT_TYPES };
+            (c.to_string(), source_column(c).to_string(), types)
+        })
+        .collect();
+    columns.push(("enabled".to_string(), source_column("enabled").to_string(), &["boolean"]));
+    let optional: [(&str, &Option<String>, &'static [&'static str]); 5] = [
+        ("DEST_NAMESPACE_COLUMN", &config.dest_namespace_column, TEXT_TYPES),
+        ("SYNC_WAVE_COLUMN", &config.sync_wave_column, SYNC_WAVE_TYPES),
+        ("ANNOTATIONS_COLUMN", &config.annotations_column, TEXT_MAP_TYPES),
+        ("PROJECT_COLUMN", &config.project_column, TEXT_TYPES),
+        ("WRITETIME_COLUMN", &config.write_time_column, &[]),
+    ];
+    for (name, column, types) in optional {
+        if let Some(column) = column {
+            columns.push((name.to_string(), column.clone(), types));
+        }
+    }
+    for column in &config.promoted_columns {
+        columns.push(("PROMOTED_COLUMNS".to_string(), column.clone(), &[]));
+    }
+    columns
+}
+
+// Read-only: system_schema queries only, one per relation scanned
+async fn schema_report(session: &Session) -> Result<serde_json::Value, String> {
+    let config = CONFIG.get().expect("config not initialized");
+    let expected = expected_columns();
+    let mut problems: Vec<String> = Vec::new();
+    let mut relations = Vec::new();
+    for relation in scan_tables(&RequestOptions::default()) {
+        let qr = session
+            .query_unpaged(
+                "SELECT column_name, type FROM system_schema.columns WHERE keyspace_name = ? AND table_name = ?",
+                (&config.keyspace, relation)
+            ).await
+            .map_err(|e| format!("schema query failed: {e}"))?;
+        let actual: HashMap<String, String> = qr
+            .into_rows_result()
+            .map_err(|e| format!("schema query failed: {e}"))?
+            .rows::<(String, String)>()
+            .map_err(|e| format!("schema query failed: {e}"))?
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("schema query failed: {e}"))?;
+        if actual.is_empty() {
+            problems.push(format!("{relation}: not found or not authorized"));
+            relations.push(serde_json::json!({ "name": relation, "found": false, "columns": [] }));
+            continue;
+        }
+        let mut columns = Vec::new();
+        for (reads, column, types) in &expected {
+            let found = actual.get(column);
+            let ok = found.is_some_and(|t| types.is_empty() || types.contains(&t.as_str()));
+            match found {
+                None => problems.push(format!("{relation}.{column} ({reads}): missing")),
+                Some(t) if !ok =>
+                    problems.push(format!("{relation}.{column} ({reads}): expected {}, found {t}", types.join(" or "))),
+                Some(_) => {}
+            }
+            columns.push(
+                serde_json::json!({
+                    "column": column,
+                    "readAs": reads,
+                    "expected": types,
+                    "actual": found,
+                    "ok": ok,
+                })
+            );
+        }
+        relations.push(serde_json::json!({ "name": relation, "found": true, "columns": columns }));
+    }
+    Ok(
+        serde_json::json!({
+            "compatible": problems.is_empty(),
+            "keyspace": &config.keyspace,
+            "relations": relations,
+            "problems": problems,
+        })
+    )
+}
+
+// On-demand version of the checks a deploy relies on: the same columns and types the scan
+// decodes, against the live schema. 422 carries the same report as 200.
+async fn schema_check(State(()): State<()>, headers: HeaderMap) -> Result<Response, AppError> {
+    authorize(&headers)?;
+    let session = SESSION.get().ok_or(AppError::Unavailable("schema check needs a Keyspaces session"))?;
+    let result = schema_report(&session).await;
+    record_query(result.is_ok());
+    let report = result.map_err(AppError::Internal)?;
+    let status = if report["compatible"] == true { StatusCode::OK } else { StatusCode::UNPROCESSABLE_ENTITY };
+    Ok((status, Json(report)).into_response())
+}
+
+// Index hint for the request's label filter. `clusters` wins: it is already a partition
+// lookup on the base table.
+fn pushdown(opts: &RequestOptions) -> Option<&'static IndexHint> {
+    let config = CONFIG.get().expect("config not initialized");
+    let (key, _) = opts.filter_label.as_ref()?;
+    // Lookup tables are keyed by the exact value
+    if !opts.clusters.is_empty() || opts.filter_regex.is_some() {
+        return None;
+    }
+    config.index_hints.get(key)
+}
+
+// Tables a request reads: the hinted lookup table, or every configured table
+fn scan_tables(opts: &RequestOptions) -> Vec<&'static str> {
+    let config = CONFIG.get().expect("config not initialized");
+    match pushdown(opts) {
+        Some(hint) => vec![hint.table.as_str()],
+        None if config.view_name.is_some() => config.view_name.as_deref().into_iter().collect(),
+        None =>
+            config.tables
+                .iter()
+                .map(|t| t.as_str())
+                .collect(),
+    }
+}
+
+fn render_settings() -> RenderSettings {
+    let config = CONFIG.get().expect("config not initialized");
+    RenderSettings {
+        multi_table: config.tables.len() > 1,
+        emit_dest_namespace: config.dest_namespace_column.is_some(),
+        default_project: config.project_column.as_ref().map(|_| config.default_project.clone()),
+        normalize_repo_url: config.normalize_repo_url,
+        namespace_template: config.namespace_template.clone(),
+        max_labels_per_tenant: config.max_labels_per_tenant,
+        max_params_per_tenant: config.max_params_per_tenant,
+        param_precedence: config.param_precedence.clone(),
+        default_params: config.default_params.clone(),
+        required_fields: config.required_fields.clone(),
+        // One region per deployment; KEYSPACES_READ_ENDPOINT is assumed to share it
+        region: env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+    }
+}
+
+// Set once at startup, replaced when a root CA reload rebuilds the sessions. Requests clone
+// the Arc, so a swap never interrupts queries already running on the old session.
+struct SessionSlot(std::sync::RwLock<Option<Arc<Session>>>);
+
+impl SessionSlot {
+    const fn new() -> Self {
+        SessionSlot(std::sync::RwLock::new(None))
+    }
+
+    fn get(&self) -> Option<Arc<Session>> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn set(&self, session: Arc<Session>) {
+        *self.0.write().unwrap() = Some(session);
+    }
+}
+
+fn tenant_source() -> &'static dyn TenantSource {
+    SOURCE.get().expect("tenant source not initialized").as_ref()
+}
+
+// Generator queries use the read session when one is configured
+fn query_session() -> Option<Arc<Session>> {
+    READ_SESSION.get().or_else(|| SESSION.get())
+}
+
+// How a request passed `authorize`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Caller {
+    // A verified client certificate, with no token checked
+    ClientCert,
+    // SHA-256 hex of the PLUGIN_TOKEN_FILE token presented, as PARAM_ALLOWLIST_FILE keys it
+    Token(String),
+}
+
+fn authorize(headers: &HeaderMap) -> Result<Caller, AppError> {
+    let config = CONFIG.get().expect("config not initialized");
+    if CLIENT_CERT.try_with(|verified| *verified).unwrap_or(false) {
+        debug!("authorized by client certificate");
+        return Ok(Caller::ClientCert);
+    }
+    let tokens = TOKENS.read().unwrap().clone();
+    check_token(headers, config, &tokens)
+}
+
+// The header half of `authorize`, against the tokens currently loaded
+fn check_token(headers: &HeaderMap, config: &Config, tokens: &[String]) -> Result<Caller, AppError> {
+    use sha2::Digest;
+    // Every rejection is a plain 403; the debug line says why, never what was sent
+    let reject = |reason: &str| {
+        debug!("unauthorized: {} (header {})", reason, config.auth_header_name);
+        Err(AppError::Unauthorized)
+    };
+    // Not loaded yet is a retryable 503, never a comparison against an empty token
+    if tokens.is_empty() {
+        warn!("request rejected: plugin token not loaded");
+        return Err(AppError::Unavailable("plugin token not loaded"));
+    }
+    let Some(authz) = headers.get(&config.auth_header_name) else {
+        return reject("header missing");
+    };
+    let Ok(authz) = authz.to_str() else {
+        return reject("header is not valid UTF-8");
+    };
+    // Schemes are case-insensitive (RFC 9110), the token is not
+    let presented = if config.auth_scheme.is_empty() {
+        authz
+    } else {
+        match authz.split_once(' ') {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case(&config.auth_scheme) => rest,
+            Some(_) => {
+                return reject(&format!("wrong scheme, expected {:?}", config.auth_scheme));
+            }
+            None => {
+                return reject(&format!("missing {:?} scheme prefix", config.auth_scheme));
+            }
+        }
+    };
+    if !tokens.iter().any(|t| t == presented) {
+        return reject("token mismatch");
+    }
+    Ok(Caller::Token(hex::encode(sha2::Sha256::digest(presented.as_bytes()))))
+}
+
+// Only known parameter names are checked: unknown ones are ignored by the pipeline anyway.
+// A caller let in by its certificate has no token, so no entry applies.
+fn check_param_allowlist(caller: &Caller, parameters: &HashMap<String, serde_json::Value>) -> Result<(), AppError> {
+    let config = CONFIG.get().expect("config not initialized");
+    let Caller::Token(hash) = caller else {
+        return Ok(());
+    };
+    let Some(allowed) = config.param_allowlist.0.get(hash) else {
+        return Ok(());
+    };
+    let mut denied: Vec<&str> = parameters
+        .keys()
+        .map(|k| k.as_str())
+        .filter(|k| REQUEST_PARAMETERS.iter().any(|p| p.name == *k) && !allowed.contains(*k))
+        .collect();
+    if denied.is_empty() {
+        return Ok(());
+    }
+    denied.sort();
+    debug!("token {}…: parameters outside its allowlist: {}", &hash[..12], denied.join(", "));
+    METRICS.inc_counter(
+        "keyspaces_plugin_denied_parameter_requests_total",
+        "getparams requests rejected by PARAM_ALLOWLIST_FILE",
+        &[]
+    );
+    Err(AppError::ParamNotAllowed(format!("parameters not allowed for this token: {}", denied.join(", "))))
+}
+
+// Scans every configured table and unions the rows per MERGE_STRATEGY. With a sink, pages
+// are handed over as they are read and the union is left to the receiver, which keeps the
+// first row per tenant; merging needs every table first, so it sends the merged rows as one
+// page instead.
+async fn fetch_tenants(
+    session: &Arc<Session>,
+    opts: &RequestOptions,
+    stats: &mut ScanStats,
+    sink: Option<&PageSink>
+) -> Result<Vec<TenantRow>, AppError> {
+    let config = CONFIG.get().expect("config not initialized");
+    let throttles = Arc::new(AtomicU32::new(0));
+    stats.page_size = opts.page_size.unwrap_or(config.page_size);
+
+    let started = Instant::now();
+    let tables = scan_tables(opts);
+    let merge = config.merge_strategy != MergeStrategy::Shadow && tables.len() > 1;
+    let table_sink = sink.filter(|_| !merge);
+    let mut scans = Vec::new();
+    for table in tables {
+        if stats.truncated || stats.partial {
+            break;
+        }
+        scans.push(scan_table(session, table, opts, stats, &throttles, table_sink).await?);
+    }
+    let mut out = union_tables(scans, config.merge_strategy).map_err(AppError::Internal)?;
+    if let Some(sink) = sink.filter(|_| merge) {
+        // A dropped receiver only means the client went away
+        let _ = sink.send(Ok(std::mem::take(&mut out))).await;
+    }
+    stats.throttle_events = throttles.load(Ordering::Relaxed);
+    warn_slow_query(config, started.elapsed(), opts, stats);
+    Ok(out)
+}
+
+// One structured line per scan at or over SLOW_QUERY_MS, for alerting
+fn warn_slow_query(config: &Config, elapsed: Duration, opts: &RequestOptions, stats: &ScanStats) {
+    if config.slow_query_ms > 0 && elapsed.as_millis() >= u128::from(config.slow_query_ms) {
+        warn!(
+            duration_ms = elapsed.as_millis() as u64,
+            threshold_ms = config.slow_query_ms,
+            tables = config.tables.len(),
+            rows = stats.rows_scanned,
+            pages = stats.pages,
+            throttle_events = stats.throttle_events,
+            filter_label_key = opts.filter_label.as_ref().map(|(k, _)| k.as_str()),
+            filter_label_value = opts.filter_label.as_ref().map(|(_, v)| v.as_str()),
+            include_tenant_ids = opts.include_tenant_ids.len(),
+            exclude_tenant_ids = opts.exclude_tenant_ids.len(),
+            clusters = opts.clusters.len(),
+            extra_columns = ?opts.extra_columns,
+            "slow keyspaces query"
+        );
+    }
+}
+
+// Receives decoded pages while a streaming scan is running; the scan's caller sends the
+// error, if any, last
+type PageSink = tokio::sync::mpsc::Sender<Result<Vec<TenantRow>, AppError>>;
+
+// Walks one table page by page so large tables never need a single huge response. Pages go
+// to `sink` when there is one, otherwise they are collected and returned.
+async fn scan_table(
+    session: &Arc<Session>,
+    table: &str,
+    opts: &RequestOptions,
+    stats: &mut ScanStats,
+    throttles: &Arc<AtomicU32>,
+    sink: Option<&PageSink>
+) -> Result<Vec<TenantRow>, AppError> {
+    let config = CONFIG.get().expect("config not initialized");
+    let cql = tenant_query(table, opts);
+    let mut statement = prepared(session, &cql, stats).await?;
+    stats.queries.push(cql);
+    statement.set_page_size(stats.page_size);
+    statement.set_consistency(opts.consistency.unwrap_or(config.consistency));
+    statement.set_retry_policy(
+        Some(
+            Arc::new(ThrottleCountingRetryPolicy {
+                inner: DefaultRetryPolicy::new(),
+                throttles: throttles.clone(),
+            })
+        )
+    );
+
+    // Bind values matching the restriction `tenant_query` chose
+    let values: Vec<CqlValue> = match pushdown(opts) {
+        _ if !opts.clusters.is_empty() =>
+            vec![CqlValue::List(opts.clusters.iter().cloned().map(CqlValue::Text).collect())],
+        Some(_) => vec![CqlValue::Text(opts.filter_label.clone().unwrap_or_default().1)],
+        None => vec![],
+    };
+    // Each fetched page says whether MAX_PAGES cut the scan after it, so read-ahead never
+    // goes past the cap
+    let max_pages = config.max_pages;
+    let mut fetched = stats.pages;
+    let query = Arc::new((session.clone(), statement, values));
+    let mut pages = Pages::new(config.page_prefetch_depth, PagingState::start(), move |paging_state| {
+        let query = query.clone();
+        fetched += 1;
+        let at_cap = max_pages > 0 && fetched >= max_pages;
+        async move {
+            let (session, statement, values) = &*query;
+            let permit = db_permit().await?;
+            let result = session.execute_single_page(statement, values, paging_state).await;
+            record_query(result.is_ok());
+            let (qr, paging_response) = result.map_err(|e| query_error(query_error_class(&e), e))?;
+            drop(permit);
+            Ok::<_, AppError>(match paging_response.into_paging_control_flow() {
+                ControlFlow::Break(()) => ((qr, false), None),
+                ControlFlow::Continue(_) if at_cap => ((qr, true), None),
+                ControlFlow::Continue(next) => ((qr, false), Some(next)),
+            })
+        }
+    });
+    let mut out = Vec::new();
+    while let Some(fetched) = pages.next().await {
+        let (qr, truncated) = match fetched {
+            Ok(page) => page,
+            // Nothing read yet means nothing worth returning
+            Err(e) if opts.partial_page_mode == PartialPageMode::ReturnPartial && stats.pages > 0 => {
+                error!(
+                    error_class = e.query_class().unwrap_or("none"),
+                    "page read of {} failed after {} rows: {}; returning a partial result",
+                    table,
+                    stats.rows_scanned,
+                    e
+                );
+                stats.partial = true;
+                break;
+            }
+            Err(e) => {
+                error!(
+                    error_class = e.query_class().unwrap_or("none"),
+                    "page read of {} failed after {} rows: {}",
+                    table,
+                    stats.rows_scanned,
+                    e
+                );
+                return Err(e);
+            }
+        };
+        stats.pages += 1;
+
+        // Rows are decoded untyped so extra columns of any CQL type can ride along
+        let decode_started = Instant::now();
+        let rows_result = qr.into_rows_result().map_err(|e| AppError::Internal(e.to_string()))?;
+        let mut page = Vec::new();
+        let mut disabled = 0;
+        for row in rows_result.rows::<Row>().map_err(|e| AppError::Internal(e.to_string()))? {
+            let mut columns = row.map_err(|e| AppError::Internal(e.to_string()))?.columns.into_iter();
+            let mut next = || columns.next().flatten();
+            let tenant = TenantRow {
+                tenant_id: text_column(next(), "tenant_id")?,
+                namespace: optional_text_column(next(), "namespace")?,
+                target_cluster: text_column(next(), "target_cluster")?,
+                // A NULL is an empty value for `missingFieldMode` to judge, not a broken row
+                repo_url: optional_text_column(next(), "repo_url")?.unwrap_or_default(),
+                repo_path: optional_text_column(next(), "repo_path")?.unwrap_or_default(),
+                labels: text_map_column(next(), "labels")?,
+                params: text_map_column(next(), "params")?,
+                dest_namespace: match &config.dest_namespace_column {
+                    Some(column) => optional_text_column(next(), column)?,
+                    None => None,
+                },
+                sync_wave: match &config.sync_wave_column {
+                    Some(column) => sync_wave_column(next(), column)?,
+                    None => None,
+                },
+                annotations: match &config.annotations_column {
+                    Some(column) => text_map_column(next(), column)?,
+                    None => None,
+                },
+                project: match &config.project_column {
+                    Some(column) => optional_text_column(next(), column)?,
+                    None => None,
+                },
+                promoted: config.promoted_columns
+                    .iter()
+                    .map(|c| (c.clone(), next()))
+                    .collect(),
+                extra: opts.extra_columns
+                    .iter()
+                    .map(|c| (c.clone(), next()))
+                    .collect(),
+                write_time: if opts.include_write_time {
+                    match next() {
+                        Some(CqlValue::BigInt(micros)) => Some(micros),
+                        None => None,
+                        Some(other) => {
+                            return Err(
+                                AppError::Internal(format!("WRITETIME: expected bigint, got {other:?}"))
+                            );
+                        }
+                    }
+                } else {
+                    None
+                },
+                source: table.to_string(),
+            };
+            if !opts.include_disabled_for_clusters.is_empty() {
+                let enabled = match next() {
+                    Some(CqlValue::Boolean(enabled)) => Some(enabled),
+                    None => None,
+                    Some(other) => {
+                        return Err(AppError::Internal(format!("enabled: expected boolean, got {other:?}")));
+                    }
+                };
+                if !keeps_enabled_state(enabled, &tenant.target_cluster, opts) {
+                    disabled += 1;
+                    continue;
+                }
+            }
+            page.push(tenant);
+        }
+        stats.decode_time += decode_started.elapsed();
+        stats.rows_scanned += (page.len() + disabled) as u64;
+        stats.rows_disabled += disabled as u64;
+        match sink {
+            Some(sink) => {
+                if sink.send(Ok(page)).await.is_err() {
+                    // The client went away; no point reading further
+                    debug!("scan of {} abandoned: stream receiver dropped", table);
+                    break;
+                }
+            }
+            None => out.extend(page),
+        }
+
+        if truncated {
+            if config.max_pages_mode == MaxPagesMode::Fail {
+                return Err(
+                    AppError::Internal(format!("scan of {table} exceeded MAX_PAGES={}", config.max_pages))
+                );
+            }
+            warn!(
+                "scan of {} stopped at MAX_PAGES={} after {} rows; returning a truncated result",
+                table,
+                config.max_pages,
+                stats.rows_scanned
+            );
+            stats.truncated = true;
+            break;
+        }
+    }
+
+    Ok(out)
+}
+
+fn text_column(value: Option<CqlValue>, column: &str) -> Result<String, AppError> {
+    match value {
+        Some(CqlValue::Text(s) | CqlValue::Ascii(s)) => Ok(s),
+        other =>
+            Err(AppError::Internal(format!("column {column}: expected non-null text, got {other:?}"))),
+    }
+}
+
+fn optional_text_column(value: Option<CqlValue>, column: &str) -> Result<Option<String>, AppError> {
+    match value {
+        None => Ok(None),
+        Some(CqlValue::Text(s) | CqlValue::Ascii(s)) => Ok(Some(s)),
+        Some(other) =>
+            Err(AppError::Internal(format!("column {column}: expected text, got {other:?}"))),
+    }
+}
+
+// ArgoCD sorts sync waves as integers. A text value that is not one is the row's problem,
+// not the schema's: it is logged and omitted rather than failing the request.
+fn sync_wave_column(value: Option<CqlValue>, column: &str) -> Result<Option<i64>, AppError> {
+    match value {
+        None => Ok(None),
+        Some(CqlValue::TinyInt(n)) => Ok(Some(n.into())),
+        Some(CqlValue::SmallInt(n)) => Ok(Some(n.into())),
+        Some(CqlValue::Int(n)) => Ok(Some(n.into())),
+        Some(CqlValue::BigInt(n)) => Ok(Some(n)),
+        Some(CqlValue::Text(s) | CqlValue::Ascii(s)) => {
+            let wave = parse_sync_wave(&s);
+            if wave.is_none() {
+                warn!("column {}: sync wave {:?} is not an integer, omitting syncWave", column, s);
+            }
+            Ok(wave)
+        }
+        Some(other) =>
+            Err(AppError::Internal(format!("column {column}: expected an integer or text, got {other:?}"))),
+    }
+}
+
+fn parse_sync_wave(value: &str) -> Option<i64> {
+    value.trim().parse().ok()
+}
+
+fn text_map_column(
+    value: Option<CqlValue>,
+    column: &str
+) -> Result<Option<HashMap<String, String>>, AppError> {
+    let entries = match value {
+        None => {
+            return Ok(None);
+        }
+        Some(CqlValue::Map(entries)) => entries,
+        Some(other) => {
+            return Err(
+                AppError::Internal(format!("column {column}: expected map<text, text>, got {other:?}"))
+            );
+        }
+    };
+    entries
+        .into_iter()
+        .map(|entry| {
+            match entry {
+                (
+                    CqlValue::Text(k) | CqlValue::Ascii(k),
+                    CqlValue::Text(v) | CqlValue::Ascii(v),
+                ) => Ok((k, v)),
+                other =>
+                    Err(
+                        AppError::Internal(
+                            format!("column {column}: expected map<text, text> entry, got {other:?}")
+                        )
+                    ),
+            }
+        })
+        .collect::<Result<HashMap<_, _>, _>>()
+        .map(Some)
+}
+
+// Projections differ per request (`extraColumns`), so prepared statements are cached by
+// CQL string and the least recently used one is dropped once the cache is full
+#[derive(Default)]
+struct StatementCache {
+    tick: u64,
+    entries: HashMap<String, (PreparedStatement, u64)>,
+}
+
+impl StatementCache {
+    fn get(&mut self, cql: &str) -> Option<PreparedStatement> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(cql).map(|(statement, used)| {
+            *used = tick;
+            statement.clone()
+        })
+    }
+
+    fn insert(&mut self, cql: String, statement: PreparedStatement, capacity: usize) {
+        self.tick += 1;
+        while self.entries.len() >= capacity && !self.entries.contains_key(&cql) {
+            let Some(oldest) = self.entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(k, _)| k.clone()) else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.entries.insert(cql, (statement, self.tick));
+        self.report();
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.report();
+    }
+
+    fn report(&self) {
+        METRICS.set_gauge(
+            "keyspaces_plugin_prepared_statements",
+            "Prepared statements currently cached",
+            &[],
+            self.entries.len() as f64
+        );
+    }
+}
+
+fn label_filter_mode(opts: &RequestOptions) -> &'static str {
+    if opts.filter_regex.is_some() || opts.exclude_regex.is_some() { "regex" } else { "exact" }
+}
+
+// `filterMode=regex`: compiled once per distinct pattern and anchored, so a pattern matches
+// the whole label value like `exact` does. The regex crate runs in linear time, so there is
+// no catastrophic backtracking to time out; the length and size caps bound the compiled
+// program instead.
+fn label_pattern(name: &str, pattern: &str) -> Result<regex::Regex, AppError> {
+    let (max_len, size_limit, capacity) = CONFIG.get().map_or(
+        (DEFAULT_REGEX_MAX_LEN, DEFAULT_REGEX_SIZE_LIMIT, DEFAULT_REGEX_CACHE_CAPACITY),
+        |c| (c.regex_max_len, c.regex_size_limit, c.regex_cache_capacity)
+    );
+    if pattern.len() > max_len {
+        return Err(
+            AppError::BadRequest(format!("{name}: pattern is {} bytes, over REGEX_MAX_LEN={max_len}", pattern.len()))
+        );
+    }
+    if let Some(re) = PATTERNS.lock().unwrap().get(pattern) {
+        return Ok(re);
+    }
+    let re = regex::RegexBuilder
+        ::new(&format!("^(?:{pattern})$"))
+        .size_limit(size_limit)
+        .dfa_size_limit(size_limit)
+        .build()
+        .map_err(|e| {
+            let reason = match &e {
+                regex::Error::CompiledTooBig(_) => format!("pattern too complex for REGEX_SIZE_LIMIT={size_limit}"),
+                // Syntax errors come with a caret diagram; the last line is the reason
+                _ => e.to_string().lines().last().unwrap_or_default().trim().trim_start_matches("error: ").to_string(),
+            };
+            AppError::BadRequest(format!("{name}: invalid regex: {reason}"))
+        })?;
+    PATTERNS.lock().unwrap().insert(pattern.to_string(), re.clone(), capacity);
+    Ok(re)
+}
+
+// Least recently used pattern goes first, as in `StatementCache`
+#[derive(Default)]
+struct PatternCache {
+    tick: u64,
+    entries: HashMap<String, (regex::Regex, u64)>,
+}
+
+impl PatternCache {
+    fn get(&mut self, pattern: &str) -> Option<regex::Regex> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(pattern).map(|(re, used)| {
+            *used = tick;
+            re.clone()
+        })
+    }
+
+    fn insert(&mut self, pattern: String, re: regex::Regex, capacity: usize) {
+        self.tick += 1;
+        while self.entries.len() >= capacity && !self.entries.contains_key(&pattern) {
+            let Some(oldest) = self.entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(k, _)| k.clone()) else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.entries.insert(pattern, (re, self.tick));
+    }
+}
+
+// Circuit breaker and staleness, as one state for `/readyz` and the generator
+fn health_status() -> Health {
+    HEALTH.get().map_or(Health::Healthy, |health| health.status())
+}
+
+// Every Keyspaces call the plugin makes feeds the breaker, including keepalives and the
+// readiness probe, so an idle plugin still notices recovery
+fn record_query(ok: bool) {
+    let Some(health) = HEALTH.get() else {
+        return;
+    };
+    if ok {
+        health.record_success();
+    } else {
+        health.record_failure();
+    }
+    METRICS.set_gauge(
+        "keyspaces_plugin_breaker_open",
+        "1 while the circuit breaker rejects requests",
+        &[],
+        if health.status() == Health::BreakerOpen { 1.0 } else { 0.0 }
+    );
+}
+
+// Held for the duration of one Keyspaces call (a page fetch or a prepare), so HTTP
+// requests rejected before the scan never queue here
+async fn db_permit() -> Result<Option<tokio::sync::SemaphorePermit<'static>>, AppError> {
+    let Some(permits) = DB_PERMITS.get() else {
+        return Ok(None);
+    };
+    let timeout = Duration::from_millis(
+        CONFIG.get().map(|c| c.db_wait_timeout_ms).unwrap_or(DEFAULT_DB_WAIT_TIMEOUT_MS)
+    );
+    let started = Instant::now();
+    let permit = tokio::time::timeout(timeout, permits.acquire()).await;
+    METRICS.observe(
+        "keyspaces_plugin_db_wait_seconds",
+        "Time spent waiting for a MAX_DB_CONCURRENCY permit",
+        &[],
+        started.elapsed().as_secs_f64()
+    );
+    match permit {
+        Ok(Ok(permit)) => Ok(Some(permit)),
+        Ok(Err(_)) => Err(AppError::Internal("db semaphore closed".to_string())),
+        Err(_) => {
+            warn!("no Keyspaces permit within {:?} (MAX_DB_CONCURRENCY reached)", timeout);
+            Err(AppError::Unavailable("keyspaces concurrency limit reached"))
+        }
+    }
+}
+
+async fn prepared(
+    session: &Session,
+    cql: &str,
+    stats: &mut ScanStats
+) -> Result<PreparedStatement, AppError> {
+    if let Some(statement) = PREPARED.lock().unwrap().get(cql) {
+        stats.prepared_cache_hits += 1;
+        return Ok(statement);
+    }
+    stats.prepared_cache_misses += 1;
+    let permit = db_permit().await?;
+    let result = session.prepare(cql).await;
+    record_query(result.is_ok());
+    let statement = result.map_err(|e| query_error(prepare_error_class(&e), e))?;
+    drop(permit);
+    let capacity = CONFIG.get()
+        .map(|c| c.prepared_cache_capacity)
+        .unwrap_or(DEFAULT_PREPARED_CACHE_CAPACITY);
+    PREPARED.lock().unwrap().insert(cql.to_string(), statement.clone(), capacity);
+    Ok(statement)
+}
+
+// Minimal Prometheus text exposition; the plugin only needs a handful of series
+#[derive(Default)]
+struct Metrics {
+    families: Mutex<BTreeMap<&'static str, MetricFamily>>,
+}
+
+const HISTOGRAM_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+struct MetricFamily {
+    kind: &'static str,
+    help: &'static str,
+    // Rendered label pairs (without braces) -> value
+    series: BTreeMap<String, f64>,
+    histograms: BTreeMap<String, Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    // Cumulative counts per HISTOGRAM_BUCKETS bound
+    buckets: [u64; HISTOGRAM_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl MetricFamily {
+    fn new(kind: &'static str, help: &'static str) -> Self {
+        MetricFamily { kind, help, series: BTreeMap::new(), histograms: BTreeMap::new() }
+    }
+}
+
+impl Metrics {
+    fn set_gauge(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name).or_insert_with(|| MetricFamily::new("gauge", help));
+        family.series.insert(render_labels(labels), value);
+    }
+
+    fn inc_counter(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)]) {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name).or_insert_with(|| MetricFamily::new("counter", help));
+        *family.series.entry(render_labels(labels)).or_default() += 1.0;
+    }
+
+    fn retain_series(&self, name: &str, keep: impl Fn(&str) -> bool) {
+        if let Some(family) = self.families.lock().unwrap().get_mut(name) {
+            family.series.retain(|labels, _| keep(labels));
+        }
+    }
+
+    fn observe(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name).or_insert_with(|| MetricFamily::new("histogram", help));
+        let histogram = family.histograms.entry(render_labels(labels)).or_default();
+        for (bound, count) in HISTOGRAM_BUCKETS.iter().zip(histogram.buckets.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            out.push_str(&format!("# HELP {name} {}\n# TYPE {name} {}\n", family.help, family.kind));
+            for (labels, value) in &family.series {
+                out.push_str(&format!("{name}{} {value}\n", wrap_labels(labels, None)));
+            }
+            for (labels, h) in &family.histograms {
+                for (bound, count) in HISTOGRAM_BUCKETS.iter().zip(h.buckets.iter()) {
+                    let le = format!("le=\"{bound}\"");
+                    out.push_str(&format!("{name}_bucket{} {count}\n", wrap_labels(labels, Some(&le))));
+                }
+                let le = "le=\"+Inf\"";
+                out.push_str(&format!("{name}_bucket{} {}\n", wrap_labels(labels, Some(le)), h.count));
+                out.push_str(&format!("{name}_sum{} {}\n", wrap_labels(labels, None), h.sum));
+                out.push_str(&format!("{name}_count{} {}\n", wrap_labels(labels, None), h.count));
+            }
+        }
+        out
+    }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{k}=\"{v}\"")
+        })
+        .collect();
+    pairs.join(",")
+}
+
+fn wrap_labels(labels: &str, extra: Option<&str>) -> String {
+    match (labels.is_empty(), extra) {
+        (true, None) => String::new(),
+        (true, Some(extra)) => format!("{{{extra}}}"),
+        (false, None) => format!("{{{labels}}}"),
+        (false, Some(extra)) => format!("{{{labels},{extra}}}"),
+    }
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+}
+
+// Counted when the error is made, so failures that partialPageMode turns into a partial
+// response are still visible
+fn query_error(class: &'static str, e: impl std::fmt::Display) -> AppError {
+    METRICS.inc_counter(
+        "keyspaces_plugin_query_errors_total",
+        "Failed Keyspaces calls by error class",
+        &[("class", class)]
+    );
+    AppError::Query { class, message: e.to_string() }
+}
+
+// Coarse class of a failed Keyspaces call for logs and metric labels: timeout, unavailable
+// (including throttling), auth, syntax, connection, or other
+fn query_error_class(e: &ExecutionError) -> &'static str {
+    match e {
+        ExecutionError::RequestTimeout(_) => "timeout",
+        ExecutionError::EmptyPlan | ExecutionError::ConnectionPoolError(_) => "connection",
+        ExecutionError::BadQuery(_) => "syntax",
+        ExecutionError::PrepareError(e) => prepare_error_class(e),
+        ExecutionError::LastAttemptError(e) => attempt_error_class(e),
+        _ => "other",
+    }
+}
+
+fn prepare_error_class(e: &PrepareError) -> &'static str {
+    match e {
+        PrepareError::ConnectionPoolError(_) => "connection",
+        PrepareError::AllAttemptsFailed { first_attempt } => attempt_error_class(first_attempt),
+        _ => "other",
+    }
+}
+
+fn attempt_error_class(e: &RequestAttemptError) -> &'static str {
+    match e {
+        RequestAttemptError::DbError(db, _) =>
+            match db {
+                DbError::ReadTimeout { .. } | DbError::WriteTimeout { .. } => "timeout",
+                DbError::Unavailable { .. } |
+                DbError::Overloaded |
+                DbError::IsBootstrapping |
+                DbError::RateLimitReached { .. } => "unavailable",
+                DbError::AuthenticationError | DbError::Unauthorized => "auth",
+                DbError::SyntaxError | DbError::Invalid => "syntax",
+                _ => "other",
+            }
+        RequestAttemptError::BrokenConnectionError(_) | RequestAttemptError::UnableToAllocStreamId => "connection",
+        _ => "other",
+    }
+}
+
+// Delegates every decision to the default policy; only counts errors that look like
+// Keyspaces throttling (timeouts, unavailable, overloaded, rate limits)
+#[derive(Debug)]
+struct ThrottleCountingRetryPolicy {
+    inner: DefaultRetryPolicy,
+    throttles: Arc<AtomicU32>,
+}
+
+impl RetryPolicy for ThrottleCountingRetryPolicy {
+    fn new_session(&self) -> Box<dyn RetrySession> {
+        Box::new(ThrottleCountingRetrySession {
+            inner: self.inner.new_session(),
+            throttles: self.throttles.clone(),
+        })
+    }
+}
+
+struct ThrottleCountingRetrySession {
+    inner: Box<dyn RetrySession>,
+    throttles: Arc<AtomicU32>,
+}
+
+impl RetrySession for ThrottleCountingRetrySession {
+    fn decide_should_retry(&mut self, request_info: RequestInfo) -> RetryDecision {
+        if
+            let RequestAttemptError::DbError(
+                DbError::ReadTimeout { .. } |
+                DbError::WriteTimeout { .. } |
+                DbError::Unavailable { .. } |
+                DbError::Overloaded |
+                DbError::RateLimitReached { .. },
+                _,
+            ) = request_info.error
+        {
+            self.throttles.fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.decide_should_retry(request_info)
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+// `transforms`: `{"<output key>": "<op>" | ["<op>", ...]}`, or the same object JSON-encoded
+// as a string (the form debug route only carries strings)
+fn parse_transforms(
+    value: Option<&serde_json::Value>
+) -> Result<Vec<(String, Vec<Transform>)>, AppError> {
+    let invalid = |why: String| AppError::BadRequest(format!("transforms: {why}"));
+    let object = match value {
+        None => {
+            return Ok(Vec::new());
+        }
+        Some(serde_json::Value::Object(m)) => m.clone(),
+        Some(serde_json::Value::String(s)) =>
+            match serde_json::from_str(s) {
+                Ok(serde_json::Value::Object(m)) => m,
+                _ => {
+                    return Err(invalid("expected a JSON object".to_string()));
+                }
+            }
+        Some(_) => {
+            return Err(invalid("expected a JSON object".to_string()));
+        }
+    };
+
+    let mut out = Vec::new();
+    for (key, ops) in object {
+        let ops: Vec<&serde_json::Value> = match &ops {
+            serde_json::Value::Array(items) => items.iter().collect(),
+            other => vec![other],
+        };
+        let mut chain = Vec::new();
+        for op in ops {
+            let Some(op) = op.as_str() else {
+                return Err(invalid(format!("{key}: operations must be strings")));
+            };
+            chain.push(match op {
+                "lower" => Transform::Lower,
+                "upper" => Transform::Upper,
+                "trim" => Transform::Trim,
+                _ =>
+                    match op.strip_prefix("default:") {
+                        Some(fallback) => Transform::Default(fallback.to_string()),
+                        None => {
+                            return Err(
+                                invalid(
+                                    format!(
+                                        "{key}: unsupported operation {op:?} (expected lower, upper, trim or default:<value>)"
+                                    )
+                                )
+                            );
+                        }
+                    }
+            });
+        }
+        out.push((key, chain));
+    }
+    Ok(out)
+}
+
+// The levels Keyspaces accepts for reads
+const READ_CONSISTENCIES: [&str; 3] = ["LOCAL_QUORUM", "LOCAL_ONE", "ONE"];
+
+fn parse_consistency(value: &str) -> Option<Consistency> {
+    match value.to_ascii_uppercase().as_str() {
+        "LOCAL_QUORUM" => Some(Consistency::LocalQuorum),
+        "LOCAL_ONE" => Some(Consistency::LocalOne),
+        "ONE" => Some(Consistency::One),
+        _ => None,
+    }
+}
+
+// Inverse of `parse_consistency`, so responses use the names requests do
+fn consistency_name(consistency: Consistency) -> &'static str {
+    match consistency {
+        Consistency::LocalOne => "LOCAL_ONE",
+        Consistency::One => "ONE",
+        _ => "LOCAL_QUORUM",
+    }
+}
+
+// Generator parameters arrive as arbitrary JSON; templates often stringify booleans
+fn param_flag(params: &HashMap<String, serde_json::Value>, key: &str) -> bool {
+    match params.get(key) {
+        Some(serde_json::Value::Bool(b)) => *b,
+        Some(serde_json::Value::String(s)) => s.eq_ignore_ascii_case("true"),
+        _ => false,
+    }
+}
+
+// Accepts a JSON array of strings or a comma-separated string; blanks are dropped
+fn param_list(params: &HashMap<String, serde_json::Value>, key: &str) -> Vec<String> {
+    let items: Vec<String> = match params.get(key) {
+        Some(serde_json::Value::Array(a)) =>
+            a
+                .iter()
+                .filter_map(|v| v.as_str())
+                .map(|s| s.to_string())
+                .collect(),
+        Some(serde_json::Value::String(s)) =>
+            s
+                .split(',')
+                .map(|s| s.to_string())
+                .collect(),
+        _ => Vec::new(),
+    };
+    items
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+// Unquoted CQL identifier; anything else is rejected before it reaches a query string
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic()) &&
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_') &&
+        s.len() <= 48
+}
+
+fn env_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|v| v.eq_ignore_ascii_case("true"))
+}
+
+fn json_type_name(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{ Request, StatusCode };
+    use tower::ServiceExt;
+
+    const TEST_TOKEN: &str = "test-token";
+    // Has a PARAM_ALLOWLIST_FILE entry allowing only `filterLabelKey`
+    const RESTRICTED_TOKEN: &str = "restricted-token";
+    // Served through TENANT_SOURCE_FILE; globex is disabled
+    const TENANTS: &str = r#"[
+        {"tenant_id": "acme", "namespace": "tn-acme", "target_cluster": "in-cluster",
+         "repo_url": "https://github.com/yourorg/tenants.git", "repo_path": "tenants/acme",
+         "labels": {"region": "ca-central-1", "tier": "gold"}},
+        {"tenant_id": "initech", "namespace": "tn-initech", "target_cluster": "in-cluster",
+         "repo_url": "https://github.com/yourorg/tenants.git", "repo_path": "tenants/initech",
+         "labels": {"region": "us-east-1"}},
+        {"tenant_id": "globex", "namespace": "tn-globex", "target_cluster": "staging",
+         "repo_url": "https://github.com/yourorg/tenants.git", "repo_path": "tenants/globex",
+         "enabled": false}
+    ]"#;
+
+    // Process-wide state the handlers read, set once: the config the environment gives (no
+    // variables in CI), one accepted token and `TENANTS` as the tenant source
+    fn init() {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            use sha2::Digest;
+            let mut config = Config::from_env().expect("default config");
+            let restricted = hex::encode(sha2::Sha256::digest(RESTRICTED_TOKEN));
+            config.param_allowlist = ParamAllowlist(
+                BTreeMap::from([(restricted, BTreeSet::from(["filterLabelKey".to_string()]))])
+            );
+            CONFIG.set(config).ok();
+            SAVED.set(SavedResponses::load(None)).ok();
+            *TOKENS.write().unwrap() = vec![TEST_TOKEN.to_string(), RESTRICTED_TOKEN.to_string()];
+            let path = env::temp_dir().join(format!("keyspaces-tenant-gen-test-{}.json", std::process::id()));
+            fs::write(&path, TENANTS).expect("write tenant fixture");
+            let source = FileSource::load(path.to_str().expect("UTF-8 temp path")).expect("tenant fixture");
+            SOURCE.set(Box::new(source)).ok();
+        });
+    }
+
+    fn generator_request(path: &str, parameters: serde_json::Value) -> Request<Body> {
+        Request::post(path)
+            .header("content-type", "application/json")
+            .header("authorization", format!("Bearer {TEST_TOKEN}"))
+            .body(Body::from(serde_json::json!({ "input": { "parameters": parameters } }).to_string()))
+            .expect("valid request")
+    }
+
+    async fn call(app: Router, request: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+        init();
+        let response = app.oneshot(request).await.expect("infallible router");
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.expect("buffered body");
+        (parts.status, parts.headers, body.to_vec())
+    }
+
+    fn json(body: &[u8]) -> serde_json::Value {
+        serde_json::from_slice(body).expect("JSON body")
+    }
+
+    fn tenant_ids(body: &serde_json::Value) -> Vec<&str> {
+        body["output"]["parameters"]
+            .as_array()
+            .expect("parameters array")
+            .iter()
+            .filter_map(|e| e["tenantId"].as_str())
+            .collect()
+    }
+
+    fn options(parameters: serde_json::Value) -> Result<RequestOptions, AppError> {
+        init();
+        let parameters: HashMap<String, serde_json::Value> = serde_json::from_value(parameters).expect("an object");
+        RequestOptions::from_parameters(&parameters)
+    }
+
+    #[tokio::test]
+    async fn endpoint_responds_under_route_prefix() {
+        let app = || router("/plugins/keyspaces");
+        let (status, _, body) = call(
+            app(),
+            generator_request("/plugins/keyspaces/api/v1/getparams.execute", serde_json::json!({}))
+        ).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tenant_ids(&json(&body)), ["acme", "initech"]);
+
+        let (status, _, _) = call(app(), generator_request("/api/v1/getparams.execute", serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let request = Request::get("/plugins/keyspaces/openapi.json").body(Body::empty()).unwrap();
+        assert_eq!(call(app(), request).await.0, StatusCode::OK);
+    }
+
+    #[test]
+    fn route_prefix_is_validated() {
+        assert_eq!(route_prefix("").unwrap(), "");
+        assert_eq!(route_prefix("/plugins/keyspaces/").unwrap(), "/plugins/keyspaces");
+        assert!(route_prefix("plugins").is_err());
+        assert!(route_prefix("/plugins/*").is_err());
+        assert!(route_prefix("/plugins/{id}").is_err());
+    }
+
+    // Null and non-integer values leave `syncWave` out
+    #[test]
+    fn sync_wave_column_values() {
+        let wave = |value| sync_wave_column(value, "sync_wave").expect("never an error for these");
+        assert_eq!(wave(Some(CqlValue::Int(5))), Some(5));
+        assert_eq!(wave(Some(CqlValue::BigInt(-2))), Some(-2));
+        assert_eq!(wave(Some(CqlValue::Text(" -1 ".to_string()))), Some(-1));
+        assert_eq!(wave(Some(CqlValue::Text("soon".to_string()))), None);
+        assert_eq!(wave(None), None);
+    }
+
+    #[test]
+    fn readiness_query_must_be_one_read_only_select() {
+        assert_eq!(read_only_query("SELECT now() FROM system.local").as_deref(), Ok("SELECT now() FROM system.local"));
+        assert_eq!(read_only_query("  select key from system.local;  ").as_deref(), Ok("select key from system.local"));
+        for rejected in [
+            "SELECT",
+            "INSERT INTO t (k) VALUES (1)",
+            "SELECT 1 FROM t; DROP TABLE t",
+            "SELECT k FROM t WHERE k = ?",
+            "selected",
+        ] {
+            assert!(read_only_query(rejected).is_err(), "{rejected}");
+        }
+    }
+
+    // X-CQL-Query only with DEBUG_ECHO_QUERY, and only when a query ran
+    #[test]
+    fn echo_queries_only_when_enabled() {
+        let queries = [
+            "SELECT tenant_id FROM ks.a WHERE enabled = true ALLOW FILTERING".to_string(),
+            "SELECT tenant_id FROM ks.b WHERE target_cluster IN ? AND enabled = true ALLOW FILTERING".to_string(),
+        ];
+        let echoed = |queries: &[String], enabled| {
+            let mut response = ().into_response();
+            echo_queries(&mut response, queries, enabled);
+            response.headers().get("x-cql-query").map(|v| v.to_str().unwrap().to_string())
+        };
+        assert_eq!(echoed(&queries, true), Some(queries.join("; ")));
+        assert_eq!(echoed(&queries, false), None);
+        assert_eq!(echoed(&[], true), None);
+    }
+
+    // The string form is what the form debug route and stringifying templates send
+    #[test]
+    fn tenant_id_lists_accept_arrays_and_comma_strings() {
+        let opts = options(serde_json::json!({
+            "includeTenantIds": ["acme", " globex ", ""],
+            "excludeTenantIds": "initech, ,umbrella",
+        })).unwrap();
+        assert_eq!(opts.include_tenant_ids, ["acme", "globex"].map(String::from).into());
+        assert_eq!(opts.exclude_tenant_ids, ["initech", "umbrella"].map(String::from).into());
+        assert!(options(serde_json::json!({})).unwrap().include_tenant_ids.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tenant_id_filters_apply_to_the_endpoint() {
+        let parameters = serde_json::json!({ "includeTenantIds": "acme,initech", "excludeTenantIds": ["initech"] });
+        let (status, _, body) = call(router(""), generator_request("/api/v1/getparams.execute", parameters)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tenant_ids(&json(&body)), ["acme"]);
+    }
+
+    // What `f` logs at debug and above, without colors
+    fn logs(f: impl FnOnce()) -> String {
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for Buffer {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(bytes);
+                Ok(bytes.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber
+            ::fmt()
+            .with_ansi(false)
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+        String::from_utf8(buffer.0.lock().unwrap().clone()).expect("UTF-8 logs")
+    }
+
+    // The scan time is injected, as a delayed Keyspaces would report it
+    #[test]
+    fn slow_query_logs_at_the_threshold() {
+        let mut config = Config::from_env().expect("default config");
+        config.slow_query_ms = 500;
+        let opts = RequestOptions {
+            filter_label: Some(("region".to_string(), "ca-central-1".to_string())),
+            ..Default::default()
+        };
+        let stats = ScanStats { rows_scanned: 1200, pages: 3, ..Default::default() };
+        let slow = |elapsed, config: &Config| logs(|| warn_slow_query(config, elapsed, &opts, &stats));
+
+        let line = slow(Duration::from_millis(750), &config);
+        assert!(line.contains("WARN"), "{line}");
+        assert!(line.contains("slow keyspaces query"), "{line}");
+        for field in ["duration_ms=750", "threshold_ms=500", "rows=1200", "pages=3", "filter_label_key=\"region\""] {
+            assert!(line.contains(field), "{field} missing from {line}");
+        }
+        assert!(!slow(Duration::from_millis(500), &config).is_empty());
+        assert!(slow(Duration::from_millis(499), &config).is_empty());
+
+        config.slow_query_ms = 0;
+        assert!(slow(Duration::from_secs(60), &config).is_empty());
+    }
+
+    #[test]
+    fn promoted_columns_are_validated() {
+        let mapped = ["sync_wave".to_string()];
+        assert_eq!(promoted_columns("", &mapped).unwrap(), Vec::<String>::new());
+        assert_eq!(promoted_columns(" Tier, replicas,,tier ", &mapped).unwrap(), ["tier", "replicas"]);
+        for invalid in ["9lives", "tier-name", "tier;drop", "\"tier\"", "namespace", "labels", "sync_wave"] {
+            assert!(promoted_columns(invalid, &mapped).is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn etag_is_stable_and_honors_if_none_match() {
+        let request = |if_none_match: Option<&str>| {
+            let mut request = generator_request("/api/v1/getparams.execute", serde_json::json!({}));
+            if let Some(tag) = if_none_match {
+                request.headers_mut().insert("if-none-match", tag.parse().unwrap());
+            }
+            request
+        };
+        let (status, headers, body) = call(router(""), request(None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let etag = headers["etag"].to_str().unwrap().to_string();
+        let (_, again, again_body) = call(router(""), request(None)).await;
+        assert_eq!(again["etag"], etag.as_str());
+        assert_eq!(again_body, body);
+
+        for matching in [etag.clone(), format!("W/{etag}"), format!("\"other\", {etag}"), "*".to_string()] {
+            let (status, headers, body) = call(router(""), request(Some(&matching))).await;
+            assert_eq!(status, StatusCode::NOT_MODIFIED, "{matching}");
+            assert_eq!(headers["etag"], etag.as_str());
+            assert!(body.is_empty());
+        }
+        let (status, _, _) = call(router(""), request(Some("\"other\""))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    // Each HashMap iterates in its own random order; the bytes must not follow it
+    #[test]
+    fn serialization_is_stable_across_identical_inputs() {
+        let entry = |keys: Vec<String>| keys.into_iter().map(|k| (k.clone(), serde_json::Value::from(k))).collect();
+        let keys: Vec<String> = (0..32).map(|i| format!("key{i}")).collect();
+        let forward: HashMap<String, serde_json::Value> = entry(keys.clone());
+        let backward: HashMap<String, serde_json::Value> = entry(keys.into_iter().rev().collect());
+        assert_eq!(to_json_bytes(&vec![forward]).unwrap(), to_json_bytes(&vec![backward]).unwrap());
+    }
+
+    fn headers(entries: &[(&str, &[u8])]) -> HeaderMap {
+        entries
+            .iter()
+            .map(|(name, value)| {
+                let name = axum::http::HeaderName::from_bytes(name.as_bytes()).unwrap();
+                (name, axum::http::HeaderValue::from_bytes(value).unwrap())
+            })
+            .collect()
+    }
+
+    fn auth_config(header_name: &str, scheme: &str) -> Config {
+        let mut config = Config::from_env().expect("default config");
+        config.auth_header_name = axum::http::HeaderName::from_bytes(header_name.as_bytes()).unwrap();
+        config.auth_scheme = scheme.to_string();
+        config
+    }
+
+    #[test]
+    fn custom_auth_header_and_scheme() {
+        use sha2::Digest;
+        let tokens = [TEST_TOKEN.to_string()];
+        let caller = Caller::Token(hex::encode(sha2::Sha256::digest(TEST_TOKEN)));
+        let config = auth_config("X-Plugin-Auth", "Token");
+        let check = |entries: &[(&str, &[u8])], config: &Config| check_token(&headers(entries), config, &tokens);
+
+        assert_eq!(check(&[("x-plugin-auth", b"Token test-token")], &config).unwrap(), caller);
+        assert_eq!(check(&[("x-plugin-auth", b"token test-token")], &config).unwrap(), caller);
+        assert!(matches!(check(&[("authorization", b"Bearer test-token")], &config), Err(AppError::Unauthorized)));
+        assert!(matches!(check(&[("x-plugin-auth", b"Token TEST-TOKEN")], &config), Err(AppError::Unauthorized)));
+
+        // No scheme: the header value is the bare token
+        let bare = auth_config("X-Plugin-Auth", "");
+        assert_eq!(check(&[("x-plugin-auth", b"test-token")], &bare).unwrap(), caller);
+        assert!(matches!(check(&[("x-plugin-auth", b"Token test-token")], &bare), Err(AppError::Unauthorized)));
+
+        let default = auth_config("Authorization", "Bearer");
+        assert_eq!(check(&[("authorization", b"Bearer test-token")], &default).unwrap(), caller);
+    }
+
+    #[test]
+    fn transforms_parse_from_an_object_or_its_json_string() {
+        let parse = |value: serde_json::Value| parse_transforms(Some(&value));
+        let mut parsed = parse(serde_json::json!({"namespace": "lower", "tier": ["trim", "default:bronze"]})).unwrap();
+        parsed.sort_by(|a, b| a.0.cmp(&b.0));
+        let expected = vec![
+            ("namespace".to_string(), vec![Transform::Lower]),
+            ("tier".to_string(), vec![Transform::Trim, Transform::Default("bronze".to_string())])
+        ];
+        assert_eq!(parsed, expected);
+        assert_eq!(parse(serde_json::json!(r#"{"namespace": "lower"}"#)).unwrap(), expected[..1]);
+        assert_eq!(parse(serde_json::json!({"path": "default:"})).unwrap()[0].1, [Transform::Default(String::new())]);
+        assert!(parse_transforms(None).unwrap().is_empty());
+
+        for invalid in [
+            serde_json::json!({"namespace": "reverse"}),
+            serde_json::json!({"namespace": ["lower", 1]}),
+            serde_json::json!(["lower"]),
+            serde_json::json!("lower")
+        ] {
+            assert!(matches!(parse(invalid.clone()), Err(AppError::BadRequest(_))), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ndjson_lines_parse_independently() {
+        let mut request = generator_request("/api/v1/getparams.execute", serde_json::json!({}));
+        request.headers_mut().insert("accept", "application/x-ndjson".parse().unwrap());
+        let (status, headers, body) = call(router(""), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-type"], "application/x-ndjson");
+        assert_eq!(body.last(), Some(&b'\n'));
+        let lines: Vec<serde_json::Value> = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(json)
+            .collect();
+        let ids: Vec<&str> = lines.iter().filter_map(|l| l["tenantId"].as_str()).collect();
+        assert_eq!(ids, ["acme", "initech"]);
+        assert!(lines.iter().all(|l| l.is_object() && l.get("output").is_none()));
+    }
+
+    #[test]
+    fn node_address_uses_the_configured_port() {
+        let primary = |region: &str, port| node_address(&format!("cassandra.{region}.amazonaws.com"), port);
+        assert_eq!(primary("us-east-1", DEFAULT_KEYSPACES_PORT), "cassandra.us-east-1.amazonaws.com:9142");
+        assert_eq!(primary("eu-west-1", 9443), "cassandra.eu-west-1.amazonaws.com:9443");
+        // KEYSPACES_READ_ENDPOINT without a port takes KEYSPACES_PORT; with one, keeps it
+        let endpoint = "vpce-0a1b.cassandra.us-east-1.vpce.amazonaws.com";
+        assert_eq!(node_address(endpoint, 443), format!("{endpoint}:443"));
+        assert_eq!(node_address("keyspaces.internal:9000", 9443), "keyspaces.internal:9000");
+    }
+
+    // Still a plain 403, with the reason logged but never the header value
+    #[test]
+    fn malformed_auth_headers_are_rejected() {
+        let config = auth_config("Authorization", "Bearer");
+        let tokens = [TEST_TOKEN.to_string()];
+        for (value, reason) in [
+            (&b"Bearer test-token\xff"[..], "not valid UTF-8"),
+            (b"Basic test-token", "wrong scheme"),
+            (b"test-token", "missing \"Bearer\" scheme prefix"),
+            (b"Bearer wrong-token", "token mismatch")
+        ] {
+            let mut result = None;
+            let logged = logs(|| result = Some(check_token(&headers(&[("authorization", value)]), &config, &tokens)));
+            assert!(matches!(result, Some(Err(AppError::Unauthorized))), "{value:?}");
+            assert!(logged.contains(reason), "{reason}: {logged}");
+            assert!(!logged.contains("test-token") && !logged.contains("wrong-token"), "{logged}");
+        }
+        assert_eq!(AppError::Unauthorized.into_response().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn unknown_routes_are_404_and_bad_tokens_403() {
+        let error_code = |body: &[u8]| json(body)["error"]["code"].as_str().map(str::to_string);
+
+        for request in [
+            generator_request("/api/v1/unknown.execute", serde_json::json!({})),
+            Request::get("/nope").body(Body::empty()).unwrap()
+        ] {
+            let (status, _, body) = call(router(""), request).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(error_code(&body).as_deref(), Some("not_found"));
+        }
+
+        let mut wrong_token = generator_request("/api/v1/getparams.execute", serde_json::json!({}));
+        wrong_token.headers_mut().insert("authorization", "Bearer wrong-token".parse().unwrap());
+        let mut no_token = generator_request("/api/v1/getparams.execute", serde_json::json!({}));
+        no_token.headers_mut().remove("authorization");
+        for request in [wrong_token, no_token] {
+            let (status, _, body) = call(router(""), request).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert_eq!(error_code(&body).as_deref(), Some("forbidden"));
+        }
+    }
+
+    // Defaults to true: only an explicit false drops the object
+    #[test]
+    fn include_params_object_parameter() {
+        let omitted = |parameters| options(parameters).unwrap().omit_params_object;
+        assert!(!omitted(serde_json::json!({})));
+        assert!(!omitted(serde_json::json!({"includeParamsObject": true})));
+        assert!(omitted(serde_json::json!({"includeParamsObject": false})));
+        assert!(omitted(serde_json::json!({"includeParamsObject": "false"})));
+    }
+
+    // Before PLUGIN_TOKEN_FILE is read: a retryable 503, even for a header that would match
+    #[test]
+    fn token_not_loaded_is_unavailable() {
+        let config = auth_config("Authorization", "Bearer");
+        for value in [&b"Bearer test-token"[..], b"Bearer ", b""] {
+            let result = check_token(&headers(&[("authorization", value)]), &config, &[]);
+            assert!(matches!(result, Err(AppError::Unavailable(_))), "{value:?}");
+        }
+        assert!(matches!(check_token(&HeaderMap::new(), &config, &[]), Err(AppError::Unavailable(_))));
+        let response = AppError::Unavailable("plugin token not loaded").into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    // A blank token file leaves nothing loaded, which the check above turns into a 503
+    #[test]
+    fn tokens_parse_one_per_line() {
+        assert!(parse_tokens("").is_empty());
+        assert!(parse_tokens("\n  \n").is_empty());
+        assert_eq!(parse_tokens("tok-a\n  tok-b \n\ntok-c"), ["tok-a", "tok-b", "tok-c"]);
+    }
+
+    // 100 tenants with a 1 KiB value each, about 110 KiB serialized
+    fn oversized_response() -> PluginResponse {
+        let parameters = (0..100)
+            .map(|i| {
+                HashMap::from([
+                    ("tenantId".to_string(), serde_json::Value::from(format!("tenant-{i:03}"))),
+                    ("blob".to_string(), serde_json::Value::from("x".repeat(1024)))
+                ])
+            })
+            .collect();
+        PluginResponse { output: Output { parameters, count: None }, meta: None, delta: None }
+    }
+
+    #[test]
+    fn max_response_bytes_fails_or_truncates() {
+        let resp = oversized_response();
+        let full = to_json_bytes(&resp).unwrap();
+
+        assert!(matches!(capped_body(&resp, 10_000, MaxResponseBytesMode::Fail), Err(AppError::Internal(_))));
+        let (body, capped) = capped_body(&resp, 10_000, MaxResponseBytesMode::Truncate).unwrap();
+        assert!(capped);
+        assert!(body.len() <= 10_000, "{}", body.len());
+        let body = json(&body);
+        let ids = tenant_ids(&body);
+        assert!(!ids.is_empty() && ids.len() < 100, "{}", ids.len());
+        assert_eq!(ids[0], "tenant-000");
+
+        // Exact accounting: the full body fits a cap of its own length, one byte less does not
+        assert_eq!(capped_body(&resp, full.len(), MaxResponseBytesMode::Fail).unwrap(), (full.clone(), false));
+        let (cut, capped) = capped_body(&resp, full.len() - 1, MaxResponseBytesMode::Truncate).unwrap();
+        assert!(capped);
+        assert_eq!(tenant_ids(&json(&cut)).len(), 99);
+    }
+
+    // A delta can't drop entries, and `includeMeta` records the truncation
+    #[test]
+    fn max_response_bytes_with_delta_and_meta() {
+        let delta = PluginResponse {
+            delta: Some(serde_json::json!({"snapshot": "s1", "removed": []})),
+            ..oversized_response()
+        };
+        assert!(matches!(capped_body(&delta, 10_000, MaxResponseBytesMode::Truncate), Err(AppError::Internal(_))));
+
+        let with_meta = PluginResponse { meta: Some(serde_json::json!({"rowsScanned": 100})), ..oversized_response() };
+        let (body, _) = capped_body(&with_meta, 10_000, MaxResponseBytesMode::Truncate).unwrap();
+        assert_eq!(json(&body)["meta"], serde_json::json!({"rowsScanned": 100, "truncated": true}));
+    }
+
+    fn regex_filter(value: &str) -> Result<RequestOptions, AppError> {
+        options(serde_json::json!({"filterMode": "regex", "filterLabelKey": "region", "filterLabelValue": value}))
+    }
+
+    // Anchored, so the pattern has to match the whole label value
+    #[test]
+    fn regex_filter_patterns() {
+        let re = regex_filter("us-(east|west)-[0-9]").unwrap().filter_regex.expect("compiled");
+        assert!(re.is_match("us-east-1"));
+        assert!(!re.is_match("us-east-10"));
+        assert!(!re.is_match("xus-east-1"));
+
+        let error = |value: &str| match regex_filter(value) {
+            Err(AppError::BadRequest(message)) => message,
+            other => panic!("{value:?}: expected a 400, got {other:?}"),
+        };
+        assert!(error("us-(east").contains("invalid regex"), "{}", error("us-(east"));
+        assert!(error(&"a".repeat(DEFAULT_REGEX_MAX_LEN + 1)).contains("over REGEX_MAX_LEN"));
+        assert!(error(r"\w{1000}{1000}").contains("too complex"), "{}", error(r"\w{1000}{1000}"));
+        // `exact` takes the same value literally
+        let exact = options(serde_json::json!({"filterLabelKey": "region", "filterLabelValue": "us-(east"})).unwrap();
+        assert!(exact.filter_regex.is_none());
+    }
+
+    #[test]
+    fn pattern_cache_evicts_the_least_recently_used() {
+        let mut cache = PatternCache::default();
+        let re = |p: &str| regex::Regex::new(p).unwrap();
+        cache.insert("a".to_string(), re("a"), 2);
+        cache.insert("b".to_string(), re("b"), 2);
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), re("c"), 2);
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[tokio::test]
+    async fn regex_filters_apply_to_the_endpoint() {
+        let parameters = serde_json::json!({
+            "filterMode": "regex",
+            "filterLabelKey": "region",
+            "excludeLabelValue": "ca-.*"
+        });
+        let (status, _, body) = call(router(""), generator_request("/api/v1/getparams.execute", parameters)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tenant_ids(&json(&body)), ["initech"]);
+
+        let invalid = serde_json::json!({"filterMode": "regex", "filterLabelKey": "region", "filterLabelValue": "("});
+        let (status, _, _) = call(router(""), generator_request("/api/v1/getparams.execute", invalid)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn query_errors_map_to_classes() {
+        use scylla::errors::{ BadQuery, ConnectionPoolError };
+        let db = |error: DbError| ExecutionError::LastAttemptError(RequestAttemptError::DbError(error, String::new()));
+        let read_timeout = DbError::ReadTimeout {
+            consistency: Consistency::LocalQuorum,
+            received: 1,
+            required: 2,
+            data_present: false,
+        };
+        let unavailable = DbError::Unavailable { consistency: Consistency::LocalQuorum, required: 2, alive: 1 };
+        for (error, class) in [
+            (ExecutionError::RequestTimeout(Duration::from_secs(5)), "timeout"),
+            (db(read_timeout), "timeout"),
+            (db(unavailable), "unavailable"),
+            (db(DbError::Overloaded), "unavailable"),
+            (db(DbError::IsBootstrapping), "unavailable"),
+            (db(DbError::AuthenticationError), "auth"),
+            (db(DbError::Unauthorized), "auth"),
+            (db(DbError::SyntaxError), "syntax"),
+            (db(DbError::Invalid), "syntax"),
+            (db(DbError::ServerError), "other"),
+            (ExecutionError::BadQuery(BadQuery::PartitionKeyExtraction), "syntax"),
+            (ExecutionError::EmptyPlan, "connection"),
+            (ExecutionError::ConnectionPoolError(ConnectionPoolError::Initializing), "connection"),
+            (ExecutionError::LastAttemptError(RequestAttemptError::UnableToAllocStreamId), "connection"),
+            (
+                ExecutionError::PrepareError(PrepareError::AllAttemptsFailed {
+                    first_attempt: RequestAttemptError::DbError(DbError::Unauthorized, String::new()),
+                }),
+                "auth",
+            ),
+            (
+                ExecutionError::PrepareError(PrepareError::ConnectionPoolError(ConnectionPoolError::Initializing)),
+                "connection",
+            ),
+            (ExecutionError::PrepareError(PrepareError::PreparedStatementIdsMismatch), "other")
+        ] {
+            assert_eq!(query_error_class(&error), class, "{error}");
+        }
+    }
+
+    // The class is for logs and metrics; the client sees the same generic 500 as before
+    #[tokio::test]
+    async fn query_errors_stay_generic_for_clients() {
+        let error = AppError::Query { class: "timeout", message: "ReadTimeout on tenant_ops.tenant_configs".to_string() };
+        assert_eq!(error.query_class(), Some("timeout"));
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(json(&body), serde_json::json!({"error": {"code": "internal", "message": "internal error"}}));
+    }
+
+    #[test]
+    fn timestamp_format_parameter() {
+        let format = |parameters| options(parameters).map(|o| o.timestamp_format);
+        assert_eq!(format(serde_json::json!({})).unwrap(), TimestampFormat::Rfc3339);
+        assert_eq!(format(serde_json::json!({"timestampFormat": "epochMillis"})).unwrap(), TimestampFormat::EpochMillis);
+        assert_eq!(format(serde_json::json!({"timestampFormat": "date"})).unwrap(), TimestampFormat::Date);
+        assert!(matches!(format(serde_json::json!({"timestampFormat": "unix"})), Err(AppError::BadRequest(_))));
+    }
+
+    // Rejected by count or size ahead of auth, so even a request without a token gets the 431
+    #[tokio::test]
+    async fn oversized_headers_are_rejected() {
+        let request = || generator_request("/api/v1/getparams.execute", serde_json::json!({}));
+        let mut too_large = request();
+        too_large.headers_mut().insert("x-padding", "a".repeat(DEFAULT_MAX_REQUEST_HEADER_BYTES).parse().unwrap());
+        let mut too_many = request();
+        for i in 0..DEFAULT_MAX_REQUEST_HEADERS {
+            too_many.headers_mut().insert(
+                axum::http::HeaderName::try_from(format!("x-extra-{i}")).unwrap(),
+                "1".parse().unwrap()
+            );
+        }
+        let mut no_token = request();
+        no_token.headers_mut().remove("authorization");
+        no_token.headers_mut().insert("x-padding", "a".repeat(DEFAULT_MAX_REQUEST_HEADER_BYTES).parse().unwrap());
+
+        for request in [too_large, too_many, no_token] {
+            let (status, _, body) = call(router(""), request).await;
+            assert_eq!(status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+            assert_eq!(json(&body)["error"]["code"], "headers_too_large");
+        }
+        assert!(METRICS.render().contains("keyspaces_plugin_rejected_header_requests_total{reason=\"size\"}"));
+        assert!(METRICS.render().contains("keyspaces_plugin_rejected_header_requests_total{reason=\"count\"}"));
+
+        let mut within = request();
+        within.headers_mut().insert("x-padding", "a".repeat(1024).parse().unwrap());
+        assert_eq!(call(router(""), within).await.0, StatusCode::OK);
+    }
+
+    // Every integer width and integer text; a non-integer column type is a schema error
+    #[test]
+    fn sync_wave_column_types() {
+        let wave = |value| sync_wave_column(Some(value), "sync_wave");
+        assert_eq!(wave(CqlValue::TinyInt(-3)).unwrap(), Some(-3));
+        assert_eq!(wave(CqlValue::SmallInt(300)).unwrap(), Some(300));
+        assert_eq!(wave(CqlValue::Ascii("7".to_string())).unwrap(), Some(7));
+        assert_eq!(wave(CqlValue::Text("1.5".to_string())).unwrap(), None);
+        assert_eq!(wave(CqlValue::Text(String::new())).unwrap(), None);
+        assert!(matches!(wave(CqlValue::Boolean(true)), Err(AppError::Internal(_))));
+    }
+
+    #[test]
+    fn annotations_column_values() {
+        let text = |s: &str| CqlValue::Text(s.to_string());
+        let map = CqlValue::Map(vec![(text("argocd.argoproj.io/sync-options"), text("Prune=false"))]);
+        assert_eq!(
+            text_map_column(Some(map), "annotations").unwrap(),
+            Some(HashMap::from([("argocd.argoproj.io/sync-options".to_string(), "Prune=false".to_string())]))
+        );
+        assert_eq!(text_map_column(None, "annotations").unwrap(), None);
+        let not_text = CqlValue::Map(vec![(text("replicas"), CqlValue::Int(3))]);
+        assert!(matches!(text_map_column(Some(not_text), "annotations"), Err(AppError::Internal(_))));
+        assert!(matches!(text_map_column(Some(text("a=b")), "annotations"), Err(AppError::Internal(_))));
+    }
+
+    #[test]
+    fn app_name_collisions_fail_with_409() {
+        let entry = |tenant_id: &str, cluster: &str, app_name: &str| {
+            HashMap::from([
+                ("tenantId".to_string(), serde_json::Value::from(tenant_id)),
+                ("cluster".to_string(), serde_json::Value::from(cluster)),
+                ("appName".to_string(), serde_json::Value::from(app_name))
+            ])
+        };
+        let distinct = [entry("acme", "in-cluster", "acme-in-cluster"), entry("acme", "staging", "acme-staging")];
+        assert!(check_app_names(&distinct).is_ok());
+
+        let colliding = [
+            entry("acme", "in-cluster", "acme-in-cluster"),
+            entry("globex", "in-cluster", "globex-in-cluster"),
+            entry("Acme", "in-cluster", "acme-in-cluster")
+        ];
+        let error = check_app_names(&colliding).unwrap_err();
+        assert_eq!(error.to_string(), "conflict: appName collisions: acme-in-cluster (Acme/in-cluster, acme/in-cluster)");
+        assert_eq!(error.into_response().status(), StatusCode::CONFLICT);
+    }
+
+    fn entry_with_path(tenant_id: &str, path: &str) -> HashMap<String, serde_json::Value> {
+        HashMap::from([
+            ("tenantId".to_string(), serde_json::Value::from(tenant_id)),
+            ("repoURL".to_string(), serde_json::Value::from("https://github.com/yourorg/tenants.git")),
+            ("path".to_string(), serde_json::Value::from(path))
+        ])
+    }
+
+    // `missingFieldMode=fail`: a row missing repo_path is a 422 naming the tenant
+    #[test]
+    fn missing_required_fields_fail_with_422() {
+        init();
+        assert!(check_required_fields(&[entry_with_path("acme", "tenants/acme")]).is_ok());
+
+        let entries = [entry_with_path("acme", ""), entry_with_path("globex", "tenants/globex")];
+        let error = check_required_fields(&entries).unwrap_err();
+        assert_eq!(error.to_string(), "unprocessable: tenants missing required fields: acme (repo_path)");
+        assert_eq!(error.into_response().status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let many: Vec<_> = (0..MAX_LISTED_OFFENDERS + 5).map(|i| entry_with_path(&format!("t{i}"), "")).collect();
+        let message = check_required_fields(&many).unwrap_err().to_string();
+        assert!(message.ends_with("t19 (repo_path); and 5 more"), "{message}");
+    }
+
+    // The project column reads as present text or null; the fallback itself is applied in
+    // the pipeline
+    #[test]
+    fn project_column_values() {
+        let project = |value| optional_text_column(value, "argocd_project");
+        assert_eq!(project(Some(CqlValue::Text("payments".to_string()))).unwrap(), Some("payments".to_string()));
+        assert_eq!(project(None).unwrap(), None);
+        assert!(matches!(project(Some(CqlValue::Int(1))), Err(AppError::Internal(_))));
+
+        // Off without EMIT_PROJECT, and ArgoCD's built-in project once it is on
+        init();
+        let config = CONFIG.get().unwrap();
+        assert_eq!(config.project_column, None);
+        assert_eq!(config.default_project, DEFAULT_PROJECT);
+        assert_eq!(render_settings().default_project, None);
+    }
+
+    // Generated by tests/fixtures/tls/generate.sh
+    fn tls_fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/tls/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    // HTTPS with SERVER_CLIENT_CA on an ephemeral port, served the way `serve` does
+    async fn serve_tls(mode: ClientAuthMode) -> SocketAddr {
+        init();
+        let (cert, key, ca) = (tls_fixture("server.pem"), tls_fixture("server-key.pem"), tls_fixture("ca.pem"));
+        let tls = ServerTls { cert: &cert, key: &key, client_ca: Some(&ca), client_auth_mode: mode };
+        let server_tls = tls::server_config(&tls).expect("fixture server config");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = client_cert_layer(router(""), true, mode);
+        tokio::spawn(async move {
+            axum::serve(TlsListener::new(listener, server_tls), app.into_make_service_with_connect_info::<Peer>()).await
+        });
+        addr
+    }
+
+    // The status of one getparams call, or None when the TLS layer turned the client away
+    async fn tls_call(addr: SocketAddr, client: Option<&str>, token: Option<&str>) -> Option<u16> {
+        use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+        let mut roots = RootCertStore::empty();
+        let ca = fs::read(tls_fixture("ca.pem")).unwrap();
+        roots.add_parsable_certificates(rustls_pemfile::certs(&mut ca.as_slice()).map(Result::unwrap));
+        let builder = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = match client {
+            None => builder.with_no_client_auth(),
+            Some(name) => {
+                let cert = fs::read(tls_fixture(&format!("{name}.pem"))).unwrap();
+                let key = fs::read(tls_fixture(&format!("{name}-key.pem"))).unwrap();
+                let chain = rustls_pemfile::certs(&mut cert.as_slice()).map(Result::unwrap).collect();
+                let key = rustls_pemfile::private_key(&mut key.as_slice()).unwrap().unwrap();
+                builder.with_client_auth_cert(chain, key).unwrap()
+            }
+        };
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        // With TLS 1.3 the client finishes its side first; a rejected certificate shows up as
+        // an alert on the first read or write
+        let mut stream = tokio_rustls::TlsConnector
+            ::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp).await
+            .ok()?;
+        let body = serde_json::json!({ "input": { "parameters": {} } }).to_string();
+        let authorization = token.map(|t| format!("authorization: Bearer {t}\r\n")).unwrap_or_default();
+        let request = format!(
+            "POST /api/v1/getparams.execute HTTP/1.1\r\nhost: localhost\r\ncontent-type: application/json\r\n\
+             content-length: {}\r\nconnection: close\r\n{authorization}\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.ok()?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.ok()?;
+        let status_line = String::from_utf8_lossy(&response).lines().next()?.to_string();
+        status_line.split(' ').nth(1)?.parse().ok()
+    }
+
+    #[tokio::test]
+    async fn client_certificates_both_mode() {
+        let addr = serve_tls(ClientAuthMode::Both).await;
+        assert_eq!(tls_call(addr, Some("client"), Some(TEST_TOKEN)).await, Some(200));
+        // The certificate does not replace the token
+        assert_eq!(tls_call(addr, Some("client"), None).await, Some(403));
+        assert_eq!(tls_call(addr, None, Some(TEST_TOKEN)).await, None);
+        assert_eq!(tls_call(addr, Some("other-client"), Some(TEST_TOKEN)).await, None);
+    }
+
+    #[tokio::test]
+    async fn client_certificates_cert_mode() {
+        let addr = serve_tls(ClientAuthMode::Cert).await;
+        assert_eq!(tls_call(addr, Some("client"), None).await, Some(200));
+        assert_eq!(tls_call(addr, None, Some(TEST_TOKEN)).await, None);
+        assert_eq!(tls_call(addr, Some("other-client"), None).await, None);
+    }
+
+    #[tokio::test]
+    async fn client_certificates_either_mode() {
+        let addr = serve_tls(ClientAuthMode::Either).await;
+        assert_eq!(tls_call(addr, Some("client"), None).await, Some(200));
+        assert_eq!(tls_call(addr, None, Some(TEST_TOKEN)).await, Some(200));
+        assert_eq!(tls_call(addr, None, None).await, Some(403));
+        // A certificate that is presented still has to verify
+        assert_eq!(tls_call(addr, Some("other-client"), None).await, None);
+    }
+
+    // globex is disabled on staging: dropped rows still count as scanned, and as disabled
+    #[tokio::test]
+    async fn disabled_rows_are_counted_per_cluster() {
+        let meta = |include_disabled: Option<&str>| {
+            let mut parameters = serde_json::json!({"includeMeta": true});
+            if let Some(clusters) = include_disabled {
+                parameters["includeDisabledForClusters"] = clusters.into();
+            }
+            call(router(""), generator_request("/api/v1/getparams.execute", parameters))
+        };
+        for (clusters, ids, disabled) in [
+            (None, &["acme", "initech"][..], 1),
+            (Some("in-cluster"), &["acme", "initech"][..], 1),
+            (Some("staging"), &["acme", "initech", "globex"][..], 0),
+            (Some("in-cluster,staging"), &["acme", "initech", "globex"][..], 0)
+        ] {
+            let (status, _, body) = meta(clusters).await;
+            assert_eq!(status, StatusCode::OK, "{clusters:?}");
+            let body = json(&body);
+            assert_eq!(tenant_ids(&body), ids, "{clusters:?}");
+            assert_eq!(body["meta"]["scanned"], 3, "{clusters:?}");
+            assert_eq!(body["meta"]["disabled"], disabled, "{clusters:?}");
+            assert_eq!(body["meta"]["returned"], ids.len(), "{clusters:?}");
+        }
+    }
+
+    // Off by default, so an ordinary response never carries the header
+    #[tokio::test]
+    async fn no_query_header_without_the_flag() {
+        init();
+        assert!(!CONFIG.get().unwrap().debug_echo_query);
+        let request = generator_request("/api/v1/getparams.execute", serde_json::json!({}));
+        let (status, headers, _) = call(router(""), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!headers.contains_key("x-cql-query"));
+    }
+
+    // A rejected caller's applicationSetName never becomes a series
+    #[tokio::test]
+    async fn unauthenticated_requests_share_one_label() {
+        let request = |name: &str, token: &str| {
+            Request::post("/api/v1/getparams.execute")
+                .header("content-type", "application/json")
+                .header("authorization", format!("Bearer {token}"))
+                .body(Body::from(serde_json::json!({ "applicationSetName": name, "input": {} }).to_string()))
+                .unwrap()
+        };
+        let (status, _, _) = call(router(""), request("made-up-by-a-stranger", "wrong-token")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _, _) = call(router(""), request("tenants-prod", TEST_TOKEN)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let metrics = METRICS.render();
+        assert!(!metrics.contains("made-up-by-a-stranger"), "{metrics}");
+        assert!(metrics.contains("keyspaces_plugin_requests_total{application_set=\"unauthenticated\"}"));
+        assert!(metrics.contains("keyspaces_plugin_errors_total{application_set=\"unauthenticated\",status=\"403\"}"));
+        assert!(metrics.contains("keyspaces_plugin_requests_total{application_set=\"tenants-prod\"}"));
+    }
+
+    #[test]
+    fn param_allowlist_debug_hides_token_hashes() {
+        use sha2::Digest;
+        let hash = hex::encode(sha2::Sha256::digest(TEST_TOKEN));
+        let allowlist = ParamAllowlist(BTreeMap::from([(hash.clone(), BTreeSet::from(["env".to_string()]))]));
+        let debug = format!("{:?}", allowlist);
+        assert_eq!(debug, "1 entries");
+        assert!(!debug.contains(&hash));
+    }
+
+    #[test]
+    fn evicted_keys_lose_their_cache_series() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 2, 1000);
+        let output = Output { parameters: Vec::new(), count: Some(0) };
+        let resp = PluginResponse { output, meta: None, delta: None };
+        let series = |key: &str| format!("{{key=\"{}\"}}", response_cache_label(key));
+        for key in ["evict-a", "evict-b"] {
+            cache.insert(key.to_string(), resp.clone(), 0, 10);
+            let label = response_cache_label(key);
+            METRICS.inc_counter("keyspaces_plugin_response_cache_hits_total", "test", &[("key", &label)]);
+        }
+        cache.insert("evict-c".to_string(), resp.clone(), 0, 10);
+        report_response_cache(&cache);
+        let metrics = METRICS.render();
+        assert!(!metrics.contains(&series("evict-a")));
+        assert!(metrics.contains(&series("evict-b")));
+
+        cache.invalidate();
+        report_response_cache(&cache);
+        assert!(!METRICS.render().contains(&series("evict-b")));
+    }
+
+    #[test]
+    fn budget_counts_only_server_failures() {
+        assert_eq!(budget_outcome(&Ok(())), Some(true));
+        assert_eq!(budget_outcome::<()>(&Err(AppError::Internal("boom".into()))), Some(false));
+        assert_eq!(budget_outcome::<()>(&Err(AppError::Unavailable("draining"))), Some(false));
+        for e in [
+            AppError::Unauthorized,
+            AppError::ParamNotAllowed("env".into()),
+            AppError::BadRequest("limit".into()),
+            AppError::Conflict("delta".into()),
+            AppError::Unprocessable("missing".into()),
+            AppError::HeadersTooLarge("x".into()),
+        ] {
+            assert_eq!(budget_outcome::<()>(&Err(e)), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn ndjson_scan_failure_aborts_the_body() {
+        use futures_util::StreamExt;
+        init();
+        let opts = Arc::new(RequestOptions::from_parameters(&HashMap::new()).unwrap());
+        let rows = tenant_source().fetch(&opts, &mut ScanStats::default(), None).await.unwrap();
+        let (tx, rx) = tokio::sync::mpsc::channel(2);
+        tx.send(Ok(rows)).await.unwrap();
+        tx.send(Err(AppError::Internal("page 2 failed".to_string()))).await.unwrap();
+        drop(tx);
+        let chunks: Vec<_> = ndjson_lines(rx, opts).collect().await;
+        assert_eq!(chunks.len(), 2);
+        assert!(String::from_utf8_lossy(chunks[0].as_ref().unwrap()).contains("\"acme\""));
+        assert!(chunks[1].as_ref().is_err_and(|e| e.to_string().contains("page 2 failed")));
+    }
+
+    #[tokio::test]
+    async fn null_repo_path_is_left_to_missing_field_mode() {
+        init();
+        let row = serde_json::json!({
+            "tenant_id": "acme", "target_cluster": "in-cluster",
+            "repo_url": "https://github.com/yourorg/tenants.git", "repo_path": null,
+        });
+        for (mode, filtered_out) in [("allow", false), ("skip", true)] {
+            let body = serde_json::json!({ "row": row, "parameters": { "missingFieldMode": mode } });
+            let request = Request::post("/api/v1/validate")
+                .header("content-type", "application/json")
+                .header("authorization", format!("Bearer {TEST_TOKEN}"))
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let (status, _, body) = call(router(""), request).await;
+            assert_eq!(status, StatusCode::OK, "{mode}");
+            let body = json(&body);
+            assert_eq!(body["filteredOut"], filtered_out, "{mode}");
+            if !filtered_out {
+                assert_eq!(body["output"]["path"], "", "{mode}");
+            }
+        }
+    }
+
+    #[test]
+    fn delta_rejects_generated_at() {
+        let both = options(serde_json::json!({"delta": true, "includeGeneratedAt": true}));
+        assert!(matches!(both, Err(AppError::BadRequest(m)) if m.contains("includeGeneratedAt")));
+        assert!(options(serde_json::json!({"delta": true})).is_ok());
+        assert!(options(serde_json::json!({"includeGeneratedAt": true})).is_ok());
+    }
+
+    #[tokio::test]
+    async fn validate_applies_the_param_allowlist() {
+        let request = |token: &str, parameters: serde_json::Value| {
+            let row = serde_json::json!({
+                "tenant_id": "acme", "target_cluster": "in-cluster",
+                "repo_url": "https://github.com/yourorg/tenants.git", "repo_path": "tenants/acme",
+            });
+            Request::post("/api/v1/validate")
+                .header("content-type", "application/json")
+                .header("authorization", format!("Bearer {token}"))
+                .body(Body::from(serde_json::json!({ "row": row, "parameters": parameters }).to_string()))
+                .unwrap()
+        };
+        let denied = request(RESTRICTED_TOKEN, serde_json::json!({ "includeAppName": true }));
+        let (status, _, body) = call(router(""), denied).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(json(&body)["error"]["code"], "param_not_allowed");
+
+        let allowed = request(RESTRICTED_TOKEN, serde_json::json!({ "filterLabelKey": "region" }));
+        assert_eq!(call(router(""), allowed).await.0, StatusCode::OK);
+        let unrestricted = request(TEST_TOKEN, serde_json::json!({ "includeAppName": true }));
+        assert_eq!(call(router(""), unrestricted).await.0, StatusCode::OK);
+    }
 }
@@ -0,0 +1,188 @@
+// Exact conversions between CQL varint/decimal and JSON numbers. serde_json is built with
+// `arbitrary_precision`, so a number keeps its literal text and values beyond i64, u64 or
+// f64 go out digit for digit instead of rounded.
+
+use scylla::value::{ CqlDecimal, CqlValue, CqlVarint };
+
+// A number that fits no integer type becomes a varint, one with a fraction or exponent a
+// decimal; f64 only when the literal is something else entirely
+pub(crate) fn json_to_cql(n: &serde_json::Number) -> CqlValue {
+    if let Some(i) = n.as_i64() {
+        return CqlValue::BigInt(i);
+    }
+    let text = n.to_string();
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.as_str()),
+    };
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i32>().ok()),
+        None => (unsigned, Some(0)),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{int}{frac}");
+    let scale = exponent.and_then(|e| i32::try_from(frac.len()).ok()?.checked_sub(e));
+    match scale {
+        _ if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) =>
+            n.as_f64().map_or(CqlValue::Empty, CqlValue::Double),
+        Some(0) if frac.is_empty() => CqlValue::Varint(CqlVarint::from_signed_bytes_be(to_signed_be(&digits, negative))),
+        Some(scale) =>
+            CqlValue::Decimal(
+                CqlDecimal::from_signed_be_bytes_and_exponent(to_signed_be(&digits, negative), scale)
+            ),
+        None => n.as_f64().map_or(CqlValue::Empty, CqlValue::Double),
+    }
+}
+
+pub(crate) fn varint_to_json(varint: &CqlVarint) -> serde_json::Value {
+    number(to_decimal(varint.as_signed_bytes_be_slice()))
+}
+
+// `unscaled * 10^-scale`, written without an exponent unless the scale is negative
+pub(crate) fn decimal_to_json(decimal: &CqlDecimal) -> serde_json::Value {
+    let (bytes, scale) = decimal.as_signed_be_bytes_slice_and_exponent();
+    let unscaled = to_decimal(bytes);
+    let (sign, digits) = match unscaled.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", unscaled.as_str()),
+    };
+    let text = match usize::try_from(scale) {
+        Ok(0) => format!("{sign}{digits}"),
+        Ok(scale) => {
+            let padded = format!("{digits:0>width$}", width = scale + 1);
+            let (int, frac) = padded.split_at(padded.len() - scale);
+            format!("{sign}{int}.{frac}")
+        }
+        Err(_) => format!("{sign}{digits}e{}", -i64::from(scale)),
+    };
+    number(text)
+}
+
+// The text is always a valid JSON number by construction
+fn number(text: String) -> serde_json::Value {
+    serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text))
+}
+
+// Two's complement big-endian bytes to decimal digits, by repeated division by 10^9
+fn to_decimal(bytes: &[u8]) -> String {
+    let negative = bytes.first().is_some_and(|b| b & 0x80 != 0);
+    let mut magnitude = bytes.to_vec();
+    if negative {
+        negate(magnitude.iter_mut().rev());
+    }
+    let mut chunks: Vec<u64> = Vec::new();
+    while magnitude.iter().any(|b| *b != 0) {
+        let mut rem = 0_u64;
+        for b in magnitude.iter_mut() {
+            let cur = (rem << 8) | u64::from(*b);
+            *b = (cur / 1_000_000_000) as u8;
+            rem = cur % 1_000_000_000;
+        }
+        chunks.push(rem);
+    }
+    let mut text = String::from(if negative { "-" } else { "" });
+    match chunks.split_last() {
+        None => text.push('0'),
+        Some((first, rest)) => {
+            text.push_str(&first.to_string());
+            for chunk in rest.iter().rev() {
+                text.push_str(&format!("{chunk:09}"));
+            }
+        }
+    }
+    text
+}
+
+// Decimal digits to two's complement big-endian bytes, with room for the sign bit
+fn to_signed_be(digits: &str, negative: bool) -> Vec<u8> {
+    // Little-endian while building
+    let mut bytes: Vec<u8> = Vec::new();
+    for d in digits.bytes() {
+        let mut carry = u32::from(d - b'0');
+        for b in bytes.iter_mut() {
+            let v = u32::from(*b) * 10 + carry;
+            *b = v as u8;
+            carry = v >> 8;
+        }
+        if carry > 0 {
+            bytes.push(carry as u8);
+        }
+    }
+    if bytes.last().is_none_or(|b| b & 0x80 != 0) {
+        bytes.push(0);
+    }
+    if negative {
+        negate(bytes.iter_mut());
+    }
+    bytes.reverse();
+    bytes
+}
+
+// In place, least significant byte first
+fn negate<'a>(bytes: impl Iterator<Item = &'a mut u8>) {
+    let mut carry = true;
+    for b in bytes {
+        let (v, overflow) = (!*b).overflowing_add(carry as u8);
+        *b = v;
+        carry = overflow;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parsed the way TENANT_SOURCE_FILE rows are, and written back
+    fn round_trip(literal: &str) -> String {
+        let serde_json::Value::Number(n) = serde_json::from_str(literal).expect("a JSON number") else {
+            panic!("{literal} did not parse as a number");
+        };
+        let value = match json_to_cql(&n) {
+            CqlValue::BigInt(i) => serde_json::Value::from(i),
+            CqlValue::Varint(v) => varint_to_json(&v),
+            CqlValue::Decimal(d) => decimal_to_json(&d),
+            other => panic!("{literal} became {other:?}"),
+        };
+        value.to_string()
+    }
+
+    #[test]
+    fn integers_beyond_f64_and_i64_round_trip() {
+        // 2^53 + 1, where f64 stops being exact; past i64; past u64; past i128
+        for literal in [
+            "9007199254740993",
+            "-9223372036854775809",
+            "18446744073709551617",
+            "-170141183460469231731687303715884105729",
+        ] {
+            assert_eq!(round_trip(literal), literal);
+        }
+    }
+
+    #[test]
+    fn decimals_keep_their_digits() {
+        for literal in ["12345678901234567890.000000000001", "-0.0050", "0.5"] {
+            assert_eq!(round_trip(literal), literal);
+        }
+    }
+
+    #[test]
+    fn small_integers_stay_bigint() {
+        let serde_json::Value::Number(n) = serde_json::json!(42) else { unreachable!() };
+        assert_eq!(json_to_cql(&n), CqlValue::BigInt(42));
+    }
+
+    #[test]
+    fn varint_sign_and_zero() {
+        assert_eq!(varint_to_json(&CqlVarint::from_signed_bytes_be(vec![0x00])).to_string(), "0");
+        assert_eq!(varint_to_json(&CqlVarint::from_signed_bytes_be(vec![0xff])).to_string(), "-1");
+        assert_eq!(varint_to_json(&CqlVarint::from_signed_bytes_be(vec![0x00, 0x80])).to_string(), "128");
+    }
+
+    // A negative scale is an exponent
+    #[test]
+    fn decimal_with_negative_scale() {
+        let decimal = CqlDecimal::from_signed_be_bytes_and_exponent(vec![0x0c], -3);
+        assert_eq!(decimal_to_json(&decimal).to_string(), "12e3");
+    }
+}
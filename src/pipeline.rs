@@ -0,0 +1,1914 @@
+// Post-query processing: everything between the decoded rows and the response body. Kept
+// free of the session and the global config so it can be benchmarked without a database
+// (`cargo bench`).
+
+use scylla::statement::Consistency;
+use scylla::value::CqlValue;
+use std::collections::{ BTreeMap, BTreeSet, HashMap, HashSet };
+use tracing::{ debug, error, warn };
+
+// One row of the tenant table, before any filtering or output shaping
+#[derive(Debug, Clone)]
+pub(crate) struct TenantRow {
+    pub(crate) tenant_id: String,
+    // Nullable: NAMESPACE_TEMPLATE can derive it
+    pub(crate) namespace: Option<String>,
+    pub(crate) target_cluster: String,
+    pub(crate) repo_url: String,
+    pub(crate) repo_path: String,
+    pub(crate) labels: Option<HashMap<String, String>>,
+    pub(crate) params: Option<HashMap<String, String>>,
+    // DEST_NAMESPACE_COLUMN, when configured
+    pub(crate) dest_namespace: Option<String>,
+    // SYNC_WAVE_COLUMN, when configured and an integer
+    pub(crate) sync_wave: Option<i64>,
+    // ANNOTATIONS_COLUMN, when configured
+    pub(crate) annotations: Option<HashMap<String, String>>,
+    // PROJECT_COLUMN, with EMIT_PROJECT
+    pub(crate) project: Option<String>,
+    // PROMOTED_COLUMNS, in configured order
+    pub(crate) promoted: Vec<(String, Option<CqlValue>)>,
+    // Columns requested via `extraColumns`, in request order
+    pub(crate) extra: Vec<(String, Option<CqlValue>)>,
+    // WRITETIME of WRITETIME_COLUMN in microseconds, when `includeWriteTime` asked for it;
+    // null when the column itself is null
+    pub(crate) write_time: Option<i64>,
+    // Table the row came from
+    pub(crate) source: String,
+}
+
+// Per-request options parsed from the generator input parameters
+#[derive(Debug, Default)]
+pub(crate) struct RequestOptions {
+    // Optional filters to trim the result set (e.g., by label)
+    pub(crate) filter_label: Option<(String, String)>,
+    // `excludeLabelValue`, on the same key; wins over `filter_label`
+    pub(crate) exclude_label: Option<(String, String)>,
+    // `filterMode=regex`: the compiled values above, used instead of equality
+    pub(crate) filter_regex: Option<regex::Regex>,
+    pub(crate) exclude_regex: Option<regex::Regex>,
+    // Pin or drop specific tenants; exclusion wins when an id is in both
+    pub(crate) include_tenant_ids: HashSet<String>,
+    pub(crate) exclude_tenant_ids: HashSet<String>,
+    // Target clusters to look up by partition key; empty means a full scan
+    pub(crate) clusters: Vec<String>,
+    // Disabled rows kept for these target clusters only; non-empty moves the `enabled`
+    // filter from the query to `keeps_enabled_state`
+    pub(crate) include_disabled_for_clusters: HashSet<String>,
+    // Additional columns selected as-is and emitted under their own names
+    pub(crate) extra_columns: Vec<String>,
+    pub(crate) output_shape: OutputShape,
+    // Null `labels`/`params` become `{}` instead of being omitted
+    pub(crate) emit_empty_maps: bool,
+    // `includeParamsObject=false`: only the expanded top-level param keys, no `params` object
+    pub(crate) omit_params_object: bool,
+    pub(crate) oversize_mode: OversizeMode,
+    // Scan side: what a page read failing mid-scan does to the request
+    pub(crate) partial_page_mode: PartialPageMode,
+    // Scan side: `pageSize`, already clamped to MAX_PAGE_SIZE
+    pub(crate) page_size: Option<i32>,
+    // Scan side: `consistency`, overriding KEYSPACES_CONSISTENCY for this request
+    pub(crate) consistency: Option<Consistency>,
+    // Applied to label and param keys only; the fixed keys keep their names
+    pub(crate) key_case: KeyCase,
+    // Promoted, extra and `_raw` timestamp columns, nested ones included
+    pub(crate) timestamp_format: TimestampFormat,
+    // `trimStrings`: strip surrounding whitespace from every output string value
+    pub(crate) trim_strings: bool,
+    // `{{`/`}}` in output strings, which ArgoCD would take for template delimiters
+    pub(crate) template_chars: TemplateCharsMode,
+    // Stamp every entry with the response time; set by the caller, not the pipeline
+    pub(crate) include_generated_at: bool,
+    // Top-level `meta` in the response; also set by the caller
+    pub(crate) include_meta: bool,
+    // `meta.warning` when nothing matched, even without `include_meta`; caller side too
+    pub(crate) empty_warning: bool,
+    // `transforms`: output key and the operations applied to it, in order
+    pub(crate) transforms: Vec<(String, Vec<Transform>)>,
+    // `preview`: keep the first N tenants that pass every filter and tag them `_preview`
+    pub(crate) preview: Option<usize>,
+    // `delta`/`sinceEtag`: changed-only output against an in-memory snapshot (caller side)
+    pub(crate) delta: bool,
+    pub(crate) since_snapshot: Option<String>,
+    // Debug: echo the untransformed columns under `_raw`
+    pub(crate) raw_row: bool,
+    // `_writeTimeMicros` on every entry
+    pub(crate) include_write_time: bool,
+    // `appName`: a DNS-safe `<tenantId>-<cluster>` on every entry
+    pub(crate) include_app_name: bool,
+    pub(crate) missing_field_mode: MissingFieldMode,
+    pub(crate) tenant_error_mode: TenantErrorMode,
+    pub(crate) app_name_collision_mode: AppNameCollisionMode,
+    // `_region` on every entry
+    pub(crate) include_region: bool,
+    // `{output: {count}}` instead of the entries; never saved
+    pub(crate) count_only: bool,
+}
+
+// What a page read failing mid-scan does to the request
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PartialPageMode {
+    // The whole request fails, as if nothing was read
+    #[default]
+    Fail,
+    // Keep the rows already read and flag the response `X-Partial: true`
+    ReturnPartial,
+}
+
+// What to do with a tenant whose labels or params exceed the configured cap
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OversizeMode {
+    // Keep the first keys in sorted order, so the subset is stable across calls
+    #[default]
+    Truncate,
+    // Drop the tenant from the output
+    Skip,
+}
+
+// `missingFieldMode`: entries with a REQUIRED_FIELDS value that is absent or blank
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MissingFieldMode {
+    // Kept as they are
+    #[default]
+    Allow,
+    // Dropped with a warning
+    Skip,
+    // Kept for `missing_fields`, and the request fails
+    Fail,
+}
+
+// `tenantErrorMode`: a tenant whose output construction fails
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TenantErrorMode {
+    // Dropped with an error log naming the tenant, and counted by `render_tenants_counted`
+    Skip,
+    // Kept as a `RENDER_ERROR_KEY` marker, and the request fails. The default: a dropped
+    // tenant would have its Application pruned by ArgoCD.
+    #[default]
+    Fail,
+}
+
+// Only ever on the markers `tenantErrorMode=fail` leaves
+pub(crate) const RENDER_ERROR_KEY: &str = "_renderError";
+
+// REQUIRED_FIELDS column -> the output key it is checked on
+pub(crate) const REQUIRED_FIELD_KEYS: [(&str, &str); 5] = [
+    ("tenant_id", "tenantId"),
+    ("namespace", "namespace"),
+    ("target_cluster", "cluster"),
+    ("repo_url", "repoURL"),
+    ("repo_path", "path"),
+];
+
+// `appNameCollisionMode`: distinct tenant/cluster pairs whose `appName` sanitizes alike
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AppNameCollisionMode {
+    // Each pair gets a hash suffix
+    #[default]
+    Suffix,
+    // Names are left alone for `app_name_collisions`, and the request fails
+    Fail,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TemplateCharsMode {
+    // Values go out as stored
+    #[default]
+    Keep,
+    // `escapeTemplateChars`: each delimiter becomes a Go template string literal
+    Escape,
+    // `rejectTemplateChars`: the tenant is dropped with a warning
+    Reject,
+}
+
+// `timestampFormat`: how timestamp columns are emitted, in UTC
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TimestampFormat {
+    // `2024-05-01T12:30:00.000Z`
+    #[default]
+    Rfc3339,
+    // `1714566600000`, a JSON number
+    EpochMillis,
+    // `2024-05-01`, the UTC day
+    Date,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeyCase {
+    #[default]
+    AsIs,
+    // `ingress_host` -> `ingressHost`
+    Camel,
+    // `ingressHost` -> `ingress_host`
+    Snake,
+}
+
+// Leading underscores are kept, so `_internal_key` stays visibly private as `_internalKey`.
+// Runs of underscores collapse; digits count as lowercase.
+fn to_camel_case(key: &str) -> String {
+    let body = key.trim_start_matches('_');
+    let mut out = key[..key.len() - body.len()].to_string();
+    for (i, part) in body.split('_').filter(|p| !p.is_empty()).enumerate() {
+        let mut chars = part.chars();
+        if i > 0 && let Some(first) = chars.next() {
+            out.extend(first.to_uppercase());
+            out.push_str(chars.as_str());
+        } else {
+            out.push_str(part);
+        }
+    }
+    out
+}
+
+// An underscore goes before an uppercase letter that follows a lowercase letter or digit,
+// or that starts a new word after an acronym (`repoURLPath` -> `repo_url_path`)
+fn to_snake_case(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    let mut out = String::with_capacity(key.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase() || prev.is_ascii_digit() || (prev.is_uppercase() && next_lower) {
+                out.push('_');
+            }
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+// Keys that collide after recasing keep the value of the first key in sorted order
+fn recase_keys(map: HashMap<String, String>, case: KeyCase) -> HashMap<String, String> {
+    let mut keys: Vec<(String, String)> = map.into_iter().collect();
+    keys.sort_unstable();
+    let mut out = HashMap::with_capacity(keys.len());
+    for (key, value) in keys {
+        let recased = match case {
+            KeyCase::AsIs => key,
+            KeyCase::Camel => to_camel_case(&key),
+            KeyCase::Snake => to_snake_case(&key),
+        };
+        out.entry(recased).or_insert(value);
+    }
+    out
+}
+
+// One step of a `transforms` chain. Deliberately a closed set: no user code runs here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Transform {
+    Lower,
+    Upper,
+    Trim,
+    // Used when the key is missing, null or an empty string
+    Default(String),
+    // Tests only: panics on the given value, to fail one tenant's output construction
+    #[cfg(test)]
+    PanicOn(String),
+}
+
+impl Transform {
+    // Case and whitespace operations only touch strings; other JSON types pass through
+    fn apply(&self, value: Option<serde_json::Value>) -> Option<serde_json::Value> {
+        use serde_json::Value;
+        match (self, value) {
+            (Transform::Lower, Some(Value::String(s))) => Some(Value::String(s.to_lowercase())),
+            (Transform::Upper, Some(Value::String(s))) => Some(Value::String(s.to_uppercase())),
+            (Transform::Trim, Some(Value::String(s))) => Some(Value::String(s.trim().to_string())),
+            (Transform::Default(fallback), None | Some(Value::Null)) =>
+                Some(Value::String(fallback.clone())),
+            (Transform::Default(fallback), Some(Value::String(s))) if s.is_empty() =>
+                Some(Value::String(fallback.clone())),
+            #[cfg(test)]
+            (Transform::PanicOn(bad), Some(Value::String(s))) if s == *bad => panic!("injected for {s}"),
+            (_, value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputShape {
+    // One parameter map per tenant (the ApplicationSet default)
+    #[default]
+    Flat,
+    // A single parameter map keyed by tenant id, for Matrix compositions
+    NestedByTenant,
+    // One parameter map per distinct target cluster, for per-cluster ApplicationSets
+    Clusters,
+}
+
+// Namespaces listed per cluster by `outputShape=clusters`
+const CLUSTER_SAMPLE_NAMESPACES: usize = 5;
+
+// RFC 1123 label limit, which Application names must fit
+const APP_NAME_MAX_LEN: usize = 63;
+// Hex characters of the disambiguating suffix
+const APP_NAME_HASH_LEN: usize = 8;
+
+// Sources of top-level parameter keys besides the fixed ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ParamSource {
+    // PROMOTED_COLUMNS
+    Promoted,
+    // Keys expanded from the `params` map
+    Params,
+}
+
+impl ParamSource {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            ParamSource::Promoted => "promoted",
+            ParamSource::Params => "params",
+        }
+    }
+}
+
+// Highest precedence first
+pub(crate) const DEFAULT_PARAM_PRECEDENCE: [ParamSource; 2] = [
+    ParamSource::Promoted,
+    ParamSource::Params,
+];
+
+// The slice of `Config` the pipeline needs
+#[derive(Debug)]
+pub(crate) struct RenderSettings {
+    // More than one table is configured, so entries carry `_source`
+    pub(crate) multi_table: bool,
+    pub(crate) namespace_template: Option<String>,
+    // DEST_NAMESPACE_COLUMN is configured, so entries carry `destNamespace`
+    pub(crate) emit_dest_namespace: bool,
+    // EMIT_PROJECT: entries carry `project`, this one when the column is null or empty
+    pub(crate) default_project: Option<String>,
+    // NORMALIZE_REPO_URL
+    pub(crate) normalize_repo_url: bool,
+    pub(crate) max_labels_per_tenant: usize,
+    pub(crate) max_params_per_tenant: usize,
+    // Which source wins when several produce the same key, highest first
+    pub(crate) param_precedence: Vec<ParamSource>,
+    // DEFAULT_PARAMS: added to an entry only when nothing for the tenant set that key
+    pub(crate) default_params: serde_json::Map<String, serde_json::Value>,
+    // Keyspaces region the rows are read from, for `includeRegion`
+    pub(crate) region: String,
+    // REQUIRED_FIELDS, checked by `missingFieldMode`
+    pub(crate) required_fields: Vec<String>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            multi_table: false,
+            namespace_template: None,
+            emit_dest_namespace: false,
+            default_project: None,
+            normalize_repo_url: false,
+            max_labels_per_tenant: 0,
+            max_params_per_tenant: 0,
+            param_precedence: DEFAULT_PARAM_PRECEDENCE.to_vec(),
+            default_params: serde_json::Map::new(),
+            region: String::new(),
+            required_fields: Vec::new(),
+        }
+    }
+}
+
+// MERGE_STRATEGY: how a tenant id found in several tables is combined
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MergeStrategy {
+    // The earliest table's row wins whole
+    #[default]
+    Shadow,
+    // Labels and params merged key by key; a key with different values keeps the earlier
+    // table's, the later table's, or fails the request
+    First,
+    Last,
+    Error,
+}
+
+impl MergeStrategy {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            MergeStrategy::Shadow => "shadow",
+            MergeStrategy::First => "first",
+            MergeStrategy::Last => "last",
+            MergeStrategy::Error => "error",
+        }
+    }
+}
+
+// Unions per-table scans in configured order. Merged rows keep everything but the maps from
+// the earliest table, `_source` included. The error names the colliding tenant and key.
+pub(crate) fn union_tables(tables: Vec<Vec<TenantRow>>, strategy: MergeStrategy) -> Result<Vec<TenantRow>, String> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut out: Vec<TenantRow> = Vec::new();
+    for row in tables.into_iter().flatten() {
+        let Some(&i) = seen.get(&row.tenant_id) else {
+            seen.insert(row.tenant_id.clone(), out.len());
+            out.push(row);
+            continue;
+        };
+        if strategy == MergeStrategy::Shadow {
+            debug!("tenant {} in {} shadowed by an earlier table", row.tenant_id, row.source);
+            continue;
+        }
+        let merged = &mut out[i];
+        let (tenant_id, earlier, later) = (&row.tenant_id, merged.source.as_str(), row.source.as_str());
+        merge_map(&mut merged.labels, row.labels, "labels", tenant_id, (earlier, later), strategy)?;
+        merge_map(&mut merged.params, row.params, "params", tenant_id, (earlier, later), strategy)?;
+    }
+    Ok(out)
+}
+
+// Equal values on both sides are not a collision
+fn merge_map(
+    into: &mut Option<HashMap<String, String>>,
+    from: Option<HashMap<String, String>>,
+    kind: &str,
+    tenant_id: &str,
+    (earlier, later): (&str, &str),
+    strategy: MergeStrategy
+) -> Result<(), String> {
+    let Some(from) = from else {
+        return Ok(());
+    };
+    let into = into.get_or_insert_with(HashMap::new);
+    for (key, value) in from {
+        match into.get(&key) {
+            None => {
+                into.insert(key, value);
+            }
+            Some(existing) if *existing == value => {}
+            Some(_) => {
+                if strategy == MergeStrategy::Error {
+                    return Err(
+                        format!(
+                            "tenant {}: {} key {} differs between {} and {} (MERGE_STRATEGY=error)",
+                            tenant_id,
+                            kind,
+                            key,
+                            earlier,
+                            later
+                        )
+                    );
+                }
+                let winner = if strategy == MergeStrategy::Last { later } else { earlier };
+                debug!(
+                    "tenant {}: {} key {} set in {} and {}; keeping {}",
+                    tenant_id,
+                    kind,
+                    key,
+                    earlier,
+                    later,
+                    winner
+                );
+                if strategy == MergeStrategy::Last {
+                    into.insert(key, value);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn render_tenants(
+    rows: Vec<TenantRow>,
+    opts: &RequestOptions,
+    settings: &RenderSettings
+) -> Vec<HashMap<String, serde_json::Value>> {
+    render_tenants_counted(rows, opts, settings).0
+}
+
+// Also returns how many tenants `tenantErrorMode=skip` dropped
+pub(crate) fn render_tenants_counted(
+    rows: Vec<TenantRow>,
+    opts: &RequestOptions,
+    settings: &RenderSettings
+) -> (Vec<HashMap<String, serde_json::Value>>, usize) {
+    let mut skipped = 0;
+    let mut out: Vec<HashMap<String, serde_json::Value>> = Vec::new();
+    // Entry index, tenant and cluster for each `appName` that has no hash suffix yet
+    let mut plain_app_names: Vec<(usize, String, String)> = Vec::new();
+
+    for row in rows {
+        if opts.preview.is_some_and(|n| out.len() >= n) {
+            break;
+        }
+        let (tenant_id, cluster) = (row.tenant_id.clone(), row.target_cluster.clone());
+        match isolate(|| render_row(row, opts, settings)) {
+            Ok(Some((map, plain_app_name))) => {
+                if plain_app_name {
+                    plain_app_names.push((out.len(), tenant_id, cluster));
+                }
+                out.push(map);
+            }
+            Ok(None) => {}
+            Err(reason) => {
+                error!("tenant {}: output construction failed: {}", tenant_id, reason);
+                // Kept as a marker for `render_errors`, which fails the request
+                match opts.tenant_error_mode {
+                    TenantErrorMode::Fail => {
+                        out.push(
+                            HashMap::from([
+                                ("tenantId".to_string(), tenant_id.into()),
+                                (RENDER_ERROR_KEY.to_string(), reason.into()),
+                            ])
+                        );
+                    }
+                    TenantErrorMode::Skip => {
+                        skipped += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    if opts.app_name_collision_mode == AppNameCollisionMode::Suffix {
+        disambiguate_app_names(&mut out, plain_app_names);
+    }
+    (out, skipped)
+}
+
+// Scan-level `enabled` filter: a null counts as disabled, as it does for `enabled = true`
+pub(crate) fn keeps_enabled_state(enabled: Option<bool>, target_cluster: &str, opts: &RequestOptions) -> bool {
+    enabled == Some(true) || opts.include_disabled_for_clusters.contains(target_cluster)
+}
+
+// One tenant's entry, or None when a filter or mode drops it; the bool is set when its
+// `appName` still needs the collision check
+fn render_row(
+    mut row: TenantRow,
+    opts: &RequestOptions,
+    settings: &RenderSettings
+) -> Option<(HashMap<String, serde_json::Value>, bool)> {
+    let namespace_template = settings.namespace_template.as_deref();
+    let max_labels = settings.max_labels_per_tenant;
+    let max_params = settings.max_params_per_tenant;
+    let ts = opts.timestamp_format;
+    let raw = opts.raw_row.then(|| {
+        let mut raw = serde_json::json!({
+            "tenant_id": &row.tenant_id,
+            "namespace": &row.namespace,
+            "target_cluster": &row.target_cluster,
+            "repo_url": &row.repo_url,
+            "repo_path": &row.repo_path,
+            "labels": &row.labels,
+            "params": &row.params,
+        });
+        for (column, value) in row.promoted.iter().chain(&row.extra) {
+            raw[column.as_str()] = value.as_ref().map(|v| cql_to_json(v, ts)).unwrap_or_default();
+        }
+        raw
+    });
+
+    if opts.exclude_tenant_ids.contains(&row.tenant_id) {
+        return None;
+    }
+    if !opts.include_tenant_ids.is_empty() && !opts.include_tenant_ids.contains(&row.tenant_id) {
+        return None;
+    }
+
+    // optional label filters; a missing label never matches
+    let label_matches = |(k, v): &(String, String), re: Option<&regex::Regex>| {
+        row.labels
+            .as_ref()
+            .and_then(|m| m.get(k))
+            .is_some_and(|val| re.map_or(val == v, |re| re.is_match(val)))
+    };
+    if opts.filter_label.as_ref().is_some_and(|f| !label_matches(f, opts.filter_regex.as_ref())) {
+        return None;
+    }
+    if opts.exclude_label.as_ref().is_some_and(|e| label_matches(e, opts.exclude_regex.as_ref())) {
+        return None;
+    }
+
+    // Caps apply after filtering so the label filter still sees every label
+    let labels_ok = cap_map(&mut row.labels, max_labels, "labels", &row.tenant_id, opts.oversize_mode);
+    let params_ok = cap_map(&mut row.params, max_params, "params", &row.tenant_id, opts.oversize_mode);
+    if !labels_ok || !params_ok {
+        return None;
+    }
+    if opts.key_case != KeyCase::AsIs {
+        row.labels = row.labels.map(|m| recase_keys(m, opts.key_case));
+        row.params = row.params.map(|m| recase_keys(m, opts.key_case));
+    }
+
+    // Precedence: non-empty column value, then NAMESPACE_TEMPLATE, then empty
+    let namespace = row.namespace
+        .filter(|n| !n.is_empty())
+        .or_else(|| {
+            namespace_template.map(|t| t.replace("{tenantId}", &row.tenant_id))
+        })
+        .unwrap_or_default();
+
+    // Taken before the params are merged, which may shadow `cluster`
+    let app_name = opts.include_app_name.then(|| app_name(&row.tenant_id, &row.target_cluster));
+    let plain_app_name = matches!(app_name, Some((_, false)));
+
+    let mut map = HashMap::new();
+    map.insert("tenantId".into(), row.tenant_id.clone().into());
+    // Falls back to the resolved namespace when the column is null or empty
+    if settings.emit_dest_namespace {
+        let dest = row.dest_namespace
+            .filter(|n| !n.is_empty())
+            .unwrap_or_else(|| namespace.clone());
+        map.insert("destNamespace".into(), dest.into());
+    }
+    map.insert("namespace".into(), namespace.into());
+    map.insert("cluster".into(), row.target_cluster.into());
+    let repo_url = if settings.normalize_repo_url {
+        normalize_repo_url(&row.repo_url)
+    } else {
+        row.repo_url
+    };
+    map.insert("repoURL".into(), repo_url.into());
+    map.insert("path".into(), row.repo_path.into());
+    // Unlike labels and params, a null column never becomes an empty value
+    if let Some(wave) = row.sync_wave {
+        map.insert("syncWave".into(), wave.into());
+    }
+    if let Some(annotations) = row.annotations {
+        map.insert("annotations".into(), text_object(annotations));
+    }
+    if let Some(default) = &settings.default_project {
+        let project = row.project
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| default.clone());
+        map.insert("project".into(), project.into());
+    }
+
+    if let Some(labels) = row.labels {
+        map.insert("labels".into(), text_object(labels));
+    } else if opts.emit_empty_maps {
+        map.insert("labels".into(), serde_json::json!({}));
+    }
+    let mut expanded: Vec<(String, serde_json::Value)> = Vec::new();
+    if let Some(params) = row.params {
+        for (k, v) in &params {
+            expanded.push((k.clone(), serde_json::Value::String(v.clone())));
+        }
+        if !opts.omit_params_object {
+            map.insert("params".into(), text_object(params));
+        }
+    } else if opts.emit_empty_maps && !opts.omit_params_object {
+        map.insert("params".into(), serde_json::json!({}));
+    }
+    // Null promoted columns are omitted, so they never shadow anything
+    let promoted: Vec<(String, serde_json::Value)> = row.promoted
+        .into_iter()
+        .filter_map(|(column, value)| value.map(|v| (column, cql_to_json(&v, ts))))
+        .collect();
+
+    // Highest-precedence source first; a key already set by a higher source is skipped
+    let mut owners: HashMap<String, ParamSource> = HashMap::new();
+    for &source in &settings.param_precedence {
+        let entries = match source {
+            ParamSource::Promoted => &promoted,
+            ParamSource::Params => &expanded,
+        };
+        for (key, value) in entries {
+            if let Some(owner) = owners.get(key) {
+                debug!(
+                    "tenant {}: {} key {} shadowed by {}",
+                    row.tenant_id,
+                    source.name(),
+                    key,
+                    owner.name()
+                );
+                continue;
+            }
+            owners.insert(key.clone(), source);
+            map.insert(key.clone(), value.clone());
+        }
+    }
+    for (column, value) in row.extra {
+        map.insert(column, value.as_ref().map(|v| cql_to_json(v, ts)).unwrap_or_default());
+    }
+    for (key, value) in &settings.default_params {
+        if !map.contains_key(key) {
+            map.insert(key.clone(), value.clone());
+        }
+    }
+    // Before transforms, so a whitespace-only value counts as empty for `default:`
+    if opts.trim_strings {
+        for (key, value) in map.iter_mut() {
+            if trim_strings(value) {
+                debug!("tenant {}: trimmed whitespace in {}", row.tenant_id, key);
+            }
+        }
+    }
+    for (key, chain) in &opts.transforms {
+        let value = chain.iter().fold(map.remove(key), |v, t| t.apply(v));
+        if let Some(value) = value {
+            map.insert(key.clone(), value);
+        }
+    }
+    match opts.template_chars {
+        TemplateCharsMode::Keep => {}
+        TemplateCharsMode::Escape => map.values_mut().for_each(escape_template_chars),
+        TemplateCharsMode::Reject => {
+            // Smallest key, so the warning names the same key on every call
+            let offending = map
+                .iter()
+                .filter(|(_, v)| has_template_chars(v))
+                .map(|(k, _)| k)
+                .min();
+            if let Some(key) = offending {
+                warn!("tenant {}: {} contains {{{{ or }}}}; dropping the tenant", row.tenant_id, key);
+                return None;
+            }
+        }
+    }
+    // On the rendered values, so a params key or NAMESPACE_TEMPLATE that fills one counts
+    if opts.missing_field_mode == MissingFieldMode::Skip {
+        let missing = missing_fields(&map, &settings.required_fields);
+        if !missing.is_empty() {
+            warn!("tenant {}: {} missing or empty; dropping the tenant", row.tenant_id, missing.join(", "));
+            return None;
+        }
+    }
+    if settings.multi_table {
+        map.insert("_source".into(), row.source.into());
+    }
+    if let Some(raw) = raw {
+        map.insert("_raw".into(), raw);
+    }
+    if opts.include_write_time {
+        map.insert("_writeTimeMicros".into(), row.write_time.into());
+    }
+    if opts.include_region {
+        map.insert("_region".into(), settings.region.clone().into());
+    }
+    if let Some((name, _)) = app_name {
+        map.insert("appName".into(), name.into());
+    }
+    if opts.preview.is_some() {
+        map.insert("_preview".into(), true.into());
+    }
+
+    Some((map, plain_app_name))
+}
+
+// Runs one tenant's output construction so that a panic fails that tenant only. The panic
+// hook still prints it; the message becomes the error.
+pub(crate) fn isolate<T>(f: impl FnOnce() -> Option<T>) -> Result<Option<T>, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|m| m.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned());
+        match message {
+            Some(message) => format!("panicked: {message}"),
+            None => "panicked".to_string(),
+        }
+    })
+}
+
+// Tenants that `tenantErrorMode=fail` kept as markers, with the reason
+pub(crate) fn render_errors(entries: &[HashMap<String, serde_json::Value>]) -> Vec<(&str, &str)> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let reason = entry.get(RENDER_ERROR_KEY)?.as_str()?;
+            let tenant = entry.get("tenantId").and_then(|v| v.as_str()).unwrap_or_default();
+            Some((tenant, reason))
+        })
+        .collect()
+}
+
+fn text_object(map: HashMap<String, String>) -> serde_json::Value {
+    serde_json::Value::Object(
+        map
+            .into_iter()
+            .map(|(k, v)| (k, serde_json::Value::String(v)))
+            .collect()
+    )
+}
+
+// A rendered entry as one JSON object, keys sorted
+pub(crate) fn entry_value(entry: &HashMap<String, serde_json::Value>) -> serde_json::Value {
+    serde_json::Value::Object(
+        entry
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    )
+}
+
+// `<tenantId>-<cluster>` as an RFC 1123 label: lowercase alphanumerics and single hyphens,
+// at most 63 characters. Too long a name is cut and given a hash suffix, flagged by the bool.
+fn app_name(tenant_id: &str, cluster: &str) -> (String, bool) {
+    let mut name = String::new();
+    for c in format!("{tenant_id}-{cluster}").chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let name = name.trim_end_matches('-');
+    if name.is_empty() || name.len() > APP_NAME_MAX_LEN {
+        (with_hash_suffix(name, tenant_id, cluster), true)
+    } else {
+        (name.to_string(), false)
+    }
+}
+
+// Hash of the unsanitized pair, so the suffix tells apart inputs that sanitize alike
+fn with_hash_suffix(name: &str, tenant_id: &str, cluster: &str) -> String {
+    use sha2::Digest;
+    let digest = sha2::Sha256::digest(format!("{tenant_id}\n{cluster}"));
+    let hash = &hex::encode(digest)[..APP_NAME_HASH_LEN];
+    // ASCII only by now, so byte slicing is safe
+    let name = name[..name.len().min(APP_NAME_MAX_LEN - APP_NAME_HASH_LEN - 1)].trim_end_matches('-');
+    if name.is_empty() { hash.to_string() } else { format!("{name}-{hash}") }
+}
+
+// Distinct tenant/cluster pairs that sanitize to the same name (`Acme`/`acme`, `a_b`/`a.b`)
+// all get a hash suffix, so no pair keeps the plain name by winning on scan order
+fn disambiguate_app_names(out: &mut [HashMap<String, serde_json::Value>], plain: Vec<(usize, String, String)>) {
+    let mut by_name: HashMap<String, Vec<(usize, String, String)>> = HashMap::new();
+    for entry in plain {
+        let name = out[entry.0]["appName"].as_str().unwrap_or_default().to_string();
+        by_name.entry(name).or_default().push(entry);
+    }
+    for (name, entries) in by_name {
+        let pairs: HashSet<(&str, &str)> = entries
+            .iter()
+            .map(|(_, t, c)| (t.as_str(), c.as_str()))
+            .collect();
+        if pairs.len() < 2 {
+            continue;
+        }
+        let mut pairs: Vec<String> = pairs
+            .into_iter()
+            .map(|(t, c)| format!("{t}/{c}"))
+            .collect();
+        pairs.sort();
+        warn!("appName {} shared by tenants {}; adding hash suffixes", name, pairs.join(", "));
+        for (i, tenant_id, cluster) in &entries {
+            out[*i].insert("appName".into(), with_hash_suffix(&name, tenant_id, cluster).into());
+        }
+    }
+}
+
+// Required columns whose output key is absent, null or blank in `entry`
+pub(crate) fn missing_fields<'a>(
+    entry: &HashMap<String, serde_json::Value>,
+    required: &'a [String]
+) -> Vec<&'a str> {
+    required
+        .iter()
+        .filter(|field| {
+            let key = REQUIRED_FIELD_KEYS.iter()
+                .find(|(column, _)| column == field)
+                .map_or(field.as_str(), |(_, key)| key);
+            match entry.get(key) {
+                None | Some(serde_json::Value::Null) => true,
+                Some(serde_json::Value::String(s)) => s.trim().is_empty(),
+                Some(_) => false,
+            }
+        })
+        .map(|field| field.as_str())
+        .collect()
+}
+
+// Names shared by several entries, each with its sorted `tenantId/cluster` pairs, for
+// `appNameCollisionMode=fail`. Hash-suffixed names never collide, so every entry is compared.
+pub(crate) fn app_name_collisions(out: &[HashMap<String, serde_json::Value>]) -> Vec<(String, Vec<String>)> {
+    let mut by_name: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for entry in out {
+        let Some(name) = entry.get("appName").and_then(|v| v.as_str()) else {
+            continue;
+        };
+        let field = |key: &str| entry.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+        by_name.entry(name).or_default().push(format!("{}/{}", field("tenantId"), field("cluster")));
+    }
+    by_name
+        .into_iter()
+        .filter(|(_, pairs)| pairs.len() > 1)
+        .map(|(name, mut pairs)| {
+            pairs.sort();
+            (name.to_string(), pairs)
+        })
+        .collect()
+}
+
+// Trims strings in place, nested ones included; true when anything changed
+fn trim_strings(value: &mut serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::String(s) => {
+            let trimmed = s.trim();
+            if trimmed.len() == s.len() {
+                return false;
+            }
+            *s = trimmed.to_string();
+            true
+        }
+        // Every element is visited, so no short-circuiting `any`
+        serde_json::Value::Array(items) => items.iter_mut().fold(false, |changed, v| trim_strings(v) | changed),
+        serde_json::Value::Object(map) => map.values_mut().fold(false, |changed, v| trim_strings(v) | changed),
+        _ => false,
+    }
+}
+
+fn has_template_chars(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::String(s) => s.contains("{{") || s.contains("}}"),
+        serde_json::Value::Array(items) => items.iter().any(has_template_chars),
+        serde_json::Value::Object(map) => map.values().any(has_template_chars),
+        _ => false,
+    }
+}
+
+// `a{{b` becomes `a{{"{{"}}b`, which a Go template (`goTemplate: true`) renders back to
+// `a{{b`. One left-to-right pass, so the inserted braces are never escaped again.
+fn escape_template_chars(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(s) if s.contains("{{") || s.contains("}}") => {
+            let mut out = String::with_capacity(s.len() + 16);
+            let mut rest = s.as_str();
+            while let Some(i) = [rest.find("{{"), rest.find("}}")].into_iter().flatten().min() {
+                out.push_str(&rest[..i]);
+                out.push_str(if rest[i..].starts_with("{{") { r#"{{"{{"}}"# } else { r#"{{"}}"}}"# });
+                rest = &rest[i + 2..];
+            }
+            out.push_str(rest);
+            *s = out;
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(escape_template_chars),
+        serde_json::Value::Object(map) => map.values_mut().for_each(escape_template_chars),
+        _ => {}
+    }
+}
+
+// `https://host/org/repo`, `.../repo/`, `.../repo.git` and `.../repo.git/` all become
+// `https://host/org/repo`; SSH and scp-style URLs are handled the same way
+fn normalize_repo_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    url.strip_suffix(".git").unwrap_or(url).to_string()
+}
+
+// Enforces a per-tenant entry cap on one map column. Returns false when the tenant should
+// be skipped.
+fn cap_map(
+    map: &mut Option<HashMap<String, String>>,
+    max: usize,
+    column: &str,
+    tenant_id: &str,
+    mode: OversizeMode
+) -> bool {
+    let Some(entries) = map.as_mut() else {
+        return true;
+    };
+    if max == 0 || entries.len() <= max {
+        return true;
+    }
+    warn!(
+        "tenant {} has {} {} (cap {}); {}",
+        tenant_id,
+        entries.len(),
+        column,
+        max,
+        match mode {
+            OversizeMode::Truncate => "truncating",
+            OversizeMode::Skip => "skipping",
+        }
+    );
+    if mode == OversizeMode::Skip {
+        return false;
+    }
+    let mut keys: Vec<String> = entries.keys().cloned().collect();
+    keys.sort_unstable();
+    for key in &keys[max..] {
+        entries.remove(key);
+    }
+    true
+}
+
+// JSON rendering of a dynamically selected column: booleans and numbers stay typed (varint
+// and decimal exactly), timestamps follow `ts` (always UTC), collections recurse
+pub(crate) fn cql_to_json(value: &CqlValue, ts: TimestampFormat) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        CqlValue::Boolean(b) => Value::Bool(*b),
+        CqlValue::TinyInt(n) => (*n).into(),
+        CqlValue::SmallInt(n) => (*n).into(),
+        CqlValue::Int(n) => (*n).into(),
+        CqlValue::BigInt(n) => (*n).into(),
+        CqlValue::Counter(c) => c.0.into(),
+        // non-finite floats have no JSON form and become null
+        CqlValue::Float(f) => Value::from(f64::from(*f)),
+        CqlValue::Double(f) => Value::from(*f),
+        CqlValue::Varint(v) => crate::number::varint_to_json(v),
+        CqlValue::Decimal(d) => crate::number::decimal_to_json(d),
+        CqlValue::Text(s) | CqlValue::Ascii(s) => Value::String(s.clone()),
+        CqlValue::Timestamp(millis) => {
+            // Out of chrono's range falls back to the raw millis
+            let time = chrono::DateTime::from_timestamp_millis(millis.0);
+            match (ts, time) {
+                (TimestampFormat::Rfc3339, Some(t)) =>
+                    Value::String(t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+                (TimestampFormat::Date, Some(t)) => Value::String(t.format("%Y-%m-%d").to_string()),
+                (TimestampFormat::EpochMillis, _) | (_, None) => millis.0.into(),
+            }
+        }
+        CqlValue::Date(d) => {
+            // CQL dates count days from 2^31 days before the unix epoch
+            let days = i64::from(d.0) - (1_i64 << 31);
+            chrono::NaiveDate
+                ::from_ymd_opt(1970, 1, 1)
+                .and_then(|epoch| epoch.checked_add_signed(chrono::TimeDelta::days(days)))
+                .map(|date| Value::String(date.format("%Y-%m-%d").to_string()))
+                .unwrap_or_else(|| days.into())
+        }
+        CqlValue::Uuid(u) => Value::String(u.to_string()),
+        CqlValue::Timeuuid(u) => Value::String(u.to_string()),
+        CqlValue::Inet(ip) => Value::String(ip.to_string()),
+        CqlValue::List(items) | CqlValue::Set(items) | CqlValue::Vector(items) =>
+            Value::Array(items.iter().map(|v| cql_to_json(v, ts)).collect()),
+        CqlValue::Map(entries) =>
+            Value::Object(
+                entries
+                    .iter()
+                    .map(|(k, v)| {
+                        let key = match cql_to_json(k, ts) {
+                            Value::String(s) => s,
+                            other => other.to_string(),
+                        };
+                        (key, cql_to_json(v, ts))
+                    })
+                    .collect()
+            ),
+        CqlValue::Tuple(items) =>
+            Value::Array(
+                items
+                    .iter()
+                    .map(|v| v.as_ref().map(|v| cql_to_json(v, ts)).unwrap_or_default())
+                    .collect()
+            ),
+        CqlValue::UserDefinedType { fields, .. } =>
+            Value::Object(
+                fields
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.as_ref().map(|v| cql_to_json(v, ts)).unwrap_or_default()))
+                    .collect()
+            ),
+        CqlValue::Empty => Value::Null,
+        // blob, duration, time: no lossless JSON form, keep a readable one
+        other => Value::String(format!("{other:?}")),
+    }
+}
+
+pub(crate) fn shape_output(
+    entries: Vec<HashMap<String, serde_json::Value>>,
+    shape: OutputShape
+) -> Vec<HashMap<String, serde_json::Value>> {
+    match shape {
+        OutputShape::Flat => entries,
+        OutputShape::NestedByTenant => {
+            if entries.is_empty() {
+                return entries;
+            }
+            let merged: HashMap<String, serde_json::Value> = entries
+                .into_iter()
+                .map(|entry| {
+                    let id = entry
+                        .get("tenantId")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string();
+                    (id, entry_value(&entry))
+                })
+                .collect();
+            vec![merged]
+        }
+        OutputShape::Clusters => {
+            // BTreeMaps keep clusters and sampled namespaces in a stable order across calls
+            let mut clusters: BTreeMap<String, (u64, BTreeSet<String>)> = BTreeMap::new();
+            for entry in &entries {
+                let text = |key: &str| entry.get(key).and_then(|v| v.as_str()).unwrap_or_default();
+                let (count, namespaces) = clusters.entry(text("cluster").to_string()).or_default();
+                *count += 1;
+                if !text("namespace").is_empty() {
+                    namespaces.insert(text("namespace").to_string());
+                }
+            }
+            clusters
+                .into_iter()
+                .map(|(cluster, (count, namespaces))| {
+                    let sample: Vec<String> = namespaces.into_iter().take(CLUSTER_SAMPLE_NAMESPACES).collect();
+                    HashMap::from([
+                        ("cluster".to_string(), cluster.into()),
+                        ("tenantCount".to_string(), count.into()),
+                        ("sampleNamespaces".to_string(), sample.into()),
+                    ])
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(tenant_id: &str, labels: &[(&str, &str)]) -> TenantRow {
+        TenantRow {
+            tenant_id: tenant_id.to_string(),
+            namespace: Some(format!("tn-{tenant_id}")),
+            target_cluster: "in-cluster".to_string(),
+            repo_url: "https://github.com/yourorg/tenants.git".to_string(),
+            repo_path: format!("tenants/{tenant_id}"),
+            labels: Some(
+                labels
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect()
+            ),
+            params: None,
+            dest_namespace: None,
+            sync_wave: None,
+            annotations: None,
+            project: None,
+            promoted: Vec::new(),
+            extra: Vec::new(),
+            write_time: None,
+            source: "tenant_configs".to_string(),
+        }
+    }
+
+    fn tenant_ids(entries: &[HashMap<String, serde_json::Value>]) -> Vec<&str> {
+        entries
+            .iter()
+            .filter_map(|e| e.get("tenantId").and_then(|v| v.as_str()))
+            .collect()
+    }
+
+    // globex's output construction panics; acme and initech render normally
+    fn render_with_panic(mode: TenantErrorMode) -> (Vec<HashMap<String, serde_json::Value>>, usize) {
+        let opts = RequestOptions {
+            tenant_error_mode: mode,
+            transforms: vec![("tenantId".to_string(), vec![Transform::PanicOn("globex".to_string())])],
+            ..Default::default()
+        };
+        let rows = vec![row("acme", &[]), row("globex", &[]), row("initech", &[])];
+        render_tenants_counted(rows, &opts, &RenderSettings::default())
+    }
+
+    fn labels(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    // `region` collides, `tier` only exists in the later table
+    #[test]
+    fn merge_strategies_combine_labels() {
+        let tables = || {
+            vec![
+                vec![row("acme", &[("region", "ca-central-1"), ("team", "a")])],
+                vec![
+                    TenantRow {
+                        source: "tenant_configs_legacy".to_string(),
+                        ..row("acme", &[("region", "us-east-1"), ("team", "a"), ("tier", "gold")])
+                    }
+                ]
+            ]
+        };
+        let merged = |strategy| union_tables(tables(), strategy).map(|rows| rows[0].labels.clone().unwrap_or_default());
+        assert_eq!(merged(MergeStrategy::Shadow), Ok(labels(&[("region", "ca-central-1"), ("team", "a")])));
+        assert_eq!(
+            merged(MergeStrategy::First),
+            Ok(labels(&[("region", "ca-central-1"), ("team", "a"), ("tier", "gold")]))
+        );
+        assert_eq!(
+            merged(MergeStrategy::Last),
+            Ok(labels(&[("region", "us-east-1"), ("team", "a"), ("tier", "gold")]))
+        );
+        let error = merged(MergeStrategy::Error).unwrap_err();
+        assert!(error.contains("labels key region differs"), "{error}");
+    }
+
+    // 2024-05-01T12:30:00.123Z, bare and nested in a list
+    #[test]
+    fn timestamp_formats() {
+        use scylla::value::CqlTimestamp;
+        let value = CqlValue::List(vec![CqlValue::Timestamp(CqlTimestamp(1_714_566_600_123))]);
+        assert_eq!(cql_to_json(&value, TimestampFormat::Rfc3339), serde_json::json!(["2024-05-01T12:30:00.123Z"]));
+        assert_eq!(cql_to_json(&value, TimestampFormat::EpochMillis), serde_json::json!([1_714_566_600_123_i64]));
+        assert_eq!(cql_to_json(&value, TimestampFormat::Date), serde_json::json!(["2024-05-01"]));
+    }
+
+    // With EMIT_PROJECT a null or empty column falls back to DEFAULT_PROJECT; without it there is no key
+    #[test]
+    fn project_falls_back_to_the_default() {
+        let render = |project: Option<&str>, default: Option<&str>| {
+            let tenant = TenantRow { project: project.map(str::to_string), ..row("acme", &[]) };
+            let settings = RenderSettings { default_project: default.map(str::to_string), ..Default::default() };
+            render_tenants(vec![tenant], &RequestOptions::default(), &settings)[0].get("project").cloned()
+        };
+        assert_eq!(render(Some("payments"), Some("default")), Some("payments".into()));
+        assert_eq!(render(None, Some("default")), Some("default".into()));
+        assert_eq!(render(Some(""), Some("shared")), Some("shared".into()));
+        assert_eq!(render(Some("payments"), None), None);
+    }
+
+    // Disabled and null rows survive only on a listed cluster; without a list, only enabled ones
+    #[test]
+    fn disabled_rows_kept_for_listed_clusters() {
+        let staging = RequestOptions {
+            include_disabled_for_clusters: ["staging".to_string()].into_iter().collect(),
+            ..Default::default()
+        };
+        assert!(keeps_enabled_state(Some(true), "prod", &staging));
+        assert!(!keeps_enabled_state(Some(false), "prod", &staging));
+        assert!(!keeps_enabled_state(None, "prod", &staging));
+        assert!(keeps_enabled_state(Some(false), "staging", &staging));
+        assert!(keeps_enabled_state(None, "staging", &staging));
+        assert!(keeps_enabled_state(Some(true), "staging", &staging));
+        assert!(!keeps_enabled_state(Some(false), "staging", &RequestOptions::default()));
+        assert!(keeps_enabled_state(Some(true), "prod", &RequestOptions::default()));
+    }
+
+    // `Acme` and `acme` on one cluster both sanitize to `acme-in-cluster`
+    #[test]
+    fn app_name_collisions_get_a_suffix_or_are_reported() {
+        let rows = || vec![row("Acme", &[]), row("acme", &[]), row("globex", &[])];
+        let mut opts = RequestOptions { include_app_name: true, ..Default::default() };
+        let suffixed = render_tenants(rows(), &opts, &RenderSettings::default());
+        let names: Vec<&str> = suffixed
+            .iter()
+            .filter_map(|e| e.get("appName").and_then(|v| v.as_str()))
+            .collect();
+        assert_eq!(names.iter().collect::<HashSet<_>>().len(), 3, "{names:?}");
+        assert!(names[0].starts_with("acme-in-cluster-"), "{names:?}");
+        assert!(names[1].starts_with("acme-in-cluster-"), "{names:?}");
+        assert_eq!(names[2], "globex-in-cluster");
+
+        opts.app_name_collision_mode = AppNameCollisionMode::Fail;
+        let collisions = app_name_collisions(&render_tenants(rows(), &opts, &RenderSettings::default()));
+        assert_eq!(
+            collisions,
+            [("acme-in-cluster".to_string(), vec!["Acme/in-cluster".to_string(), "acme/in-cluster".to_string()])]
+        );
+    }
+
+    // acme has an empty repo_path: allow keeps it, skip drops it, fail keeps it for the 422
+    #[test]
+    fn missing_field_modes() {
+        let rows = || {
+            vec![
+                TenantRow { repo_path: String::new(), ..row("acme", &[]) },
+                row("globex", &[]),
+                // A NULL repo_path, as `scan_table` decodes it
+                TenantRow { repo_path: String::default(), ..row("initech", &[]) }
+            ]
+        };
+        let settings = RenderSettings {
+            required_fields: vec!["repo_url".to_string(), "repo_path".to_string()],
+            ..Default::default()
+        };
+        for (mode, kept, flagged) in [
+            (MissingFieldMode::Allow, &["acme", "globex", "initech"][..], 2),
+            (MissingFieldMode::Skip, &["globex"][..], 0),
+            (MissingFieldMode::Fail, &["acme", "globex", "initech"][..], 2),
+        ] {
+            let opts = RequestOptions { missing_field_mode: mode, ..Default::default() };
+            let entries = render_tenants(rows(), &opts, &settings);
+            assert_eq!(tenant_ids(&entries), kept, "{mode:?}");
+            let missing: Vec<Vec<&str>> = entries
+                .iter()
+                .map(|e| missing_fields(e, &settings.required_fields))
+                .filter(|m| !m.is_empty())
+                .collect();
+            assert_eq!(missing.len(), flagged, "{mode:?}");
+            assert!(missing.iter().all(|m| m == &["repo_path"]), "{mode:?}: {missing:?}");
+        }
+    }
+
+    fn ids(values: &[&str]) -> HashSet<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn with_tenant_ids(include: &[&str], exclude: &[&str]) -> Vec<String> {
+        let opts = RequestOptions {
+            include_tenant_ids: ids(include),
+            exclude_tenant_ids: ids(exclude),
+            ..Default::default()
+        };
+        let rows = vec![row("acme", &[]), row("globex", &[]), row("initech", &[])];
+        tenant_ids(&render_tenants(rows, &opts, &RenderSettings::default()))
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn empty_tenant_id_lists_keep_everyone() {
+        assert_eq!(with_tenant_ids(&[], &[]), ["acme", "globex", "initech"]);
+    }
+
+    #[test]
+    fn include_and_exclude_tenant_ids() {
+        assert_eq!(with_tenant_ids(&["acme", "initech", "unknown"], &[]), ["acme", "initech"]);
+        assert_eq!(with_tenant_ids(&[], &["globex"]), ["acme", "initech"]);
+    }
+
+    #[test]
+    fn exclusion_wins_over_inclusion() {
+        assert_eq!(with_tenant_ids(&["acme", "globex"], &["globex"]), ["acme"]);
+        assert!(with_tenant_ids(&["globex"], &["globex"]).is_empty());
+    }
+
+    #[test]
+    fn scalar_columns_keep_their_json_types() {
+        use scylla::value::CqlTimestamp;
+        let ts = TimestampFormat::default();
+        assert_eq!(cql_to_json(&CqlValue::Boolean(true), ts), serde_json::json!(true));
+        assert_eq!(cql_to_json(&CqlValue::Int(-7), ts), serde_json::json!(-7));
+        assert_eq!(cql_to_json(&CqlValue::BigInt(i64::MAX), ts), serde_json::json!(i64::MAX));
+        assert_eq!(
+            cql_to_json(&CqlValue::Timestamp(CqlTimestamp(0)), ts),
+            serde_json::json!("1970-01-01T00:00:00.000Z")
+        );
+    }
+
+    // Selected via `extraColumns`; a null column is emitted as null, not left out
+    #[test]
+    fn extra_columns_render_typed_values() {
+        let tenant = TenantRow {
+            extra: vec![
+                ("enabled".to_string(), Some(CqlValue::Boolean(false))),
+                ("replicas".to_string(), Some(CqlValue::Int(3))),
+                ("quota_bytes".to_string(), Some(CqlValue::BigInt(10_737_418_240))),
+                ("created_at".to_string(), None)
+            ],
+            ..row("acme", &[])
+        };
+        let entry = &render_tenants(vec![tenant], &RequestOptions::default(), &RenderSettings::default())[0];
+        assert_eq!(entry["enabled"], serde_json::json!(false));
+        assert_eq!(entry["replicas"], serde_json::json!(3));
+        assert_eq!(entry["quota_bytes"], serde_json::json!(10_737_418_240_i64));
+        assert_eq!(entry["created_at"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn tenant_error_mode_defaults_to_fail() {
+        assert_eq!(RequestOptions::default().tenant_error_mode, TenantErrorMode::Fail);
+    }
+
+    #[test]
+    fn skip_drops_only_the_failed_tenant() {
+        let (entries, skipped) = render_with_panic(TenantErrorMode::Skip);
+        assert_eq!(tenant_ids(&entries), ["acme", "initech"]);
+        assert_eq!(skipped, 1);
+        assert!(render_errors(&entries).is_empty());
+    }
+
+    #[test]
+    fn fail_leaves_a_marker_for_the_failed_tenant() {
+        let (entries, skipped) = render_with_panic(TenantErrorMode::Fail);
+        assert_eq!(tenant_ids(&entries), ["acme", "globex", "initech"]);
+        assert_eq!(skipped, 0);
+        assert_eq!(render_errors(&entries), [("globex", "panicked: injected for globex")]);
+        // The other tenants are complete entries, not markers
+        assert_eq!(entries[0]["namespace"], "tn-acme");
+        assert_eq!(entries[2]["namespace"], "tn-initech");
+    }
+
+    #[test]
+    fn isolate_passes_results_through() {
+        assert_eq!(isolate(|| Some(1)), Ok(Some(1)));
+        assert_eq!(isolate(|| None::<u8>), Ok(None));
+        assert_eq!(isolate(|| -> Option<()> { panic!("boom") }), Err("panicked: boom".to_string()));
+    }
+
+    // The column wins when non-empty; null and empty fall back to NAMESPACE_TEMPLATE
+    #[test]
+    fn namespace_template_fills_null_and_empty_columns() {
+        let render = |namespace: Option<&str>, template: Option<&str>| {
+            let tenant = TenantRow { namespace: namespace.map(str::to_string), ..row("acme", &[]) };
+            let settings = RenderSettings { namespace_template: template.map(str::to_string), ..Default::default() };
+            render_tenants(vec![tenant], &RequestOptions::default(), &settings)[0]["namespace"].clone()
+        };
+        let template = Some("tenant-{tenantId}");
+        assert_eq!(render(Some("payments"), template), "payments");
+        assert_eq!(render(None, template), "tenant-acme");
+        assert_eq!(render(Some(""), template), "tenant-acme");
+        assert_eq!(render(None, None), "");
+        assert_eq!(render(Some(""), None), "");
+    }
+
+    // Present maps render either way; null ones are omitted unless `emitEmptyMaps`
+    #[test]
+    fn emit_empty_maps_for_null_columns() {
+        let render = |labels: Option<HashMap<String, String>>, params: Option<HashMap<String, String>>, emit| {
+            let tenant = TenantRow { labels, params, ..row("acme", &[]) };
+            let opts = RequestOptions { emit_empty_maps: emit, ..Default::default() };
+            let entry = render_tenants(vec![tenant], &opts, &RenderSettings::default()).remove(0);
+            (entry.get("labels").cloned(), entry.get("params").cloned())
+        };
+        let present = || Some(labels(&[("team", "a")]));
+        for emit in [false, true] {
+            assert_eq!(
+                render(present(), present(), emit),
+                (Some(serde_json::json!({"team": "a"})), Some(serde_json::json!({"team": "a"}))),
+                "emitEmptyMaps={emit}"
+            );
+        }
+        assert_eq!(render(None, None, false), (None, None));
+        assert_eq!(render(None, None, true), (Some(serde_json::json!({})), Some(serde_json::json!({}))));
+        assert_eq!(render(present(), None, true), (Some(serde_json::json!({"team": "a"})), Some(serde_json::json!({}))));
+    }
+
+    // Promoted columns keep their CQL types next to the expanded params, win on a shared
+    // key by default, and a null one is omitted rather than shadowing the params value
+    #[test]
+    fn promoted_columns_mix_with_the_params_map() {
+        let tenant = TenantRow {
+            params: Some(labels(&[("replicas", "1"), ("ingressHost", "acme.example.com"), ("tier", "bronze")])),
+            promoted: vec![
+                ("replicas".to_string(), Some(CqlValue::Int(3))),
+                ("tier".to_string(), None),
+                ("canary".to_string(), Some(CqlValue::Boolean(true)))
+            ],
+            ..row("acme", &[])
+        };
+        let entry = &render_tenants(vec![tenant], &RequestOptions::default(), &RenderSettings::default())[0];
+        assert_eq!(entry["replicas"], serde_json::json!(3));
+        assert_eq!(entry["canary"], serde_json::json!(true));
+        assert_eq!(entry["ingressHost"], "acme.example.com");
+        assert_eq!(entry["tier"], "bronze");
+        // The params object itself is untouched
+        assert_eq!(entry["params"]["replicas"], "1");
+    }
+
+    #[test]
+    fn transform_operations() {
+        use serde_json::json;
+        let apply = |t: Transform, v: Option<serde_json::Value>| t.apply(v);
+        assert_eq!(apply(Transform::Lower, Some(json!("Acme-EU"))), Some(json!("acme-eu")));
+        assert_eq!(apply(Transform::Upper, Some(json!("acme-eu"))), Some(json!("ACME-EU")));
+        assert_eq!(apply(Transform::Trim, Some(json!("  acme \n"))), Some(json!("acme")));
+        let default = || Transform::Default("shared".to_string());
+        assert_eq!(apply(default(), None), Some(json!("shared")));
+        assert_eq!(apply(default(), Some(json!(null))), Some(json!("shared")));
+        assert_eq!(apply(default(), Some(json!(""))), Some(json!("shared")));
+        assert_eq!(apply(default(), Some(json!("payments"))), Some(json!("payments")));
+        // Non-strings pass through, and case operations don't invent a missing key
+        assert_eq!(apply(Transform::Upper, Some(json!(3))), Some(json!(3)));
+        assert_eq!(apply(Transform::Trim, Some(json!({"a": " b "}))), Some(json!({"a": " b "})));
+        assert_eq!(apply(Transform::Lower, None), None);
+    }
+
+    // Chains run in order on the extracted output, so `default:` can fill a missing key
+    #[test]
+    fn transform_chains_apply_after_extraction() {
+        let opts = RequestOptions {
+            transforms: vec![
+                ("namespace".to_string(), vec![Transform::Trim, Transform::Upper]),
+                ("tier".to_string(), vec![Transform::Default("Bronze".to_string()), Transform::Lower])
+            ],
+            ..Default::default()
+        };
+        let tenant = TenantRow { namespace: Some(" tn-acme ".to_string()), ..row("acme", &[]) };
+        let entry = &render_tenants(vec![tenant], &opts, &RenderSettings::default())[0];
+        assert_eq!(entry["namespace"], "TN-ACME");
+        assert_eq!(entry["tier"], "bronze");
+    }
+
+    // `cluster` is also a fixed key and `tier` also a DEFAULT_PARAMS key, so each has three
+    // sources; the configured order decides between promoted and params, and both beat the rest
+    #[test]
+    fn three_way_collisions_follow_the_precedence() {
+        let render = |precedence: Vec<ParamSource>| {
+            let tenant = TenantRow {
+                params: Some(labels(&[("cluster", "from-params"), ("tier", "silver")])),
+                promoted: vec![
+                    ("cluster".to_string(), Some(CqlValue::Text("from-promoted".to_string()))),
+                    ("tier".to_string(), Some(CqlValue::Text("gold".to_string())))
+                ],
+                ..row("acme", &[])
+            };
+            let settings = RenderSettings {
+                param_precedence: precedence,
+                default_params: serde_json::json!({"tier": "bronze", "owner": "platform"}).as_object().unwrap().clone(),
+                ..Default::default()
+            };
+            let entry = render_tenants(vec![tenant], &RequestOptions::default(), &settings).remove(0);
+            (entry["cluster"].clone(), entry["tier"].clone(), entry["owner"].clone())
+        };
+        assert_eq!(render(DEFAULT_PARAM_PRECEDENCE.to_vec()), ("from-promoted".into(), "gold".into(), "platform".into()));
+        assert_eq!(
+            render(vec![ParamSource::Params, ParamSource::Promoted]),
+            ("from-params".into(), "silver".into(), "platform".into())
+        );
+    }
+
+    #[test]
+    fn camel_case_edge_cases() {
+        for (key, camel) in [
+            ("ingress_host", "ingressHost"),
+            ("_internal_key", "_internalKey"),
+            ("__a_b", "__aB"),
+            ("_", "_"),
+            ("replica_2_count", "replica2Count"),
+            ("v2_api", "v2Api"),
+            ("a__b", "aB"),
+            ("trailing_", "trailing"),
+            ("ingressHost", "ingressHost"),
+            ("", "")
+        ] {
+            assert_eq!(to_camel_case(key), camel, "{key:?}");
+        }
+    }
+
+    #[test]
+    fn snake_case_edge_cases() {
+        for (key, snake) in [
+            ("ingressHost", "ingress_host"),
+            ("repoURLPath", "repo_url_path"),
+            ("HTTPServer", "http_server"),
+            ("replica2Count", "replica2_count"),
+            ("_privateKey", "_private_key"),
+            ("v2", "v2"),
+            ("ingress_host", "ingress_host")
+        ] {
+            assert_eq!(to_snake_case(key), snake, "{key:?}");
+        }
+    }
+
+    // Only label and param keys change; a collision keeps the first key in sorted order
+    #[test]
+    fn key_case_applies_to_maps_only() {
+        let tenant = TenantRow {
+            params: Some(labels(&[("ingress_host", "a.example.com"), ("ingressHost", "b.example.com")])),
+            ..row("acme", &[("cost_center", "42")])
+        };
+        let opts = RequestOptions { key_case: KeyCase::Camel, ..Default::default() };
+        let entry = &render_tenants(vec![tenant], &opts, &RenderSettings::default())[0];
+        assert_eq!(entry["labels"], serde_json::json!({"costCenter": "42"}));
+        assert_eq!(entry["params"], serde_json::json!({"ingressHost": "b.example.com"}));
+        assert_eq!(entry["ingressHost"], "b.example.com");
+        assert_eq!(entry["tenantId"], "acme");
+        assert!(entry.contains_key("repoURL"));
+    }
+
+    // With DEST_NAMESPACE_COLUMN the key is always emitted; null and empty fall back to `namespace`
+    #[test]
+    fn dest_namespace_present_and_absent() {
+        let render = |dest: Option<&str>, emit| {
+            let tenant = TenantRow { dest_namespace: dest.map(str::to_string), ..row("acme", &[]) };
+            let settings = RenderSettings { emit_dest_namespace: emit, ..Default::default() };
+            let entry = render_tenants(vec![tenant], &RequestOptions::default(), &settings).remove(0);
+            assert_eq!(entry["namespace"], "tn-acme");
+            entry.get("destNamespace").cloned()
+        };
+        assert_eq!(render(Some("acme-prod"), true), Some("acme-prod".into()));
+        assert_eq!(render(None, true), Some("tn-acme".into()));
+        assert_eq!(render(Some(""), true), Some("tn-acme".into()));
+        assert_eq!(render(Some("acme-prod"), false), None);
+    }
+
+    // The fallback is the resolved namespace, so NAMESPACE_TEMPLATE reaches it too
+    #[test]
+    fn dest_namespace_falls_back_to_the_templated_namespace() {
+        let tenant = TenantRow { namespace: None, ..row("acme", &[]) };
+        let settings = RenderSettings {
+            emit_dest_namespace: true,
+            namespace_template: Some("tenant-{tenantId}".to_string()),
+            ..Default::default()
+        };
+        let entry = &render_tenants(vec![tenant], &RequestOptions::default(), &settings)[0];
+        assert_eq!(entry["destNamespace"], "tenant-acme");
+    }
+
+    #[test]
+    fn repo_url_normalization_variants() {
+        for url in [
+            "https://github.com/yourorg/tenants",
+            "https://github.com/yourorg/tenants/",
+            "https://github.com/yourorg/tenants.git",
+            "https://github.com/yourorg/tenants.git/",
+            " https://github.com/yourorg/tenants.git "
+        ] {
+            assert_eq!(normalize_repo_url(url), "https://github.com/yourorg/tenants", "{url:?}");
+        }
+        assert_eq!(normalize_repo_url("git@github.com:yourorg/tenants.git"), "git@github.com:yourorg/tenants");
+        assert_eq!(normalize_repo_url("ssh://git@github.com/yourorg/tenants.git/"), "ssh://git@github.com/yourorg/tenants");
+        // Only a `.git` suffix is removed
+        assert_eq!(normalize_repo_url("https://github.com/yourorg/tenants.github"), "https://github.com/yourorg/tenants.github");
+    }
+
+    // Off by default; only `repoURL` changes, `path` is left alone
+    #[test]
+    fn normalize_repo_url_setting() {
+        let render = |normalize| {
+            let tenant = TenantRow { repo_path: "tenants/acme/".to_string(), ..row("acme", &[]) };
+            let settings = RenderSettings { normalize_repo_url: normalize, ..Default::default() };
+            let entry = render_tenants(vec![tenant], &RequestOptions::default(), &settings).remove(0);
+            assert_eq!(entry["path"], "tenants/acme/");
+            entry["repoURL"].clone()
+        };
+        assert_eq!(render(false), "https://github.com/yourorg/tenants.git");
+        assert_eq!(render(true), "https://github.com/yourorg/tenants");
+    }
+
+    // Anything the tenant sets wins: fixed keys, expanded params, promoted and extra columns
+    #[test]
+    fn default_params_never_override_tenant_values() {
+        let settings = RenderSettings {
+            default_params: serde_json::json!({
+                "namespace": "platform",
+                "ingressHost": "default.example.com",
+                "replicas": 1,
+                "monitoring": true,
+                "tier": "bronze"
+            })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ..Default::default()
+        };
+        let tenant = TenantRow {
+            params: Some(labels(&[("ingressHost", "acme.example.com")])),
+            promoted: vec![("replicas".to_string(), Some(CqlValue::Int(3))), ("tier".to_string(), None)],
+            ..row("acme", &[])
+        };
+        let entry = &render_tenants(vec![tenant], &RequestOptions::default(), &settings)[0];
+        assert_eq!(entry["namespace"], "tn-acme");
+        assert_eq!(entry["ingressHost"], "acme.example.com");
+        assert_eq!(entry["replicas"], serde_json::json!(3));
+        // Unset by the tenant, or null in a promoted column: the default keeps its JSON type
+        assert_eq!(entry["monitoring"], serde_json::json!(true));
+        assert_eq!(entry["tier"], "bronze");
+    }
+
+    // The expanded top-level keys are emitted either way; only the nested object is optional
+    #[test]
+    fn include_params_object_settings() {
+        let render = |omit, params: Option<HashMap<String, String>>| {
+            let tenant = TenantRow { params, ..row("acme", &[]) };
+            let opts = RequestOptions { omit_params_object: omit, emit_empty_maps: true, ..Default::default() };
+            render_tenants(vec![tenant], &opts, &RenderSettings::default()).remove(0)
+        };
+        let params = || Some(labels(&[("ingressHost", "acme.example.com")]));
+
+        let with_object = render(false, params());
+        assert_eq!(with_object["params"], serde_json::json!({"ingressHost": "acme.example.com"}));
+        assert_eq!(with_object["ingressHost"], "acme.example.com");
+
+        let without = render(true, params());
+        assert!(!without.contains_key("params"));
+        assert_eq!(without["ingressHost"], "acme.example.com");
+        // Nor does `emitEmptyMaps` bring an empty one back
+        assert!(!render(true, None).contains_key("params"));
+        assert_eq!(render(false, None)["params"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn template_delimiters_are_escaped_for_go_templates() {
+        let escaped = |s: &str| {
+            let mut value = serde_json::Value::from(s);
+            escape_template_chars(&mut value);
+            value
+        };
+        assert_eq!(escaped("a{{b"), r#"a{{"{{"}}b"#);
+        assert_eq!(escaped("{{ .name }}"), r#"{{"{{"}} .name {{"}}"}}"#);
+        assert_eq!(escaped("{{{"), r#"{{"{{"}}{"#);
+        assert_eq!(escaped("}}}"), r#"{{"}}"}}}"#);
+        assert_eq!(escaped("{ single } braces"), "{ single } braces");
+        assert!(has_template_chars(&serde_json::json!({"a": ["x", "y}}"]})));
+        assert!(!has_template_chars(&serde_json::json!({"a": ["{x}", 3]})));
+    }
+
+    // acme carries `{{` in a label, globex in a param value; initech is clean
+    #[test]
+    fn template_chars_modes() {
+        let rows = || {
+            vec![
+                row("acme", &[("owner", "{{ .team }}")]),
+                TenantRow { params: Some(labels(&[("host", "globex}}.example.com")])), ..row("globex", &[]) },
+                row("initech", &[("owner", "ops")])
+            ]
+        };
+        let render = |mode| {
+            let opts = RequestOptions { template_chars: mode, ..Default::default() };
+            render_tenants(rows(), &opts, &RenderSettings::default())
+        };
+
+        let kept = render(TemplateCharsMode::Keep);
+        assert_eq!(kept[0]["labels"]["owner"], "{{ .team }}");
+        assert_eq!(kept[1]["host"], "globex}}.example.com");
+
+        let escaped = render(TemplateCharsMode::Escape);
+        assert_eq!(tenant_ids(&escaped), ["acme", "globex", "initech"]);
+        assert_eq!(escaped[0]["labels"]["owner"], r#"{{"{{"}} .team {{"}}"}}"#);
+        assert_eq!(escaped[1]["host"], r#"globex{{"}}"}}.example.com"#);
+        assert_eq!(escaped[1]["params"]["host"], r#"globex{{"}}"}}.example.com"#);
+        assert_eq!(escaped[2]["labels"]["owner"], "ops");
+
+        assert_eq!(tenant_ids(&render(TemplateCharsMode::Reject)), ["initech"]);
+    }
+
+    #[test]
+    fn trim_strings_reports_changes() {
+        let mut padded = serde_json::json!({"a": [" x", "y"], "b": {"c": "z\t"}, "d": 3});
+        assert!(trim_strings(&mut padded));
+        assert_eq!(padded, serde_json::json!({"a": ["x", "y"], "b": {"c": "z"}, "d": 3}));
+        assert!(!trim_strings(&mut padded));
+        assert!(!trim_strings(&mut serde_json::json!("")));
+    }
+
+    // Fields, label and param values are trimmed, keys are not; a blank value is then empty
+    // for `default:`
+    #[test]
+    fn trim_strings_with_padded_inputs() {
+        let rows = || {
+            vec![TenantRow {
+                namespace: Some("tn-acme\t".to_string()),
+                repo_path: " tenants/acme \n".to_string(),
+                params: Some(labels(&[(" host ", " acme.example.com"), ("tier", "   ")])),
+                ..row("acme", &[("region", " ca-central-1 ")])
+            }]
+        };
+        let render = |trim| {
+            let opts = RequestOptions {
+                trim_strings: trim,
+                transforms: vec![("tier".to_string(), vec![Transform::Default("bronze".to_string())])],
+                ..Default::default()
+            };
+            render_tenants(rows(), &opts, &RenderSettings::default()).remove(0)
+        };
+
+        let trimmed = render(true);
+        assert_eq!(trimmed["namespace"], "tn-acme");
+        assert_eq!(trimmed["path"], "tenants/acme");
+        assert_eq!(trimmed["labels"], serde_json::json!({"region": "ca-central-1"}));
+        assert_eq!(trimmed[" host "], "acme.example.com");
+        assert_eq!(trimmed["params"][" host "], "acme.example.com");
+        assert_eq!(trimmed["tier"], "bronze");
+
+        let untouched = render(false);
+        assert_eq!(untouched["path"], " tenants/acme \n");
+        assert_eq!(untouched["labels"]["region"], " ca-central-1 ");
+        assert_eq!(untouched["tier"], "   ");
+    }
+
+    fn is_rfc1123_label(name: &str) -> bool {
+        name.len() <= APP_NAME_MAX_LEN &&
+            !name.starts_with('-') &&
+            !name.ends_with('-') &&
+            name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    }
+
+    #[test]
+    fn app_names_are_sanitized() {
+        assert_eq!(app_name("acme", "in-cluster"), ("acme-in-cluster".to_string(), false));
+        assert_eq!(app_name("Acme_Corp", "prod.EU"), ("acme-corp-prod-eu".to_string(), false));
+        assert_eq!(app_name("--acme..", "__prod__"), ("acme-prod".to_string(), false));
+        assert_eq!(app_name("zürich", "prod"), ("z-rich-prod".to_string(), false));
+        // Nothing usable left: the hash alone
+        let (name, hashed) = app_name("__", "??");
+        assert!(hashed);
+        assert_eq!(name.len(), APP_NAME_HASH_LEN);
+        assert!(is_rfc1123_label(&name), "{name}");
+    }
+
+    #[test]
+    fn long_app_names_are_truncated_with_a_hash() {
+        let long = "tenant-".repeat(10);
+        let (name, hashed) = app_name(&long, "in-cluster");
+        assert!(hashed);
+        assert!(name.len() <= APP_NAME_MAX_LEN, "{name}");
+        assert!(is_rfc1123_label(&name), "{name}");
+        assert!(name.starts_with("tenant-tenant-"), "{name}");
+        // Stable across calls, and different for inputs with the same truncated prefix
+        assert_eq!(app_name(&long, "in-cluster").0, name);
+        assert_ne!(app_name(&long, "staging").0, name);
+        assert_ne!(app_name(&format!("{long}x"), "in-cluster").0, name);
+        // Exactly 63 characters still fits unchanged
+        let fits = "a".repeat(APP_NAME_MAX_LEN - "-c".len());
+        assert_eq!(app_name(&fits, "c"), (format!("{fits}-c"), false));
+        assert!(app_name(&format!("{fits}a"), "c").1);
+    }
+
+    // Params merge like labels; equal values are no collision, and the rest of the row stays
+    // the earliest table's
+    #[test]
+    fn merge_strategies_combine_params_across_three_tables() {
+        let table = |source: &str, params: Option<&[(&str, &str)]>| {
+            vec![
+                TenantRow {
+                    namespace: Some(format!("tn-{source}")),
+                    params: params.map(labels),
+                    source: source.to_string(),
+                    ..row("acme", &[])
+                },
+                TenantRow { source: source.to_string(), ..row(&format!("only-{source}"), &[]) }
+            ]
+        };
+        let tables = || {
+            vec![
+                table("t1", Some(&[("replicas", "1"), ("tier", "gold")])),
+                table("t2", None),
+                table("t3", Some(&[("replicas", "3"), ("tier", "gold"), ("host", "acme.example.com")]))
+            ]
+        };
+        let merged = |strategy| union_tables(tables(), strategy);
+
+        let last = merged(MergeStrategy::Last).unwrap();
+        let ids: Vec<&str> = last.iter().map(|r| r.tenant_id.as_str()).collect();
+        assert_eq!(ids, ["acme", "only-t1", "only-t2", "only-t3"]);
+        let params = |replicas| Some(labels(&[("replicas", replicas), ("tier", "gold"), ("host", "acme.example.com")]));
+        assert_eq!(last[0].params, params("3"));
+        assert_eq!(last[0].namespace.as_deref(), Some("tn-t1"));
+        assert_eq!(last[0].source, "t1");
+
+        let first = merged(MergeStrategy::First).unwrap();
+        assert_eq!(first[0].params, params("1"));
+        let shadowed = merged(MergeStrategy::Shadow).unwrap();
+        assert_eq!(shadowed[0].params, Some(labels(&[("replicas", "1"), ("tier", "gold")])));
+
+        let error = merged(MergeStrategy::Error).unwrap_err();
+        assert_eq!(error, "tenant acme: params key replicas differs between t1 and t3 (MERGE_STRATEGY=error)");
+    }
+
+    // One region per plugin: every entry carries it, from every configured table
+    #[test]
+    fn include_region_tags_every_entry() {
+        let settings = RenderSettings { multi_table: true, region: "ca-central-1".to_string(), ..Default::default() };
+        let rows = || vec![row("acme", &[]), TenantRow { source: "tenant_configs_legacy".to_string(), ..row("globex", &[]) }];
+        let render = |include_region| {
+            let opts = RequestOptions { include_region, ..Default::default() };
+            render_tenants(rows(), &opts, &settings)
+        };
+        let tagged = render(true);
+        assert!(tagged.iter().all(|e| e["_region"] == "ca-central-1"), "{tagged:?}");
+        assert_eq!(tagged[1]["_source"], "tenant_configs_legacy");
+        assert!(render(false).iter().all(|e| !e.contains_key("_region")));
+    }
+
+    // Promoted and extra timestamp columns in every format; a null one is omitted when
+    // promoted and null when selected via `extraColumns`, whatever the format
+    #[test]
+    fn timestamp_columns_in_each_format_and_null() {
+        use scylla::value::CqlTimestamp;
+        // 1969-12-31T23:59:59.500Z: before the epoch, still UTC
+        let tenant = || TenantRow {
+            promoted: vec![
+                ("created_at".to_string(), Some(CqlValue::Timestamp(CqlTimestamp(-500)))),
+                ("deleted_at".to_string(), None)
+            ],
+            extra: vec![("rotated_at".to_string(), None)],
+            ..row("acme", &[])
+        };
+        for (format, created_at) in [
+            (TimestampFormat::Rfc3339, serde_json::json!("1969-12-31T23:59:59.500Z")),
+            (TimestampFormat::EpochMillis, serde_json::json!(-500)),
+            (TimestampFormat::Date, serde_json::json!("1969-12-31"))
+        ] {
+            let opts = RequestOptions { timestamp_format: format, ..Default::default() };
+            let entry = render_tenants(vec![tenant()], &opts, &RenderSettings::default()).remove(0);
+            assert_eq!(entry["created_at"], created_at, "{format:?}");
+            assert!(!entry.contains_key("deleted_at"), "{format:?}");
+            assert_eq!(entry["rotated_at"], serde_json::Value::Null, "{format:?}");
+        }
+    }
+
+    // Both keys are omitted for null columns, never emitted as null or empty
+    #[test]
+    fn sync_wave_and_annotations_present_and_absent() {
+        let present = TenantRow {
+            sync_wave: Some(-1),
+            annotations: Some(labels(&[("notifications.argoproj.io/subscribe", "slack")])),
+            ..row("acme", &[])
+        };
+        let rows = vec![present, row("globex", &[])];
+        let entries = render_tenants(rows, &RequestOptions::default(), &RenderSettings::default());
+        assert_eq!(entries[0]["syncWave"], serde_json::json!(-1));
+        assert_eq!(entries[0]["annotations"], serde_json::json!({"notifications.argoproj.io/subscribe": "slack"}));
+        assert!(!entries[1].contains_key("syncWave"));
+        assert!(!entries[1].contains_key("annotations"));
+    }
+
+    // DEFAULT_PARAMS and varint columns with integers past 2^53 and u64, in the serialized entry
+    #[test]
+    fn big_integers_round_trip_through_the_output() {
+        let defaults = r#"{"accountId": 9007199254740993, "quota": 18446744073709551617}"#;
+        let settings = RenderSettings { default_params: serde_json::from_str(defaults).unwrap(), ..Default::default() };
+        let tenant = TenantRow {
+            extra: vec![(
+                "ledger_id".to_string(),
+                Some(CqlValue::Varint(scylla::value::CqlVarint::from_signed_bytes_be(vec![0x7f; 12])))
+            )],
+            ..row("acme", &[])
+        };
+        let entry = &render_tenants(vec![tenant], &RequestOptions::default(), &settings)[0];
+        let json = serde_json::to_string(entry).unwrap();
+        assert!(json.contains(r#""accountId":9007199254740993"#), "{json}");
+        assert!(json.contains(r#""quota":18446744073709551617"#), "{json}");
+        // 0x7f7f… as a 96-bit two's complement integer
+        assert!(json.contains(r#""ledger_id":39458731918868905389725810559"#), "{json}");
+    }
+}
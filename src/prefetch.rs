@@ -0,0 +1,87 @@
+// Sequential page fetching with optional read-ahead. Each fetch needs the paging state of the
+// one before it, so pages still arrive in order; with a depth the next fetches run on their
+// own task while the caller decodes the current page. Kept free of the driver so the effect
+// can be benchmarked without a database (`cargo bench`).
+
+use std::future::Future;
+use tokio::sync::mpsc;
+
+pub(crate) struct Pages<S, T, E, F> {
+    mode: Mode<S, T, E, F>,
+}
+
+enum Mode<S, T, E, F> {
+    // depth 0: fetch only when asked
+    Inline {
+        state: Option<S>,
+        fetch: F,
+    },
+    Ahead(mpsc::Receiver<Result<T, E>>),
+}
+
+impl<S, T, E, F, Fut> Pages<S, T, E, F>
+    where
+        S: Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+        F: FnMut(S) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(T, Option<S>), E>> + Send + 'static
+{
+    // `fetch` turns a paging state into a page and the state of the next one, `None` when
+    // the scan is over. At most `depth` pages are fetched or in flight ahead of the caller.
+    pub(crate) fn new(depth: usize, start: S, fetch: F) -> Self {
+        if depth == 0 {
+            return Pages { mode: Mode::Inline { state: Some(start), fetch } };
+        }
+        let (tx, rx) = mpsc::channel(depth);
+        tokio::spawn(read_ahead(tx, start, fetch));
+        Pages { mode: Mode::Ahead(rx) }
+    }
+
+    // The next page in order; `None` after the last page or the first error
+    pub(crate) async fn next(&mut self) -> Option<Result<T, E>> {
+        match &mut self.mode {
+            Mode::Inline { state, fetch } => {
+                let current = state.take()?;
+                match fetch(current).await {
+                    Ok((page, next)) => {
+                        *state = next;
+                        Some(Ok(page))
+                    }
+                    Err(e) => Some(Err(e)),
+                }
+            }
+            Mode::Ahead(rx) => rx.recv().await,
+        }
+    }
+}
+
+// A slot is reserved before each fetch, so the channel capacity bounds buffered and in-flight
+// pages together. Dropping `Pages` closes the channel and stops the task at its next reserve.
+async fn read_ahead<S, T, E, F, Fut>(tx: mpsc::Sender<Result<T, E>>, start: S, mut fetch: F)
+    where F: FnMut(S) -> Fut, Fut: Future<Output = Result<(T, Option<S>), E>>
+{
+    let mut state = start;
+    loop {
+        let Ok(slot) = tx.reserve().await else {
+            return;
+        };
+        match fetch(state).await {
+            Ok((page, next)) => {
+                slot.send(Ok(page));
+                match next {
+                    Some(next) => {
+                        state = next;
+                    }
+                    None => {
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                slot.send(Err(e));
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Wake-ups for files mounted from Secrets and ConfigMaps (plugin token, credentials, root
+// CA). Callers compare contents themselves, so a spurious wake-up costs one read.
+
+use notify::{ RecommendedWatcher, RecursiveMode, Watcher };
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{ Instant, Interval, MissedTickBehavior };
+use tracing::{ info, warn };
+
+// Kubernetes swaps a Secret volume as several renames in a row; one reload covers them all
+const EVENT_SETTLE: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReloadStrategy {
+    // Timer only
+    Poll,
+    // File events as they happen, with the timer as a backstop
+    Notify,
+}
+
+pub(crate) struct FileChanges {
+    ticker: Option<Interval>,
+    events: Option<mpsc::Receiver<()>>,
+    // Dropping the watcher stops its events
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl FileChanges {
+    // `period` 0 means no timer. The parent directories are watched rather than the files:
+    // a Secret update repoints the `..data` symlink, which a watch on the file itself, bound
+    // to the old inode, would never see.
+    pub(crate) fn new(what: &str, paths: &[String], strategy: ReloadStrategy, period: Duration) -> Self {
+        let ticker = (!period.is_zero()).then(|| {
+            let mut ticker = tokio::time::interval_at(Instant::now() + period, period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ticker
+        });
+        let (events, watcher) = match strategy {
+            ReloadStrategy::Poll => (None, None),
+            ReloadStrategy::Notify =>
+                match watch_dirs(paths) {
+                    Ok((rx, watcher)) => {
+                        info!("{}: watching for file events", what);
+                        (Some(rx), Some(watcher))
+                    }
+                    Err(e) => {
+                        warn!("{}: file events unavailable ({}), polling every {:?}", what, e, period);
+                        (None, None)
+                    }
+                }
+        };
+        FileChanges { ticker, events, _watcher: watcher }
+    }
+
+    // Resolves at the next file event or tick and says which; never resolves with neither
+    pub(crate) async fn next(&mut self) -> &'static str {
+        let FileChanges { ticker, events, .. } = self;
+        let tick = async {
+            match ticker {
+                Some(ticker) => {
+                    ticker.tick().await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let event = async {
+            let Some(rx) = events else {
+                return std::future::pending().await;
+            };
+            if rx.recv().await.is_none() {
+                return std::future::pending().await;
+            }
+            tokio::time::sleep(EVENT_SETTLE).await;
+            while rx.try_recv().is_ok() {}
+        };
+        tokio::select! {
+            () = tick => "poll",
+            () = event => "file event",
+        }
+    }
+}
+
+fn watch_dirs(paths: &[String]) -> notify::Result<(mpsc::Receiver<()>, RecommendedWatcher)> {
+    let (tx, rx) = mpsc::channel(1);
+    // A full channel already holds a pending wake-up
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok() {
+            let _ = tx.try_send(());
+        }
+    })?;
+    for path in paths {
+        let dir = Path::new(path)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok((rx, watcher))
+}
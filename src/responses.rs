@@ -0,0 +1,183 @@
+// Computed getparams responses, keyed like saved responses by the sorted request parameters,
+// so an ApplicationSet polling with the same generator config skips the scan and the
+// filter/sort pipeline until the entry expires. Bounded by entry count and by serialized
+// size; the least recently used entry goes first, as in `StatementCache`.
+
+use crate::PluginResponse;
+use std::collections::{ HashMap, VecDeque };
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+
+pub(crate) struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<String, Cached>,
+    // Least recently used first
+    order: VecDeque<String>,
+    bytes: usize,
+}
+
+struct Cached {
+    response: PluginResponse,
+    rows_returned: u64,
+    stored: Instant,
+    bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Usage {
+    pub(crate) entries: usize,
+    pub(crate) bytes: usize,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(ttl: Duration, max_entries: usize, max_bytes: usize) -> Self {
+        ResponseCache { ttl, max_entries, max_bytes, entries: Mutex::new(Entries::default()) }
+    }
+
+    // The response and its returned row count; an expired entry is dropped on the way
+    pub(crate) fn get(&self, key: &str) -> Option<(PluginResponse, u64)> {
+        let mut entries = self.entries.lock().unwrap();
+        let fresh = entries.by_key.get(key)?.stored.elapsed() < self.ttl;
+        if !fresh {
+            entries.remove(key);
+            return None;
+        }
+        entries.touch(key);
+        entries.by_key.get(key).map(|c| (c.response.clone(), c.rows_returned))
+    }
+
+    // `bytes` is the serialized size; a response over MAX_BYTES on its own is not kept
+    pub(crate) fn insert(&self, key: String, response: PluginResponse, rows_returned: u64, bytes: usize) {
+        if bytes > self.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&key);
+        entries.bytes += bytes;
+        entries.order.push_back(key.clone());
+        entries.by_key.insert(key, Cached { response, rows_returned, stored: Instant::now(), bytes });
+        while entries.by_key.len() > self.max_entries || entries.bytes > self.max_bytes {
+            let Some(oldest) = entries.order.front().cloned() else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+    }
+
+    pub(crate) fn invalidate(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let dropped = entries.by_key.len();
+        *entries = Entries::default();
+        dropped
+    }
+
+    pub(crate) fn usage(&self) -> Usage {
+        let entries = self.entries.lock().unwrap();
+        Usage { entries: entries.by_key.len(), bytes: entries.bytes }
+    }
+
+    // Held keys, expired or not, least recently used first
+    pub(crate) fn keys(&self) -> Vec<String> {
+        self.entries.lock().unwrap().order.iter().cloned().collect()
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) {
+        if let Some(cached) = self.by_key.remove(key) {
+            self.bytes -= cached.bytes;
+            self.order.retain(|k| k != key);
+        }
+    }
+
+    fn touch(&mut self, key: &str) {
+        let pos = self.order.iter().position(|k| k == key);
+        if let Some(key) = pos.and_then(|pos| self.order.remove(pos)) {
+            self.order.push_back(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Output;
+
+    fn response() -> PluginResponse {
+        PluginResponse { output: Output { parameters: Vec::new(), count: Some(0) }, meta: None, delta: None }
+    }
+
+    fn held(cache: &ResponseCache, keys: &[&'static str]) -> Vec<&'static str> {
+        keys.iter()
+            .copied()
+            .filter(|k| cache.get(k).is_some())
+            .collect()
+    }
+
+    // Two entries or 100 bytes
+    fn cache() -> ResponseCache {
+        ResponseCache::new(Duration::from_secs(60), 2, 100)
+    }
+
+    #[test]
+    fn least_recently_used_entry_goes_first() {
+        let cache = cache();
+        cache.insert("a".to_string(), response(), 0, 10);
+        cache.insert("b".to_string(), response(), 0, 10);
+        cache.get("a");
+        cache.insert("c".to_string(), response(), 0, 10);
+        assert_eq!(held(&cache, &["a", "b", "c"]), ["a", "c"]);
+        assert_eq!(cache.usage(), Usage { entries: 2, bytes: 20 });
+        assert_eq!(cache.keys(), ["a", "c"]);
+    }
+
+    #[test]
+    fn oversized_response_is_not_kept() {
+        let cache = cache();
+        cache.insert("a".to_string(), response(), 0, 10);
+        cache.insert("big".to_string(), response(), 0, 101);
+        assert_eq!(held(&cache, &["a", "big"]), ["a"]);
+    }
+
+    #[test]
+    fn byte_bound_evicts_until_it_fits() {
+        let cache = cache();
+        cache.insert("a".to_string(), response(), 0, 10);
+        cache.insert("b".to_string(), response(), 0, 95);
+        assert_eq!(held(&cache, &["a", "b"]), ["b"]);
+        assert_eq!(cache.usage(), Usage { entries: 1, bytes: 95 });
+    }
+
+    #[test]
+    fn replaced_key_is_counted_once() {
+        let cache = cache();
+        cache.insert("a".to_string(), response(), 1, 10);
+        cache.insert("a".to_string(), response(), 2, 30);
+        assert_eq!(cache.usage(), Usage { entries: 1, bytes: 30 });
+        assert_eq!(cache.get("a").map(|(_, rows)| rows), Some(2));
+    }
+
+    #[test]
+    fn expired_entry_is_dropped_on_lookup() {
+        let cache = ResponseCache::new(Duration::ZERO, 2, 100);
+        cache.insert("a".to_string(), response(), 0, 10);
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.usage(), Usage { entries: 0, bytes: 0 });
+    }
+
+    #[test]
+    fn invalidate_drops_everything() {
+        let cache = cache();
+        cache.insert("a".to_string(), response(), 0, 10);
+        cache.insert("b".to_string(), response(), 0, 10);
+        assert_eq!(cache.invalidate(), 2);
+        assert_eq!(cache.usage(), Usage { entries: 0, bytes: 0 });
+    }
+}
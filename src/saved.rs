@@ -0,0 +1,145 @@
+// The last complete response per generator parameter set. Kept in memory for maintenance
+// mode; with SNAPSHOT_FILE also persisted, so a restarted plugin can answer while Keyspaces
+// is still unreachable. Cold-start reads stop at the first live response.
+
+use serde::{ Deserialize, Serialize };
+use std::collections::{ BTreeMap, HashMap };
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use tracing::{ debug, info, warn };
+
+// Parameter sets kept, oldest save evicted first; each ApplicationSet generator uses one
+const CAPACITY: usize = 64;
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Snapshot {
+    pub(crate) saved_at: String,
+    pub(crate) parameters: Vec<HashMap<String, serde_json::Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) meta: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    version: u32,
+    // Keyed by the canonical JSON of the request parameters
+    responses: BTreeMap<String, Snapshot>,
+}
+
+impl SnapshotFile {
+    fn empty() -> Self {
+        SnapshotFile { version: FORMAT_VERSION, responses: BTreeMap::new() }
+    }
+}
+
+pub(crate) struct SavedResponses {
+    // SNAPSHOT_FILE; None keeps the responses in memory only
+    path: Option<String>,
+    // Also held while writing, so an older save never renames over a newer one
+    file: Mutex<SnapshotFile>,
+    // Set by the first live response
+    live: AtomicBool,
+}
+
+impl SavedResponses {
+    // A missing, unreadable or foreign file starts empty; the next save replaces it
+    pub(crate) fn load(path: Option<&str>) -> Self {
+        let file = match path.map(|p| (p, std::fs::read(p))) {
+            None => SnapshotFile::empty(),
+            Some((path, Ok(raw))) =>
+                match serde_json::from_slice::<SnapshotFile>(&raw) {
+                    Ok(file) if file.version == FORMAT_VERSION => {
+                        info!("snapshot file {}: {} saved responses", path, file.responses.len());
+                        file
+                    }
+                    Ok(file) => {
+                        warn!("snapshot file {}: unsupported version {}, starting empty", path, file.version);
+                        SnapshotFile::empty()
+                    }
+                    Err(e) => {
+                        warn!("snapshot file {}: unreadable ({}), starting empty", path, e);
+                        SnapshotFile::empty()
+                    }
+                }
+            Some((path, Err(e))) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!("snapshot file {}: not found, starting empty", path);
+                SnapshotFile::empty()
+            }
+            Some((path, Err(e))) => {
+                warn!("snapshot file {}: {}, starting empty", path, e);
+                SnapshotFile::empty()
+            }
+        };
+        SavedResponses {
+            path: path.map(str::to_string),
+            file: Mutex::new(file),
+            live: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Snapshot> {
+        self.file.lock().unwrap().responses.get(key).cloned()
+    }
+
+    // Only from SNAPSHOT_FILE, and only until fresh data is available
+    pub(crate) fn cold_start(&self, key: &str) -> Option<Snapshot> {
+        if self.path.is_none() || self.live.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.get(key)
+    }
+
+    // Whether `cold_start` can still answer anything, for `/readyz`
+    pub(crate) fn cold_start_serving(&self) -> bool {
+        self.path.is_some() && !self.live.load(Ordering::Relaxed) && !self.is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.file.lock().unwrap().responses.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn mark_live(&self) {
+        let first = !self.live.swap(true, Ordering::Relaxed);
+        if let Some(path) = self.path.as_ref().filter(|_| first) {
+            info!("live response served; {} is no longer read", path);
+        }
+    }
+
+    // Blocking: call from `spawn_blocking`. Unchanged entries skip the write.
+    pub(crate) fn save(&self, key: String, snapshot: Snapshot) {
+        let mut file = self.file.lock().unwrap();
+        if file.responses.get(&key).is_some_and(|old| old.parameters == snapshot.parameters) {
+            return;
+        }
+        file.responses.insert(key, snapshot);
+        while file.responses.len() > CAPACITY {
+            let oldest = file.responses
+                .iter()
+                .min_by(|a, b| a.1.saved_at.cmp(&b.1.saved_at))
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                file.responses.remove(&oldest);
+            }
+        }
+        let Some(path) = &self.path else {
+            return;
+        };
+        // Through a temporary file, so a crash mid-write leaves the previous snapshot
+        let tmp = format!("{path}.tmp");
+        let result = serde_json
+            ::to_vec(&*file)
+            .map_err(std::io::Error::other)
+            .and_then(|body| std::fs::write(&tmp, body))
+            .and_then(|()| std::fs::rename(&tmp, path));
+        match result {
+            Ok(()) => debug!("snapshot file {}: saved, {} responses", path, file.responses.len()),
+            Err(e) => warn!("snapshot file {}: save failed: {}", path, e),
+        }
+    }
+}
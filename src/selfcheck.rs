@@ -0,0 +1,91 @@
+// `--selfcheck` / SELFCHECK=true: runs the request pipeline (parameter parsing, union,
+// filters, rendering, shaping, serialization) over embedded rows and compares the result with
+// an embedded golden. No session, token or config is needed, so it works in CI and in the
+// image itself. Never reachable from the serving path. Everything else is covered by the unit
+// tests (`cargo test`).
+
+use crate::pipeline::{ MergeStrategy, RenderSettings, TenantRow, render_tenants, shape_output, union_tables };
+use crate::{ PluginInput, PluginResponse, Output, RequestOptions };
+use scylla::value::CqlValue;
+use std::collections::HashMap;
+
+const REQUEST: &str = include_str!("selfcheck/request.json");
+const GOLDEN: &str = include_str!("selfcheck/golden.json");
+
+pub(crate) fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let actual = render()?;
+    let expected: serde_json::Value = serde_json::from_str(GOLDEN)?;
+    if actual != expected {
+        eprintln!("selfcheck: output differs from the golden");
+        eprintln!("expected: {}", serde_json::to_string_pretty(&expected)?);
+        eprintln!("actual:   {}", serde_json::to_string_pretty(&actual)?);
+        return Err("selfcheck failed".into());
+    }
+    println!("selfcheck: ok");
+    Ok(())
+}
+
+fn render() -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let input: PluginInput = serde_json::from_str(REQUEST)?;
+    let opts = RequestOptions::from_parameters(&input.input.parameters).map_err(|e|
+        format!("selfcheck request rejected: {e}")
+    )?;
+    let settings = RenderSettings {
+        multi_table: true,
+        namespace_template: Some("tn-{tenantId}".to_string()),
+        max_labels_per_tenant: 2,
+        max_params_per_tenant: 0,
+        // `kafkaTopic` is set for acme, so its own value must win there
+        default_params: serde_json
+            ::from_str(r#"{"managedBy": "platform", "kafkaTopic": "default.events"}"#)
+            .expect("valid default params"),
+        ..Default::default()
+    };
+
+    let rows = union_tables(
+        vec![
+            vec![
+                row("acme", Some(" TN-Acme "), &[("region", "ca-central-1"), ("tier", "gold")], Some(&[("kafkaTopic", "acme.events")]), "tenant_configs"),
+                row("globex", None, &[("region", "ca-central-1"), ("team", "platform"), ("zone", "a")], None, "tenant_configs"),
+                row("initech", Some("tn-initech"), &[("region", "ca-central-1")], None, "tenant_configs"),
+                row("umbrella", Some("tn-umbrella"), &[("region", "us-east-1")], None, "tenant_configs")
+            ],
+            vec![row("acme", Some("shadowed"), &[("region", "ca-central-1")], None, "tenant_configs_legacy")]
+        ],
+        MergeStrategy::Shadow
+    )?;
+    let out = shape_output(render_tenants(rows, &opts, &settings), opts.output_shape);
+    Ok(serde_json::to_value(PluginResponse { output: Output { parameters: out, count: None }, meta: None, delta: None })?)
+}
+
+fn row(
+    tenant_id: &str,
+    namespace: Option<&str>,
+    labels: &[(&str, &str)],
+    params: Option<&[(&str, &str)]>,
+    source: &str
+) -> TenantRow {
+    let map = |entries: &[(&str, &str)]| -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+    TenantRow {
+        tenant_id: tenant_id.to_string(),
+        namespace: namespace.map(|n| n.to_string()),
+        target_cluster: "in-cluster".to_string(),
+        repo_url: "https://github.com/yourorg/tenants.git".to_string(),
+        repo_path: format!("tenants/{tenant_id}"),
+        labels: Some(map(labels)),
+        params: params.map(map),
+        dest_namespace: None,
+        sync_wave: None,
+        annotations: None,
+        project: None,
+        promoted: vec![("tier".to_string(), None::<CqlValue>)],
+        extra: Vec::new(),
+        write_time: None,
+        source: source.to_string(),
+    }
+}
@@ -0,0 +1,159 @@
+// Where tenant rows come from. Handlers only see `TenantSource`; Keyspaces is the default and
+// TENANT_SOURCE_FILE swaps in a JSON file for local runs and tests without a database.
+
+use crate::pipeline::{ RequestOptions, TenantRow, keeps_enabled_state };
+use crate::health::Health;
+use crate::{
+    AppError,
+    PageSink,
+    SESSION,
+    ScanStats,
+    check_table,
+    decode_json_row,
+    fetch_tenants,
+    health_status,
+    query_session,
+    record_query,
+};
+use futures_util::future::BoxFuture;
+use tracing::info;
+
+pub(crate) trait TenantSource: Send + Sync {
+    // 503 before any response is started, e.g. while Keyspaces is still unreachable
+    fn check_available(&self) -> Result<(), AppError>;
+
+    // Rows matching the scan-level filters (`enabled`, `clusters`); the rest is left to the
+    // pipeline. With a sink, pages are handed over as they are read instead of returned.
+    fn fetch<'a>(
+        &'a self,
+        opts: &'a RequestOptions,
+        stats: &'a mut ScanStats,
+        sink: Option<&'a PageSink>
+    ) -> BoxFuture<'a, Result<Vec<TenantRow>, AppError>>;
+
+    // `/readyz`; the error is the reason shown to the probe
+    fn readiness(&self) -> BoxFuture<'_, Result<(), String>>;
+}
+
+pub(crate) struct KeyspacesSource;
+
+impl TenantSource for KeyspacesSource {
+    // Load shedding: an open breaker or a stale last success answers 503 without a query
+    fn check_available(&self) -> Result<(), AppError> {
+        query_session().ok_or(AppError::Unavailable("session not initialized"))?;
+        match health_status().reason() {
+            Some(reason) => Err(AppError::Unavailable(reason)),
+            None => Ok(()),
+        }
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        opts: &'a RequestOptions,
+        stats: &'a mut ScanStats,
+        sink: Option<&'a PageSink>
+    ) -> BoxFuture<'a, Result<Vec<TenantRow>, AppError>> {
+        Box::pin(async move {
+            self.check_available()?;
+            // Resolved per call: a root CA reload can replace the session
+            let session = query_session().ok_or(AppError::Unavailable("session not initialized"))?;
+            fetch_tenants(&session, opts, stats, sink).await
+        })
+    }
+
+    fn readiness(&self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let session = SESSION.get().ok_or_else(|| "session not initialized".to_string())?;
+            // No probe query while the breaker is open; a stale state is refreshed by it
+            if health_status() == Health::BreakerOpen {
+                return Err(Health::BreakerOpen.reason().unwrap_or_default().to_string());
+            }
+            let result = check_table(&session).await;
+            record_query(result.is_ok());
+            result
+        })
+    }
+}
+
+// A JSON array of rows in the `/api/v1/validate` row format, plus an optional boolean
+// `enabled` (default true). Read once at startup; keys that are not tenant columns are only
+// used when a request names them in `extraColumns`.
+pub(crate) struct FileSource {
+    path: String,
+    rows: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl FileSource {
+    // Every row is decoded once here, so a bad file fails startup instead of each request
+    pub(crate) fn load(path: &str) -> anyhow::Result<Self> {
+        let raw = std::fs::read_to_string(path)?;
+        let rows: Vec<serde_json::Map<String, serde_json::Value>> = serde_json
+            ::from_str(&raw)
+            .map_err(|e| anyhow::anyhow!("invalid TENANT_SOURCE_FILE {path}: {e}"))?;
+        let opts = RequestOptions::default();
+        let mut problems = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            let mut row = row.clone();
+            if row.remove("enabled").is_some_and(|v| !v.is_boolean()) {
+                problems.push(format!("row {i}: enabled: expected a boolean"));
+            }
+            if let Err(errors) = decode_json_row(row, &opts, path.to_string(), true) {
+                problems.push(format!("row {i}: {}", errors.join("; ")));
+            }
+        }
+        if !problems.is_empty() {
+            anyhow::bail!("invalid TENANT_SOURCE_FILE {path}: {}", problems.join(", "));
+        }
+        info!("tenant source: {} rows from {}", rows.len(), path);
+        Ok(FileSource { path: path.to_string(), rows })
+    }
+}
+
+impl TenantSource for FileSource {
+    fn check_available(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        opts: &'a RequestOptions,
+        stats: &'a mut ScanStats,
+        sink: Option<&'a PageSink>
+    ) -> BoxFuture<'a, Result<Vec<TenantRow>, AppError>> {
+        Box::pin(async move {
+            let mut page = Vec::new();
+            for row in &self.rows {
+                let mut row = row.clone();
+                // Missing means enabled; `load` rejected anything but a boolean
+                let enabled = row.remove("enabled").map_or(Some(true), |v| v.as_bool());
+                let row = decode_json_row(row, opts, self.path.clone(), true).map_err(|errors|
+                    AppError::Internal(format!("{}: {}", self.path, errors.join("; ")))
+                )?;
+                // The cluster lookup comes first, as a Keyspaces partition lookup never reads
+                // other clusters
+                if !opts.clusters.is_empty() && !opts.clusters.contains(&row.target_cluster) {
+                    continue;
+                }
+                if !keeps_enabled_state(enabled, &row.target_cluster, opts) {
+                    stats.rows_disabled += 1;
+                    continue;
+                }
+                page.push(row);
+            }
+            stats.pages = 1;
+            stats.rows_scanned = page.len() as u64 + stats.rows_disabled;
+            match sink {
+                Some(sink) => {
+                    // A dropped receiver only means the client went away
+                    let _ = sink.send(Ok(page)).await;
+                    Ok(Vec::new())
+                }
+                None => Ok(page),
+            }
+        })
+    }
+
+    fn readiness(&self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async { Ok(()) })
+    }
+}
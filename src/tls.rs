@@ -0,0 +1,174 @@
+// HTTPS for the plugin endpoint, optionally with client certificates (SERVER_CLIENT_CA).
+// Handshakes run on their own tasks, so a slow or silent client never holds up the accept
+// loop; a failed handshake is dropped there and never reaches the router.
+
+use axum::extract::connect_info::Connected;
+use axum::serve::{ IncomingStream, Listener };
+use rustls::RootCertStore;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{ CertificateDer, PrivateKeyDer };
+use rustls::server::{ ServerConfig, WebPkiClientVerifier };
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{ TcpListener, TcpStream };
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tracing::{ debug, warn };
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Completed handshakes waiting for the router
+const ACCEPT_BACKLOG: usize = 64;
+
+// How a client certificate and the bearer token combine with SERVER_CLIENT_CA
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ClientAuthMode {
+    // A verified certificate is required and the token is still checked
+    #[default]
+    Both,
+    // A certificate is optional; a request over a connection without one needs the token
+    Either,
+    // A verified certificate is required and replaces the token
+    Cert,
+}
+
+impl ClientAuthMode {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "both" => Some(ClientAuthMode::Both),
+            "either" => Some(ClientAuthMode::Either),
+            "cert" => Some(ClientAuthMode::Cert),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            ClientAuthMode::Both => "both",
+            ClientAuthMode::Either => "either",
+            ClientAuthMode::Cert => "cert",
+        }
+    }
+}
+
+pub(crate) struct ServerTls<'a> {
+    pub(crate) cert: &'a str,
+    pub(crate) key: &'a str,
+    pub(crate) client_ca: Option<&'a str>,
+    pub(crate) client_auth_mode: ClientAuthMode,
+}
+
+// Read once at startup; a rotated certificate needs a restart
+pub(crate) fn server_config(tls: &ServerTls<'_>) -> anyhow::Result<ServerConfig> {
+    let provider = CryptoProvider::get_default()
+        .cloned()
+        .unwrap_or_else(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()));
+    let certs: Vec<CertificateDer<'static>> = rustls_pemfile
+        ::certs(&mut BufReader::new(File::open(tls.cert)?))
+        .collect::<Result<_, _>>()?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificate in SERVER_TLS_CERT {}", tls.cert);
+    }
+    let key: PrivateKeyDer<'static> = rustls_pemfile
+        ::private_key(&mut BufReader::new(File::open(tls.key)?))?
+        .ok_or_else(|| anyhow::anyhow!("no private key in SERVER_TLS_KEY {}", tls.key))?;
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+    let builder = match tls.client_ca {
+        None => builder.with_no_client_auth(),
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            let cas: Vec<CertificateDer<'static>> = rustls_pemfile
+                ::certs(&mut BufReader::new(File::open(path)?))
+                .collect::<Result<_, _>>()?;
+            let (added, _) = roots.add_parsable_certificates(cas);
+            if added == 0 {
+                anyhow::bail!("no usable certificate in SERVER_CLIENT_CA {path}");
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match tls.client_auth_mode {
+                ClientAuthMode::Either => verifier.allow_unauthenticated().build()?,
+                ClientAuthMode::Both | ClientAuthMode::Cert => verifier.build()?,
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+    let mut config = builder.with_single_cert(certs, key)?;
+    // The router is served over HTTP/1.1 only
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+// The peer of one TLS connection, as `ConnectInfo`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Peer {
+    // Presented a certificate that SERVER_CLIENT_CA verified
+    pub(crate) client_cert: bool,
+}
+
+pub(crate) struct TlsListener {
+    accepted: mpsc::Receiver<(TlsStream<TcpStream>, Peer)>,
+}
+
+impl TlsListener {
+    pub(crate) fn new(listener: TcpListener, config: ServerConfig) -> Self {
+        let (tx, accepted) = mpsc::channel(ACCEPT_BACKLOG);
+        tokio::spawn(accept_loop(listener, TlsAcceptor::from(Arc::new(config)), tx));
+        TlsListener { accepted }
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    tx: mpsc::Sender<(TlsStream<TcpStream>, Peer)>
+) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // Usually out of file descriptors; give in-flight connections time to close
+                warn!("accept failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let client_cert = stream.get_ref().1.peer_certificates().is_some_and(|c| !c.is_empty());
+                    let _ = tx.send((stream, Peer { client_cert })).await;
+                }
+                // Includes a missing or unverified client certificate
+                Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", addr, e),
+                Err(_) => debug!("TLS handshake with {} timed out", addr),
+            }
+        });
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = Peer;
+
+    // The accept loop only ends if its task panics; then no connection arrives again
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.accepted.recv().await {
+            Some(accepted) => accepted,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(Peer { client_cert: false })
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        *stream.remote_addr()
+    }
+}
@@ -0,0 +1,30 @@
+#!/bin/sh
+# Regenerates the certificates the TLS tests in src/main.rs use. Everything is valid for
+# 100 years and only for localhost; none of it is a secret.
+set -eu
+cd "$(dirname "$0")"
+days=36500
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+ca() {
+    openssl req -x509 -new -nodes -newkey ec -pkeyopt ec_paramgen_curve:P-256 \
+        -keyout "$tmp/$1.key" -out "$1.pem" -days $days -subj "/CN=$2" \
+        -addext basicConstraints=critical,CA:TRUE -addext keyUsage=critical,keyCertSign
+}
+
+leaf() {
+    openssl req -new -nodes -newkey ec -pkeyopt ec_paramgen_curve:P-256 \
+        -keyout "$tmp/$1.key" -out "$tmp/$1.csr" -subj "/CN=$2"
+    printf 'basicConstraints=CA:FALSE\nkeyUsage=critical,digitalSignature\nextendedKeyUsage=%s\n%s' "$4" "$5" > "$tmp/$1.ext"
+    openssl x509 -req -in "$tmp/$1.csr" -CA "$3.pem" -CAkey "$tmp/$3.key" -CAcreateserial \
+        -out "$1.pem" -days $days -extfile "$tmp/$1.ext"
+    openssl pkcs8 -topk8 -nocrypt -in "$tmp/$1.key" -out "$1-key.pem"
+}
+
+ca ca "keyspaces-tenant-gen test CA"
+ca other-ca "unrelated test CA"
+leaf server localhost ca serverAuth "subjectAltName=DNS:localhost"
+leaf client argocd-repo-server ca clientAuth ""
+leaf other-client argocd-repo-server other-ca clientAuth ""
+rm -f ./*.srl other-ca.pem